@@ -1,3 +1,6 @@
+pub mod output;
+#[cfg(feature = "python")]
+mod python;
 pub mod util;
 
 use clap::Parser;
@@ -32,10 +35,16 @@ pub struct Args {
     #[clap(long, default_value_t = 0.3)]
     pub start_c: f64,
 
+    /// Weight of the total variation regularization when optimizing c
+    #[clap(long, default_value_t = util::reconstructor::DEFAULT_LAMBDA)]
+    #[serde(default = "default_lambda")]
+    pub lambda: f64,
+
     /// Deblur only?
     /// If yes, then the system will only deblur the APS images, and NOT generate the intermediate
     /// image frames. This is useful for transcoding to another event representation
     /// (https://github.com/ac-freeman/adder-codec-rs)
+    /// Equivalent to `OutputMode::SharpFrameOnly` in the library API
     #[clap(long, action)]
     pub deblur_only: bool,
 
@@ -48,6 +57,12 @@ pub struct Args {
     #[clap(long, action)]
     pub simulate_packet_latency: bool,
 
+    /// If true, the input file is assumed to start with an APS frame, so the events before the
+    /// first frame aren't skipped over. Only applies to the "file" mode.
+    #[clap(long, action)]
+    #[serde(default)]
+    pub skip_priming: bool,
+
     /// The target maximum latency (in milliseconds) between an APS frame packet being decoded from
     /// the camera, and deblurring it.
     #[clap(short, long, default_value_t = 200.0)]
@@ -85,3 +100,7 @@ pub struct Args {
     #[clap(long, action)]
     pub write_video: bool,
 }
+
+fn default_lambda() -> f64 {
+    util::reconstructor::DEFAULT_LAMBDA
+}
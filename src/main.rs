@@ -1,5 +1,5 @@
 use clap::Parser;
-use davis_edi_rs::util::reconstructor::{show_display, Reconstructor};
+use davis_edi_rs::util::reconstructor::{show_display, OutputMode, ReconstructorBuilder};
 use davis_edi_rs::Args;
 use opencv::core::{Mat, MatTraitConst, CV_8U};
 use opencv::prelude::VideoWriterTrait;
@@ -15,24 +15,29 @@ async fn main() -> Result<(), Box<dyn Error>> {
         args = toml::from_str(&content).unwrap();
     }
 
-    let mut reconstructor = Reconstructor::new(
-        args.base_path,
-        args.events_filename_0,
-        args.events_filename_1,
-        args.mode,
-        args.start_c,
-        args.optimize_c,
-        args.optimize_c_frequency,
-        args.optimize_controller,
-        args.show_display,
-        args.show_blurred_display,
-        args.output_fps,
-        args.deblur_only,
-        args.events_only,
-        args.target_latency,
-        args.simulate_packet_latency,
-    )
-    .await?;
+    let mut reconstructor = ReconstructorBuilder::new()
+        .directory(args.base_path)
+        .aedat_filename_0(args.events_filename_0)
+        .aedat_filename_1(args.events_filename_1)
+        .mode(args.mode)
+        .start_c(args.start_c)
+        .optimize_c(args.optimize_c, args.optimize_c_frequency)
+        .optimize_controller(args.optimize_controller)
+        .show_display(args.show_display)
+        .show_blurred_display(args.show_blurred_display)
+        .output_fps(args.output_fps)
+        .output_mode(match args.deblur_only {
+            true => OutputMode::SharpFrameOnly,
+            false => OutputMode::FullSequence,
+        })
+        .events_only(args.events_only)
+        .target_latency(args.target_latency)
+        .simulate_latency(args.simulate_packet_latency)
+        .skip_priming(args.skip_priming)
+        .lambda(args.lambda)
+        .build()
+        .await?;
+    reconstructor.set_retain_frame_events(false);
     let mut last_time = Instant::now();
     let first_time = last_time;
     let mut frame_count = 0;
@@ -49,7 +54,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         false,
     )?;
     loop {
-        match reconstructor.next(false).await {
+        match reconstructor.next_frame().await {
             None => {
                 println!("\nFinished!");
                 break;
@@ -57,9 +62,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
             Some(image_res) => {
                 frame_count += 1;
                 let image = match image_res {
-                    Ok((a, _packet_ts, _, _)) => a,
-                    Err(_) => {
-                        panic!("No image")
+                    Ok(frame) => frame.image,
+                    Err(e) => {
+                        eprintln!("\nSkipping frame: {}", e);
+                        continue;
                     }
                 };
 
@@ -0,0 +1,882 @@
+use crate::util::reconstructor::ReconstructorError::{ArgumentError, ExportError};
+use crate::util::reconstructor::{
+    EdiEvent, OutputMode, ReconstructedFrame, Reconstructor, ReconstructorError,
+};
+use memmap2::{Mmap, MmapMut};
+use opencv::core::{Mat, MatTrait, MatTraitConst, Scalar, Size, Vector, CV_32F, CV_64F, CV_8U};
+use opencv::imgcodecs::imwrite;
+use opencv::prelude::{VideoWriterTrait, VideoWriterTraitConst};
+use opencv::videoio::VideoWriter;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Writes reconstructed frames to a directory as a numbered image sequence
+pub struct FrameWriter {
+    directory: PathBuf,
+    prefix: String,
+    suffix: String,
+    width: usize,
+    frame_count: u64,
+}
+
+impl FrameWriter {
+    /// Create a writer for the given directory, which is created if it doesn't exist. The
+    /// `pattern` names each file, with a single placeholder for the frame number: either `{}`, or
+    /// `{:0N}` to zero-pad it to `N` digits (e.g., `frame_{:06}.png`). The image format is chosen
+    /// by the extension.
+    pub fn new(directory: &str, pattern: &str) -> Result<FrameWriter, ReconstructorError> {
+        let (prefix, placeholder, suffix) = match (pattern.find('{'), pattern.find('}')) {
+            (Some(open), Some(close)) if open < close => (
+                &pattern[..open],
+                &pattern[open + 1..close],
+                &pattern[close + 1..],
+            ),
+            _ => {
+                return Err(ArgumentError(format!(
+                    "Filename pattern `{}` has no frame number placeholder",
+                    pattern
+                )))
+            }
+        };
+        if suffix.contains('{') || suffix.contains('}') {
+            return Err(ArgumentError(format!(
+                "Filename pattern `{}` has more than one placeholder",
+                pattern
+            )));
+        }
+        let width = match placeholder {
+            "" => 0,
+            spec => spec
+                .strip_prefix(":0")
+                .and_then(|digits| digits.parse::<usize>().ok())
+                .ok_or_else(|| {
+                    ArgumentError(format!(
+                        "Unsupported placeholder `{{{}}}`; use `{{}}` or `{{:0N}}`",
+                        spec
+                    ))
+                })?,
+        };
+
+        std::fs::create_dir_all(directory).map_err(|e| ExportError(e.to_string()))?;
+        Ok(FrameWriter {
+            directory: PathBuf::from(directory),
+            prefix: prefix.to_string(),
+            suffix: suffix.to_string(),
+            width,
+            frame_count: 0,
+        })
+    }
+
+    /// The number of frames written so far, which is also the number of the next frame
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    fn next_path(&self) -> PathBuf {
+        let filename = format!(
+            "{}{:0width$}{}",
+            self.prefix,
+            self.frame_count,
+            self.suffix,
+            width = self.width
+        );
+        self.directory.join(filename)
+    }
+
+    /// Write a frame. `CV_64F` and `CV_32F` latent images are scaled from `[0, 1]` to 8-bit;
+    /// other images are written as they are.
+    pub fn write(&mut self, mat: &Mat) -> Result<(), ReconstructorError> {
+        let image = to_8u(mat)?;
+        let path = self.next_path();
+        let path = path
+            .to_str()
+            .ok_or_else(|| ExportError("Output path is not valid UTF-8".to_string()))?;
+        if !imwrite(path, &image, &Vector::new())? {
+            return Err(ExportError(format!("Could not write {}", path)));
+        }
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    /// Write a single-channel frame as a PNG, with its metadata embedded in `tEXt` chunks so that
+    /// the file describes itself. The pattern must end in `.png`. The image is scaled as in
+    /// [`FrameWriter::write`]. Read the metadata back with [`FrameMetadata::read_png`].
+    pub fn write_with_metadata(
+        &mut self,
+        mat: &Mat,
+        metadata: &FrameMetadata,
+    ) -> Result<(), ReconstructorError> {
+        if !self.suffix.to_ascii_lowercase().ends_with(".png") {
+            return Err(ArgumentError(
+                "Frame metadata can only be embedded in PNG files".to_string(),
+            ));
+        }
+        if mat.channels() != 1 {
+            return Err(ArgumentError(
+                "Frames with metadata must be single-channel".to_string(),
+            ));
+        }
+        let image = to_8u(mat)?;
+        if image.depth() != CV_8U {
+            return Err(ArgumentError(
+                "Frames with metadata must be 8-bit or floating-point".to_string(),
+            ));
+        }
+
+        let path = self.next_path();
+        let file = File::create(&path).map_err(|e| ExportError(e.to_string()))?;
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(file),
+            image.cols() as u32,
+            image.rows() as u32,
+        );
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        for (keyword, text) in metadata.text_chunks() {
+            encoder
+                .add_text_chunk(keyword.to_string(), text)
+                .map_err(png_error)?;
+        }
+        let mut writer = encoder.write_header().map_err(png_error)?;
+        writer
+            .write_image_data(image.data_bytes()?)
+            .map_err(png_error)?;
+        writer.finish().map_err(png_error)?;
+        self.frame_count += 1;
+        Ok(())
+    }
+}
+
+/// Scale `CV_64F` and `CV_32F` latent images from `[0, 1]` to 8-bit, and leave others as they are
+fn to_8u(mat: &Mat) -> Result<Mat, ReconstructorError> {
+    match mat.depth() {
+        CV_64F | CV_32F => {
+            let mut image_8u = Mat::default();
+            mat.convert_to(&mut image_8u, CV_8U, 255.0, 0.0)?;
+            Ok(image_8u)
+        }
+        _ => Ok(mat.clone()),
+    }
+}
+
+fn png_error(error: impl std::fmt::Display) -> ReconstructorError {
+    ExportError(error.to_string())
+}
+
+const PNG_TIMESTAMP_KEY: &str = "Timestamp";
+const PNG_C_KEY: &str = "Contrast threshold";
+const PNG_EXPOSURE_BEGIN_KEY: &str = "Exposure begin";
+const PNG_EXPOSURE_END_KEY: &str = "Exposure end";
+
+/// The metadata of a reconstructed frame, for embedding in an exported image. See
+/// [`FrameWriter::write_with_metadata`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameMetadata {
+    /// The frame's timestamp: the middle of its interval, or the reference instant of its
+    /// exposure in [`OutputMode::SharpFrameOnly`](crate::util::reconstructor::OutputMode)
+    pub timestamp: i64,
+
+    /// The contrast threshold used for the reconstruction
+    pub c: f64,
+
+    /// The exposure of the APS frame that the frame was reconstructed from
+    pub exposure_begin_t: i64,
+    pub exposure_end_t: i64,
+}
+
+impl FrameMetadata {
+    /// Get the metadata of the frame most recently returned by a reconstructor, or `None` if no
+    /// frame has been returned yet
+    pub fn for_last_frame(reconstructor: &Reconstructor) -> Option<FrameMetadata> {
+        let timestamp = reconstructor.last_returned_timestamp()?;
+        let stats = reconstructor.last_frame_stats()?;
+        let timestamp = match reconstructor.output_mode() {
+            OutputMode::FullSequence => {
+                timestamp
+                    + (reconstructor.timebase_hz() / reconstructor.current_output_fps() / 2.0)
+                        .round() as i64
+            }
+            OutputMode::SharpFrameOnly => timestamp,
+        };
+        Some(FrameMetadata {
+            timestamp,
+            c: stats.c,
+            exposure_begin_t: stats.exposure_begin_t,
+            exposure_end_t: stats.exposure_end_t,
+        })
+    }
+
+    fn text_chunks(&self) -> [(&'static str, String); 4] {
+        [
+            (PNG_TIMESTAMP_KEY, self.timestamp.to_string()),
+            (PNG_C_KEY, self.c.to_string()),
+            (PNG_EXPOSURE_BEGIN_KEY, self.exposure_begin_t.to_string()),
+            (PNG_EXPOSURE_END_KEY, self.exposure_end_t.to_string()),
+        ]
+    }
+
+    /// Read the metadata embedded in a PNG written by [`FrameWriter::write_with_metadata`]
+    pub fn read_png(path: &Path) -> Result<FrameMetadata, ReconstructorError> {
+        let file = File::open(path).map_err(|e| ExportError(e.to_string()))?;
+        let reader = png::Decoder::new(file).read_info().map_err(png_error)?;
+        let chunks = &reader.info().uncompressed_latin1_text;
+        let field = |keyword: &str| {
+            chunks
+                .iter()
+                .find(|chunk| chunk.keyword == keyword)
+                .map(|chunk| chunk.text.as_str())
+                .ok_or_else(|| ExportError(format!("PNG has no `{}` text chunk", keyword)))
+        };
+        let parse_error = |e: std::num::ParseIntError| ExportError(e.to_string());
+        Ok(FrameMetadata {
+            timestamp: field(PNG_TIMESTAMP_KEY)?.parse().map_err(parse_error)?,
+            c: field(PNG_C_KEY)?
+                .parse()
+                .map_err(|e: std::num::ParseFloatError| ExportError(e.to_string()))?,
+            exposure_begin_t: field(PNG_EXPOSURE_BEGIN_KEY)?
+                .parse()
+                .map_err(parse_error)?,
+            exposure_end_t: field(PNG_EXPOSURE_END_KEY)?.parse().map_err(parse_error)?,
+        })
+    }
+}
+
+/// Writes reconstructed frames to a grayscale video file
+pub struct VideoExporter {
+    writer: VideoWriter,
+    size: Size,
+}
+
+impl VideoExporter {
+    /// Open a video file for frames of the given `(height, width)`. `fourcc` selects the codec,
+    /// e.g., `VideoWriter::fourcc('M', 'J', 'P', 'G')`. Fails if OpenCV can't open a writer with
+    /// the codec, rather than producing an empty file.
+    pub fn new(
+        path: &str,
+        fourcc: i32,
+        fps: f64,
+        (height, width): (i32, i32),
+    ) -> Result<VideoExporter, ReconstructorError> {
+        if !fps.is_finite() || fps <= 0.0 {
+            return Err(ArgumentError(
+                "Video frame rate must be positive".to_string(),
+            ));
+        }
+        let size = Size::new(width, height);
+        let writer = VideoWriter::new(path, fourcc, fps, size, false)?;
+        if !writer.is_opened()? {
+            return Err(ExportError(format!(
+                "Could not open {} for writing. Is the codec available?",
+                path
+            )));
+        }
+        Ok(VideoExporter { writer, size })
+    }
+
+    /// Open a video file matching the output frame rate and dimensions of a reconstructor
+    pub fn for_reconstructor(
+        path: &str,
+        fourcc: i32,
+        reconstructor: &Reconstructor,
+    ) -> Result<VideoExporter, ReconstructorError> {
+        VideoExporter::new(
+            path,
+            fourcc,
+            reconstructor.current_output_fps(),
+            reconstructor.output_dimensions(),
+        )
+    }
+
+    /// Write a frame. `CV_64F` and `CV_32F` latent images are scaled from `[0, 1]` to 8-bit.
+    /// OpenCV silently drops frames of the wrong size, so those are an error here.
+    pub fn push_frame(&mut self, latent: &Mat) -> Result<(), ReconstructorError> {
+        if latent.size()? != self.size {
+            return Err(ArgumentError(format!(
+                "Frame is {}x{}, but the video is {}x{}",
+                latent.rows(),
+                latent.cols(),
+                self.size.height,
+                self.size.width
+            )));
+        }
+        let mut image_8u = Mat::default();
+        match latent.depth() {
+            CV_64F | CV_32F => latent.convert_to(&mut image_8u, CV_8U, 255.0, 0.0)?,
+            _ => latent.convert_to(&mut image_8u, CV_8U, 1.0, 0.0)?,
+        }
+        self.writer.write(&image_8u)?;
+        Ok(())
+    }
+
+    /// Finish the video and release the file
+    pub fn finish(mut self) -> Result<(), ReconstructorError> {
+        self.writer.release()?;
+        Ok(())
+    }
+}
+
+/// Identifies a frame file written by [`MmapFrameWriter`]
+const MMAP_MAGIC: &[u8; 8] = b"EDIMMAP1";
+
+/// The header is the magic, then the rows, columns, and OpenCV type of the frames as
+/// little-endian `u32`s, then the number of frames as a little-endian `u64`
+const MMAP_HEADER_BYTES: usize = 28;
+
+/// How many frames of space to add to the file when it fills up, at minimum
+const MMAP_MIN_GROWTH_FRAMES: u64 = 16;
+
+fn mmap_error(error: std::io::Error) -> ReconstructorError {
+    ExportError(error.to_string())
+}
+
+/// Writes reconstructed frames to a memory-mapped file, so that long recordings can be read back
+/// in any order with [`MmapFrameReader`] without loading them all into memory. Every frame has the
+/// same size, so frame `i` is at a fixed offset, and the header serves as the index. The file
+/// grows as frames are written. The pixel data is in the machine's native byte order.
+pub struct MmapFrameWriter {
+    file: File,
+    mmap: MmapMut,
+    rows: i32,
+    cols: i32,
+    mat_type: i32,
+    frame_bytes: usize,
+    frame_count: u64,
+    capacity: u64,
+}
+
+impl MmapFrameWriter {
+    /// Create (or overwrite) the file at `path` for single-channel `CV_64F` or `CV_32F` frames of
+    /// the given `(height, width)`
+    pub fn new(
+        path: &Path,
+        (height, width): (i32, i32),
+        mat_type: i32,
+    ) -> Result<MmapFrameWriter, ReconstructorError> {
+        let element_bytes = match mat_type {
+            CV_64F => 8,
+            CV_32F => 4,
+            _ => {
+                return Err(ArgumentError(
+                    "Memory-mapped frames must be CV_64F or CV_32F".to_string(),
+                ))
+            }
+        };
+        if height <= 0 || width <= 0 {
+            return Err(ArgumentError(
+                "Frame dimensions must be positive".to_string(),
+            ));
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(mmap_error)?;
+        file.set_len(MMAP_HEADER_BYTES as u64).map_err(mmap_error)?;
+        // Safety: the file was just created by us, and isn't resized while this map is alive
+        let mmap = unsafe { MmapMut::map_mut(&file) }.map_err(mmap_error)?;
+        let mut writer = MmapFrameWriter {
+            file,
+            mmap,
+            rows: height,
+            cols: width,
+            mat_type,
+            frame_bytes: height as usize * width as usize * element_bytes,
+            frame_count: 0,
+            capacity: 0,
+        };
+        writer.mmap[..8].copy_from_slice(MMAP_MAGIC);
+        writer.mmap[8..12].copy_from_slice(&(height as u32).to_le_bytes());
+        writer.mmap[12..16].copy_from_slice(&(width as u32).to_le_bytes());
+        writer.mmap[16..20].copy_from_slice(&(mat_type as u32).to_le_bytes());
+        writer.write_frame_count();
+        Ok(writer)
+    }
+
+    /// The number of frames written so far
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    fn write_frame_count(&mut self) {
+        self.mmap[20..28].copy_from_slice(&self.frame_count.to_le_bytes());
+    }
+
+    /// Grow the file to hold at least one more frame, doubling its capacity to amortize the
+    /// remapping
+    fn grow(&mut self) -> Result<(), ReconstructorError> {
+        self.mmap.flush().map_err(mmap_error)?;
+        self.capacity = (self.capacity * 2).max(MMAP_MIN_GROWTH_FRAMES);
+        let len = MMAP_HEADER_BYTES as u64 + self.capacity * self.frame_bytes as u64;
+        self.file.set_len(len).map_err(mmap_error)?;
+        // Safety: as in `new`. The old map is replaced, so nothing refers to the old length.
+        self.mmap = unsafe { MmapMut::map_mut(&self.file) }.map_err(mmap_error)?;
+        Ok(())
+    }
+
+    /// Append a frame, which must match the writer's dimensions and type
+    pub fn write(&mut self, mat: &Mat) -> Result<(), ReconstructorError> {
+        if mat.rows() != self.rows || mat.cols() != self.cols || mat.typ() != self.mat_type {
+            return Err(ArgumentError(format!(
+                "Frame is {}x{} of type {}, but the file holds {}x{} of type {}",
+                mat.rows(),
+                mat.cols(),
+                mat.typ(),
+                self.rows,
+                self.cols,
+                self.mat_type
+            )));
+        }
+        if self.frame_count == self.capacity {
+            self.grow()?;
+        }
+        // A cropped frame is a view with gaps between its rows, so copy it out first
+        let continuous;
+        let data = match mat.is_continuous() {
+            true => mat.data_bytes()?,
+            false => {
+                continuous = mat.try_clone()?;
+                continuous.data_bytes()?
+            }
+        };
+        let offset = MMAP_HEADER_BYTES + self.frame_count as usize * self.frame_bytes;
+        self.mmap[offset..offset + self.frame_bytes].copy_from_slice(data);
+        self.frame_count += 1;
+        self.write_frame_count();
+        Ok(())
+    }
+
+    /// Trim the unused capacity, and flush the file
+    pub fn finish(self) -> Result<(), ReconstructorError> {
+        self.mmap.flush().map_err(mmap_error)?;
+        let len = MMAP_HEADER_BYTES as u64 + self.frame_count * self.frame_bytes as u64;
+        drop(self.mmap);
+        self.file.set_len(len).map_err(mmap_error)?;
+        self.file.sync_all().map_err(mmap_error)
+    }
+}
+
+/// Reads frames in any order from a file written by [`MmapFrameWriter`]
+pub struct MmapFrameReader {
+    mmap: Mmap,
+    rows: i32,
+    cols: i32,
+    mat_type: i32,
+    frame_bytes: usize,
+    frame_count: u64,
+}
+
+impl MmapFrameReader {
+    pub fn open(path: &Path) -> Result<MmapFrameReader, ReconstructorError> {
+        let file = File::open(path).map_err(mmap_error)?;
+        // Safety: the file must not be modified while it's being read
+        let mmap = unsafe { Mmap::map(&file) }.map_err(mmap_error)?;
+        if mmap.len() < MMAP_HEADER_BYTES || &mmap[..8] != MMAP_MAGIC {
+            return Err(ArgumentError(format!(
+                "{} is not a memory-mapped frame file",
+                path.display()
+            )));
+        }
+        let read_u32 =
+            |offset: usize| u32::from_le_bytes(mmap[offset..offset + 4].try_into().unwrap()) as i32;
+        let (rows, cols, mat_type) = (read_u32(8), read_u32(12), read_u32(16));
+        let frame_count = u64::from_le_bytes(mmap[20..28].try_into().unwrap());
+        let element_bytes = match mat_type {
+            CV_64F => 8,
+            CV_32F => 4,
+            _ => {
+                return Err(ArgumentError(format!(
+                    "Unsupported frame type {}",
+                    mat_type
+                )))
+            }
+        };
+        let frame_bytes = rows as usize * cols as usize * element_bytes;
+        if mmap.len() < MMAP_HEADER_BYTES + frame_count as usize * frame_bytes {
+            return Err(ArgumentError(format!("{} is truncated", path.display())));
+        }
+        Ok(MmapFrameReader {
+            mmap,
+            rows,
+            cols,
+            mat_type,
+            frame_bytes,
+            frame_count,
+        })
+    }
+
+    /// The number of frames in the file
+    pub fn len(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frame_count == 0
+    }
+
+    /// The (height, width) of the frames
+    pub fn dimensions(&self) -> (i32, i32) {
+        (self.rows, self.cols)
+    }
+
+    /// Read frame `index` into a new `Mat`
+    pub fn frame(&self, index: u64) -> Result<Mat, ReconstructorError> {
+        if index >= self.frame_count {
+            return Err(ArgumentError(format!(
+                "Frame {} is out of range for {} frames",
+                index, self.frame_count
+            )));
+        }
+        let mut mat =
+            Mat::new_rows_cols_with_default(self.rows, self.cols, self.mat_type, Scalar::all(0.0))?;
+        let offset = MMAP_HEADER_BYTES + index as usize * self.frame_bytes;
+        mat.data_bytes_mut()?
+            .copy_from_slice(&self.mmap[offset..offset + self.frame_bytes]);
+        Ok(mat)
+    }
+}
+
+const EVENT_CHUNK_MAGIC: &[u8; 8] = b"EDIEVTS1";
+
+/// Writes the events that produced each reconstructed frame to a file, alongside the frames, so
+/// that each frame can be traced back to its events. The file starts with a magic number, then
+/// holds one chunk per frame, in order:
+/// - the frame number, as a `u64`
+/// - the numbers of events before the frame and after its start, as two `u32`s
+/// - the events before, then the events after, each as `t: i64`, `x: u16`, `y: u16`, and
+///   `polarity: u8`
+///
+/// All values are little-endian. See [`ReconstructedFrame`] for which events each output mode
+/// returns.
+pub struct EventChunkWriter {
+    writer: BufWriter<File>,
+    frame_count: u64,
+}
+
+impl EventChunkWriter {
+    /// Create (or overwrite) the file at `path`
+    pub fn new(path: &Path) -> Result<EventChunkWriter, ReconstructorError> {
+        let mut writer =
+            BufWriter::new(File::create(path).map_err(|e| ExportError(e.to_string()))?);
+        writer
+            .write_all(EVENT_CHUNK_MAGIC)
+            .map_err(|e| ExportError(e.to_string()))?;
+        Ok(EventChunkWriter {
+            writer,
+            frame_count: 0,
+        })
+    }
+
+    /// The number of frames written so far, which is also the number of the next frame
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Append the events of a frame. The frame must carry its events; see
+    /// [`Reconstructor::set_retain_frame_events`].
+    pub fn write(&mut self, frame: &ReconstructedFrame) -> Result<(), ReconstructorError> {
+        let (before, after) = match (&frame.events_before, &frame.events_after) {
+            (before, Some(after)) => (before.as_deref().unwrap_or_default(), after),
+            (_, None) => {
+                return Err(ArgumentError(
+                    "Frame has no events; enable retain_frame_events".to_string(),
+                ))
+            }
+        };
+        let mut chunk = Vec::with_capacity(16 + 13 * (before.len() + after.len()));
+        chunk.extend_from_slice(&self.frame_count.to_le_bytes());
+        chunk.extend_from_slice(&(before.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(&(after.len() as u32).to_le_bytes());
+        for event in before.iter().chain(after.iter()) {
+            write_event(&mut chunk, event);
+        }
+        self.writer
+            .write_all(&chunk)
+            .map_err(|e| ExportError(e.to_string()))?;
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    /// Flush the file
+    pub fn finish(mut self) -> Result<(), ReconstructorError> {
+        self.writer.flush().map_err(|e| ExportError(e.to_string()))
+    }
+}
+
+fn write_event(chunk: &mut Vec<u8>, event: &EdiEvent) {
+    chunk.extend_from_slice(&event.t.to_le_bytes());
+    chunk.extend_from_slice(&event.x.to_le_bytes());
+    chunk.extend_from_slice(&event.y.to_le_bytes());
+    chunk.push(event.polarity as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::reconstructor::{dmatrix_to_mat, mat_to_dmatrix};
+    use crate::util::test_support::{self, EXPOSURES};
+    use nalgebra::DMatrix;
+    use opencv::imgcodecs::{imread, IMREAD_UNCHANGED};
+    use opencv::prelude::{VideoCaptureTrait, VideoCaptureTraitConst};
+    use opencv::videoio::{VideoCapture, CAP_ANY, CAP_PROP_FRAME_COUNT};
+
+    /// A fresh directory for a test's output files
+    fn output_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn frames_are_written_as_a_numbered_8_bit_sequence() {
+        let directory = output_directory("davis-edi-frame-writer");
+        let mut writer = FrameWriter::new(directory.to_str().unwrap(), "frame_{:04}.png").unwrap();
+        let latent = DMatrix::from_fn(2, 3, |row, col| (row * 3 + col) as f64 / 5.0);
+        writer.write(&dmatrix_to_mat(&latent).unwrap()).unwrap();
+        writer
+            .write(&dmatrix_to_mat(&latent.map(|v| 1.0 - v)).unwrap())
+            .unwrap();
+        assert_eq!(writer.frame_count(), 2);
+
+        let image = imread(
+            directory.join("frame_0000.png").to_str().unwrap(),
+            IMREAD_UNCHANGED,
+        )
+        .unwrap();
+        assert_eq!((image.rows(), image.cols(), image.typ()), (2, 3, CV_8U));
+        assert_eq!(image.data_bytes().unwrap(), &[0, 51, 102, 153, 204, 255]);
+        let image = imread(
+            directory.join("frame_0001.png").to_str().unwrap(),
+            IMREAD_UNCHANGED,
+        )
+        .unwrap();
+        assert_eq!(image.data_bytes().unwrap(), &[255, 204, 153, 102, 51, 0]);
+
+        let mut writer = FrameWriter::new(directory.to_str().unwrap(), "{}.png").unwrap();
+        writer.write(&dmatrix_to_mat(&latent).unwrap()).unwrap();
+        assert!(directory.join("0.png").exists());
+    }
+
+    #[test]
+    fn patterns_need_exactly_one_supported_placeholder() {
+        let directory = output_directory("davis-edi-frame-patterns");
+        let directory = directory.to_str().unwrap();
+        for pattern in [
+            "frame.png",
+            "frame_{:4}.png",
+            "frame_{}_{}.png",
+            "frame_}{.png",
+        ] {
+            assert!(matches!(
+                FrameWriter::new(directory, pattern),
+                Err(ArgumentError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn videos_hold_every_frame_of_the_right_size() {
+        let path = std::env::temp_dir().join("davis-edi-video.avi");
+        let path = path.to_str().unwrap();
+        let fourcc = VideoWriter::fourcc('M', 'J', 'P', 'G').unwrap();
+        let mut exporter = VideoExporter::new(path, fourcc, 30.0, (16, 24)).unwrap();
+        for value in [0.2, 0.5, 0.8] {
+            let latent = DMatrix::from_element(16, 24, value);
+            exporter
+                .push_frame(&dmatrix_to_mat(&latent).unwrap())
+                .unwrap();
+        }
+        let wrong_size = dmatrix_to_mat(&DMatrix::from_element(24, 16, 0.5)).unwrap();
+        assert!(matches!(
+            exporter.push_frame(&wrong_size),
+            Err(ArgumentError(_))
+        ));
+        exporter.finish().unwrap();
+
+        let mut capture = VideoCapture::from_file(path, CAP_ANY).unwrap();
+        assert_eq!(capture.get(CAP_PROP_FRAME_COUNT).unwrap(), 3.0);
+        let mut frame = Mat::default();
+        assert!(capture.read(&mut frame).unwrap());
+        assert_eq!((frame.rows(), frame.cols()), (16, 24));
+
+        assert!(matches!(
+            VideoExporter::new(path, fourcc, 0.0, (16, 24)),
+            Err(ArgumentError(_))
+        ));
+    }
+
+    #[test]
+    fn mmap_frames_read_back_in_any_order() {
+        let directory = output_directory("davis-edi-mmap");
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("frames.bin");
+        let image =
+            |index: usize| DMatrix::from_fn(3, 4, |row, col| (index + row * 4 + col) as f64);
+
+        // Enough frames to grow the file more than once
+        let count = 2 * MMAP_MIN_GROWTH_FRAMES as usize + 1;
+        let mut writer = MmapFrameWriter::new(&path, (3, 4), CV_64F).unwrap();
+        for index in 0..count {
+            writer
+                .write(&dmatrix_to_mat(&image(index)).unwrap())
+                .unwrap();
+        }
+        assert!(matches!(
+            writer.write(&dmatrix_to_mat(&DMatrix::zeros(4, 3)).unwrap()),
+            Err(ArgumentError(_))
+        ));
+        assert_eq!(writer.frame_count(), count as u64);
+        writer.finish().unwrap();
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            (MMAP_HEADER_BYTES + count * 3 * 4 * 8) as u64
+        );
+
+        let reader = MmapFrameReader::open(&path).unwrap();
+        assert_eq!((reader.len(), reader.dimensions()), (count as u64, (3, 4)));
+        for index in [count - 1, 0, count / 2] {
+            let frame = reader.frame(index as u64).unwrap();
+            assert_eq!(mat_to_dmatrix(&frame).unwrap(), image(index));
+        }
+        assert!(matches!(reader.frame(count as u64), Err(ArgumentError(_))));
+
+        // Cropped single-precision frames are copied out of their parent
+        let wide =
+            dmatrix_to_mat(&DMatrix::from_fn(3, 6, |row, col| (row * 6 + col) as f64)).unwrap();
+        let mut wide_32f = Mat::default();
+        wide.convert_to(&mut wide_32f, CV_32F, 1.0, 0.0).unwrap();
+        let cropped = Mat::roi(&wide_32f, opencv::core::Rect::new(1, 0, 4, 3)).unwrap();
+        assert!(!cropped.is_continuous());
+        let mut writer = MmapFrameWriter::new(&path, (3, 4), CV_32F).unwrap();
+        writer.write(&cropped).unwrap();
+        writer.finish().unwrap();
+        let reader = MmapFrameReader::open(&path).unwrap();
+        let frame = reader.frame(0).unwrap();
+        assert_eq!(frame.typ(), CV_32F);
+        assert_eq!(
+            mat_to_dmatrix(&frame).unwrap(),
+            mat_to_dmatrix(&cropped).unwrap()
+        );
+
+        assert!(MmapFrameWriter::new(&path, (3, 4), CV_8U).is_err());
+        let other = directory.join("other.bin");
+        std::fs::write(&other, b"not a frame file at all, honestly").unwrap();
+        assert!(matches!(
+            MmapFrameReader::open(&other),
+            Err(ArgumentError(_))
+        ));
+    }
+
+    #[test]
+    fn event_chunks_hold_each_frames_events_in_order() {
+        let directory = output_directory("davis-edi-event-chunks");
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("events.bin");
+        let event = |t, x, y, polarity| EdiEvent { t, x, y, polarity };
+        let frame = |events: Option<(Vec<EdiEvent>, Vec<EdiEvent>)>| {
+            ReconstructedFrame::from((
+                Mat::default(),
+                None,
+                events.map(|(before, after)| (0.25, before, after, 0, 1000)),
+                None,
+            ))
+        };
+
+        let mut writer = EventChunkWriter::new(&path).unwrap();
+        writer
+            .write(&frame(Some((
+                vec![event(-5, 1, 2, true)],
+                vec![event(300, 65535, 0, false), event(301, 4, 3, true)],
+            ))))
+            .unwrap();
+        writer.write(&frame(Some((vec![], vec![])))).unwrap();
+        assert!(matches!(writer.write(&frame(None)), Err(ArgumentError(_))));
+        assert_eq!(writer.frame_count(), 2);
+        writer.finish().unwrap();
+
+        let mut expected = EVENT_CHUNK_MAGIC.to_vec();
+        expected.extend_from_slice(&0u64.to_le_bytes());
+        expected.extend_from_slice(&1u32.to_le_bytes());
+        expected.extend_from_slice(&2u32.to_le_bytes());
+        for (t, x, y, polarity) in [(-5i64, 1u16, 2u16, 1u8), (300, 65535, 0, 0), (301, 4, 3, 1)] {
+            expected.extend_from_slice(&t.to_le_bytes());
+            expected.extend_from_slice(&x.to_le_bytes());
+            expected.extend_from_slice(&y.to_le_bytes());
+            expected.push(polarity);
+        }
+        expected.extend_from_slice(&1u64.to_le_bytes());
+        expected.extend_from_slice(&[0; 8]);
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+    }
+
+    #[test]
+    fn png_metadata_reads_back() {
+        let directory = output_directory("davis-edi-png-metadata");
+        let metadata = FrameMetadata {
+            timestamp: 2500,
+            c: 0.215,
+            exposure_begin_t: 2000,
+            exposure_end_t: 7000,
+        };
+        let latent = DMatrix::from_fn(2, 3, |row, col| (row * 3 + col) as f64 / 5.0);
+        let mut writer = FrameWriter::new(directory.to_str().unwrap(), "frame_{:04}.png").unwrap();
+        writer
+            .write_with_metadata(&dmatrix_to_mat(&latent).unwrap(), &metadata)
+            .unwrap();
+        assert_eq!(writer.frame_count(), 1);
+
+        let path = directory.join("frame_0000.png");
+        assert_eq!(FrameMetadata::read_png(&path).unwrap(), metadata);
+        let image = imread(path.to_str().unwrap(), IMREAD_UNCHANGED).unwrap();
+        assert_eq!(image.data_bytes().unwrap(), &[0, 51, 102, 153, 204, 255]);
+
+        // A plain PNG has no metadata to read
+        writer.write(&dmatrix_to_mat(&latent).unwrap()).unwrap();
+        assert!(matches!(
+            FrameMetadata::read_png(&directory.join("frame_0001.png")),
+            Err(ExportError(_))
+        ));
+
+        let mut color = Mat::default();
+        opencv::imgproc::cvt_color(
+            &dmatrix_to_mat(&latent).unwrap(),
+            &mut color,
+            opencv::imgproc::COLOR_GRAY2BGR,
+            0,
+        )
+        .unwrap();
+        assert!(matches!(
+            writer.write_with_metadata(&color, &metadata),
+            Err(ArgumentError(_))
+        ));
+        let mut writer = FrameWriter::new(directory.to_str().unwrap(), "{}.jpg").unwrap();
+        assert!(matches!(
+            writer.write_with_metadata(&dmatrix_to_mat(&latent).unwrap(), &metadata),
+            Err(ArgumentError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn metadata_describes_the_last_returned_frame() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        assert_eq!(FrameMetadata::for_last_frame(&reconstructor), None);
+        reconstructor.next_frame().await.unwrap().unwrap();
+
+        // Sequence frames are stamped at the middle of their interval
+        assert_eq!(
+            FrameMetadata::for_last_frame(&reconstructor),
+            Some(FrameMetadata {
+                timestamp: 2500,
+                c: test_support::C,
+                exposure_begin_t: 2000,
+                exposure_end_t: 7000,
+            })
+        );
+    }
+}
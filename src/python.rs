@@ -0,0 +1,129 @@
+use crate::util::config::{InputSource, ReconstructorConfig};
+use crate::util::reconstructor::{Reconstructor, ReconstructorError};
+use ndarray::Array2;
+use numpy::{IntoPyArray, PyArray2};
+use opencv::core::{Mat, MatTraitConst, CV_64F};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use std::path::Path;
+use tokio::runtime::Runtime;
+
+fn to_py_err(error: ReconstructorError) -> PyErr {
+    match error {
+        ReconstructorError::ArgumentError(_) | ReconstructorError::ConfigError(_) => {
+            PyValueError::new_err(error.to_string())
+        }
+        _ => PyRuntimeError::new_err(error.to_string()),
+    }
+}
+
+/// Copy a single-channel latent image into a 2D array of `f64`
+fn mat_to_array(mat: &Mat) -> Result<Array2<f64>, ReconstructorError> {
+    let mut mat_64f = Mat::default();
+    mat.convert_to(&mut mat_64f, CV_64F, 1.0, 0.0)?;
+    let shape = (mat_64f.rows() as usize, mat_64f.cols() as usize);
+    Array2::from_shape_vec(shape, mat_64f.data_typed::<f64>()?.to_vec())
+        .map_err(|e| ReconstructorError::ConversionError(e.to_string()))
+}
+
+/// A blocking wrapper around [`Reconstructor`] for Python, which reads an aedat4 file and yields
+/// the reconstructed frames as 2D NumPy arrays of intensities in `[0, 1]`
+#[pyclass(name = "Reconstructor", unsendable)]
+pub struct PyReconstructor {
+    runtime: Runtime,
+    reconstructor: Reconstructor,
+}
+
+#[pymethods]
+impl PyReconstructor {
+    /// Open the aedat4 file at `path`. `config_path` is an optional TOML or JSON
+    /// [`ReconstructorConfig`]; otherwise, the defaults are used.
+    #[new]
+    #[pyo3(signature = (path, config_path = None))]
+    fn new(path: &str, config_path: Option<&str>) -> PyResult<Self> {
+        let config = match config_path {
+            Some(config_path) => ReconstructorConfig::load(Path::new(config_path)),
+            None => Ok(ReconstructorConfig::default()),
+        }
+        .map_err(to_py_err)?;
+
+        let path = Path::new(path);
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| PyValueError::new_err("Path has no file name"))?;
+        let directory = match path.parent().and_then(|parent| parent.to_str()) {
+            Some("") | None => ".",
+            Some(parent) => parent,
+        };
+        let source = InputSource {
+            mode: "file".to_string(),
+            directory: directory.to_string(),
+            aedat_filename_0: file_name.to_string(),
+            aedat_filename_1: String::new(),
+        };
+
+        let runtime = Runtime::new().map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        let mut reconstructor = runtime
+            .block_on(Reconstructor::from_config(config, source))
+            .map_err(to_py_err)?;
+
+        // Only the images are returned to Python
+        reconstructor.set_retain_frame_events(false);
+        Ok(PyReconstructor {
+            runtime,
+            reconstructor,
+        })
+    }
+
+    /// The `(height, width)` of the output frames
+    #[getter]
+    fn shape(&self) -> (i32, i32) {
+        self.reconstructor.output_dimensions()
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<Py<PyArray2<f64>>>> {
+        match self.runtime.block_on(self.reconstructor.next_frame()) {
+            None => Ok(None),
+            Some(Ok(frame)) => {
+                let array = mat_to_array(&frame.image).map_err(to_py_err)?;
+                Ok(Some(array.into_pyarray(py).to_owned()))
+            }
+            Some(Err(e)) => Err(PyRuntimeError::new_err(e.to_string())),
+        }
+    }
+}
+
+#[pymodule]
+fn davis_edi_rs(_py: Python<'_>, module: &PyModule) -> PyResult<()> {
+    module.add_class::<PyReconstructor>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::reconstructor::dmatrix_to_mat;
+    use nalgebra::DMatrix;
+    use opencv::core::CV_32F;
+
+    #[test]
+    fn frames_become_row_major_arrays() {
+        let image = DMatrix::from_fn(2, 3, |row, col| (row * 3 + col) as f64 / 8.0);
+        let mat = dmatrix_to_mat(&image).unwrap();
+        let array = mat_to_array(&mat).unwrap();
+        assert_eq!(array.dim(), (2, 3));
+        for ((row, col), &value) in array.indexed_iter() {
+            assert_eq!(value, image[(row, col)]);
+        }
+
+        // Single-precision frames are widened to match
+        let mut mat_32f = Mat::default();
+        mat.convert_to(&mut mat_32f, CV_32F, 1.0, 0.0).unwrap();
+        assert_eq!(mat_to_array(&mat_32f).unwrap(), array);
+    }
+}
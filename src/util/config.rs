@@ -0,0 +1,476 @@
+use crate::util::display::DisplayView;
+use crate::util::event_adder::MAX_C_SEARCH_POINTS;
+use crate::util::reconstructor::{
+    EdgeThreshold, FpsResolutionCheck, GradientOperator, LatentCarry, LatentReference, OutputMode,
+    ReconstructorError, TruncatedExposure, DEFAULT_LAMBDA, DEFAULT_MAX_C, DEFAULT_MIN_C,
+    DEFAULT_N_POINTS, DEFAULT_TIMEBASE_HZ,
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Where the reconstructor reads its input from
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InputSource {
+    /// Valid options are "file", "socket", and "tcp"
+    pub mode: String,
+
+    /// Directory containing the input aedat4 file or sockets
+    pub directory: String,
+
+    /// The input aedat4 file, or the events socket
+    pub aedat_filename_0: String,
+
+    /// The frames socket. Unused in the "file" mode.
+    pub aedat_filename_1: String,
+}
+
+/// Every tunable parameter of a [`Reconstructor`](crate::util::reconstructor::Reconstructor),
+/// so that reconstruction settings can be versioned and shared as a TOML or JSON file. Missing
+/// fields take their default values. See the corresponding `Reconstructor` setters for what each
+/// option does.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconstructorConfig {
+    pub start_c: f64,
+    pub optimize_c: bool,
+    pub optimize_c_frequency: u32,
+    pub optimize_controller: bool,
+    pub lambda: f64,
+    pub min_c: f64,
+    pub max_c: f64,
+    pub n_points: u32,
+    pub show_display: bool,
+
+    /// Also show the latent image at the optimized c after each c-optimization
+    pub debug_display: bool,
+    pub show_blurred_display: bool,
+    pub output_fps: f64,
+    pub output_mode: OutputMode,
+    pub events_only: bool,
+
+    /// The target maximum latency, in milliseconds
+    pub target_latency: f64,
+    pub simulate_latency: bool,
+    pub skip_priming: bool,
+
+    /// If true, events before the first APS frame of a file are discarded. If false, they're kept
+    /// and sorted against the first frame, as they always are for live sources.
+    pub require_initial_frame: bool,
+
+    /// How long to wait for the first APS frame, in milliseconds, before giving up on the source.
+    /// `None` waits indefinitely. Only effective for live sources, since reading a file doesn't
+    /// yield to the timer.
+    pub initial_frame_timeout: Option<f64>,
+
+    /// How long a live source may stop sending packets, in milliseconds, before it's considered
+    /// ended. `None` ends it immediately. File sources always end immediately.
+    pub end_of_stream_grace: Option<f64>,
+    pub timebase_hz: f64,
+    pub super_resolution_factor: f64,
+    pub edge_window_intervals: Option<u32>,
+
+    /// The time constant of the edge image's decay, in ticks. `None` is one second.
+    pub edge_decay_tau: Option<f64>,
+    pub event_trim_intervals: Option<i64>,
+    pub rolling_shutter_line_delay: Option<i64>,
+    pub output_event_counts: bool,
+    pub estimate_variance: bool,
+    pub keep_log_latents: bool,
+    pub fps_resolution_check: FpsResolutionCheck,
+
+    /// What to do with the final APS frame when the events end before its exposure does
+    pub truncated_exposure: TruncatedExposure,
+    pub input_histogram_bins: Option<usize>,
+    pub long_exposure: bool,
+    pub use_imu: bool,
+
+    /// How many independent APS frames to deblur in parallel. See
+    /// [`Reconstructor::set_parallel_frames`](crate::util::reconstructor::Reconstructor::set_parallel_frames).
+    pub parallel_frames: usize,
+    pub output_intensity_limit: Option<f64>,
+    pub log_space_intermediates: bool,
+    pub max_desync: Option<i64>,
+
+    /// Merge APS frames closer than this many microseconds into one exposure
+    pub min_frame_gap: Option<i64>,
+    pub supersamples: u32,
+    pub shutter_angle: f64,
+    pub output_crop_margin: u32,
+    pub min_events_for_optimize: usize,
+    pub latent_reference: LatentReference,
+    pub gradient_operator: GradientOperator,
+    pub edge_threshold: EdgeThreshold,
+    pub quality_check_threshold: Option<f64>,
+    pub max_intermediate_frames: Option<usize>,
+    pub fill_interval_gaps: bool,
+    pub input_temporal_average: usize,
+    pub display_views: Vec<DisplayView>,
+    pub trigger_window: Option<i64>,
+    pub reverse: bool,
+    pub latent_carry: LatentCarry,
+    pub density_blend: Option<f64>,
+    pub log_polar: bool,
+    pub reserve_event_queues: bool,
+    pub c_smoothing: Option<f64>,
+
+    /// Mask the pixels that average more than this many events per output interval
+    pub hot_pixel_threshold: Option<f64>,
+    pub retain_frame_events: bool,
+
+    /// Return `CV_32F` frames instead of `CV_64F`
+    pub single_precision_output: bool,
+
+    /// Decode and reconstruct serially, with no reader thread, so that the output for a given
+    /// file is identical across runs. Only for the "file" mode, and not with `simulate_latency`.
+    pub deterministic: bool,
+}
+
+impl Default for ReconstructorConfig {
+    fn default() -> Self {
+        ReconstructorConfig {
+            start_c: 0.3,
+            optimize_c: false,
+            optimize_c_frequency: 1,
+            optimize_controller: false,
+            lambda: DEFAULT_LAMBDA,
+            min_c: DEFAULT_MIN_C,
+            max_c: DEFAULT_MAX_C,
+            n_points: DEFAULT_N_POINTS,
+            show_display: false,
+            debug_display: false,
+            show_blurred_display: false,
+            output_fps: 100.0,
+            output_mode: OutputMode::FullSequence,
+            events_only: false,
+            target_latency: 200.0,
+            simulate_latency: false,
+            skip_priming: false,
+            require_initial_frame: true,
+            initial_frame_timeout: None,
+            end_of_stream_grace: None,
+            timebase_hz: DEFAULT_TIMEBASE_HZ,
+            super_resolution_factor: 1.0,
+            edge_window_intervals: None,
+            edge_decay_tau: None,
+            event_trim_intervals: None,
+            rolling_shutter_line_delay: None,
+            output_event_counts: false,
+            estimate_variance: false,
+            keep_log_latents: false,
+            fps_resolution_check: FpsResolutionCheck::Warn,
+            truncated_exposure: TruncatedExposure::BestEffort,
+            input_histogram_bins: None,
+            long_exposure: false,
+            use_imu: false,
+            parallel_frames: 1,
+            output_intensity_limit: None,
+            log_space_intermediates: false,
+            max_desync: None,
+            min_frame_gap: None,
+            supersamples: 1,
+            shutter_angle: 360.0,
+            output_crop_margin: 0,
+            min_events_for_optimize: 0,
+            latent_reference: LatentReference::Center,
+            gradient_operator: GradientOperator::Sobel,
+            edge_threshold: EdgeThreshold::Fixed,
+            quality_check_threshold: None,
+            max_intermediate_frames: None,
+            fill_interval_gaps: false,
+            input_temporal_average: 1,
+            display_views: vec![],
+            trigger_window: None,
+            reverse: false,
+            latent_carry: LatentCarry::Always,
+            density_blend: None,
+            log_polar: false,
+            reserve_event_queues: false,
+            c_smoothing: None,
+            hot_pixel_threshold: None,
+            retain_frame_events: true,
+            single_precision_output: false,
+            deterministic: false,
+        }
+    }
+}
+
+impl ReconstructorConfig {
+    /// Load a config from a file. Files with a `.json` extension are parsed as JSON, and anything
+    /// else as TOML. The config is validated.
+    pub fn load(path: &Path) -> Result<ReconstructorConfig, ReconstructorError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| ReconstructorError::ConfigError(e.to_string()))?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => ReconstructorConfig::from_json_str(&content),
+            _ => ReconstructorConfig::from_toml_str(&content),
+        }
+    }
+
+    /// Save the config to a file, as JSON if `path` has a `.json` extension, and as TOML
+    /// otherwise, so that it can be read back with [`ReconstructorConfig::load`]
+    pub fn save(&self, path: &Path) -> Result<(), ReconstructorError> {
+        let content = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => self.to_json_string()?,
+            _ => self.to_toml_string()?,
+        };
+        std::fs::write(path, content).map_err(|e| ReconstructorError::ConfigError(e.to_string()))
+    }
+
+    /// Serialize the config as TOML
+    pub fn to_toml_string(&self) -> Result<String, ReconstructorError> {
+        // Going through a `Value` puts the nested tables (e.g., a scene-change threshold) after
+        // the plain values, as TOML requires
+        toml::Value::try_from(self)
+            .and_then(|value| toml::to_string(&value))
+            .map_err(|e| ReconstructorError::ConfigError(e.to_string()))
+    }
+
+    /// Serialize the config as JSON
+    pub fn to_json_string(&self) -> Result<String, ReconstructorError> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| ReconstructorError::ConfigError(e.to_string()))
+    }
+
+    /// Parse and validate a TOML config
+    pub fn from_toml_str(content: &str) -> Result<ReconstructorConfig, ReconstructorError> {
+        let config: ReconstructorConfig =
+            toml::from_str(content).map_err(|e| ReconstructorError::ConfigError(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Parse and validate a JSON config
+    pub fn from_json_str(content: &str) -> Result<ReconstructorConfig, ReconstructorError> {
+        let config: ReconstructorConfig = serde_json::from_str(content)
+            .map_err(|e| ReconstructorError::ConfigError(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the parameters that don't depend on the input source. The rest (e.g., whether the
+    /// crop margin fits the sensor) are checked when the reconstructor is built.
+    pub fn validate(&self) -> Result<(), ReconstructorError> {
+        let check = |valid: bool, message: &str| match valid {
+            true => Ok(()),
+            false => Err(ReconstructorError::ConfigError(message.to_string())),
+        };
+        check(
+            self.start_c.is_finite() && self.start_c > 0.0,
+            "start_c must be positive",
+        )?;
+        check(
+            self.lambda.is_finite() && self.lambda >= 0.0,
+            "lambda must be non-negative",
+        )?;
+        check(
+            self.min_c.is_finite()
+                && self.max_c.is_finite()
+                && self.min_c > 0.0
+                && self.min_c < self.max_c,
+            "the c search range must satisfy 0 < min_c < max_c",
+        )?;
+        check(
+            (3..=MAX_C_SEARCH_POINTS).contains(&self.n_points),
+            &format!("n_points must be between 3 and {}", MAX_C_SEARCH_POINTS),
+        )?;
+        check(
+            self.optimize_c_frequency > 0,
+            "optimize_c_frequency must be at least 1",
+        )?;
+        check(
+            self.output_fps.is_finite() && self.output_fps > 0.0,
+            "output_fps must be positive",
+        )?;
+        check(
+            self.target_latency.is_finite() && self.target_latency > 0.0,
+            "target_latency must be positive",
+        )?;
+        check(
+            !(self.events_only && self.output_mode == OutputMode::SharpFrameOnly),
+            "events_only can't be used with the sharp-frame-only output mode",
+        )?;
+        check(
+            self.initial_frame_timeout
+                .map_or(true, |timeout| timeout.is_finite() && timeout > 0.0),
+            "initial_frame_timeout must be positive",
+        )?;
+        check(
+            self.end_of_stream_grace
+                .map_or(true, |grace| grace.is_finite() && grace >= 0.0),
+            "end_of_stream_grace must be non-negative",
+        )?;
+        check(
+            self.timebase_hz.is_finite() && self.timebase_hz > 0.0,
+            "timebase_hz must be positive",
+        )?;
+        check(
+            self.super_resolution_factor.is_finite() && self.super_resolution_factor >= 1.0,
+            "super_resolution_factor must be at least 1.0",
+        )?;
+        check(self.supersamples > 0, "supersamples must be at least 1")?;
+        check(
+            self.shutter_angle > 0.0 && self.shutter_angle <= 360.0,
+            "shutter_angle must be in (0, 360] degrees",
+        )?;
+        check(
+            self.max_intermediate_frames != Some(0),
+            "max_intermediate_frames must be at least 1",
+        )?;
+        check(
+            self.input_temporal_average > 0,
+            "input_temporal_average must be at least 1",
+        )?;
+        check(
+            self.density_blend
+                .map_or(true, |half_density| half_density > 0.0),
+            "density_blend must be positive",
+        )?;
+        check(
+            self.c_smoothing
+                .map_or(true, |alpha| alpha > 0.0 && alpha <= 1.0),
+            "c_smoothing must be in (0, 1]",
+        )?;
+        check(
+            self.min_frame_gap.map_or(true, |gap| gap > 0),
+            "min_frame_gap must be positive",
+        )?;
+        check(
+            self.edge_decay_tau
+                .map_or(true, |tau| tau.is_finite() && tau > 0.0),
+            "edge_decay_tau must be positive",
+        )?;
+        check(
+            self.hot_pixel_threshold
+                .map_or(true, |threshold| threshold.is_finite() && threshold > 0.0),
+            "hot_pixel_threshold must be positive",
+        )?;
+        check(
+            !(self.deterministic && self.simulate_latency),
+            "deterministic mode can't simulate latency",
+        )?;
+        check(
+            !(self.log_polar && self.rolling_shutter_line_delay.is_some()),
+            "log_polar is not compatible with rolling_shutter_line_delay",
+        )?;
+        check(
+            !self.use_imu || self.latent_reference == LatentReference::MinimumMotion,
+            "use_imu requires the MinimumMotion latent_reference",
+        )?;
+        check(
+            self.parallel_frames > 0,
+            "parallel_frames must be at least 1",
+        )?;
+        check(
+            self.parallel_frames == 1
+                || (self.output_mode == OutputMode::SharpFrameOnly
+                    && self.latent_carry == LatentCarry::Never
+                    && (!self.optimize_c
+                        || (self.optimize_c_frequency == 1 && self.c_smoothing.is_none()))),
+            "parallel_frames requires independent frames: the SharpFrameOnly output_mode, the \
+             Never latent_carry, and c fixed or optimized every frame without smoothing",
+        )?;
+        check(
+            !(self.long_exposure && self.rolling_shutter_line_delay.is_some()),
+            "long_exposure is not compatible with rolling_shutter_line_delay",
+        )?;
+        check(
+            self.input_histogram_bins
+                .map_or(true, |bins| bins > 0 && bins <= 256),
+            "input_histogram_bins must be between 1 and 256",
+        )?;
+        if let LatentCarry::ResetOnSceneChange { threshold } = self.latent_carry {
+            check(
+                threshold.is_finite() && threshold >= 0.0,
+                "latent_carry threshold must be non-negative",
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configs_load_from_toml_and_json_with_defaults_for_missing_fields() {
+        let config = ReconstructorConfig::from_toml_str(
+            "start_c = 0.25\noutput_mode = \"SharpFrameOnly\"\ndisplay_views = [\"Latent\"]\n",
+        )
+        .unwrap();
+        assert_eq!(config.start_c, 0.25);
+        assert_eq!(config.output_mode, OutputMode::SharpFrameOnly);
+        assert_eq!(config.display_views, [DisplayView::Latent]);
+        assert_eq!(config.output_fps, ReconstructorConfig::default().output_fps);
+
+        let path = std::env::temp_dir().join("davis-edi-config.json");
+        std::fs::write(&path, r#"{ "output_fps": 250.0, "optimize_c": true }"#).unwrap();
+        let config = ReconstructorConfig::load(&path).unwrap();
+        assert_eq!(config.output_fps, 250.0);
+        assert!(config.optimize_c);
+        assert_eq!(config.start_c, ReconstructorConfig::default().start_c);
+    }
+
+    #[test]
+    fn malformed_and_invalid_configs_are_rejected() {
+        for content in [
+            "output_fps = \"fast\"",
+            "start_c = -0.1",
+            "min_c = 0.5\nmax_c = 0.2",
+        ] {
+            assert!(matches!(
+                ReconstructorConfig::from_toml_str(content),
+                Err(ReconstructorError::ConfigError(_))
+            ));
+        }
+        let path = std::env::temp_dir().join("davis-edi-missing-config.toml");
+        assert!(matches!(
+            ReconstructorConfig::load(&path),
+            Err(ReconstructorError::ConfigError(_))
+        ));
+    }
+
+    #[test]
+    fn conflicting_and_non_positive_settings_are_rejected() {
+        for content in [
+            "target_latency = 0.0",
+            "target_latency = -1.0",
+            "target_latency = inf",
+            "output_fps = 0.0",
+            "events_only = true\noutput_mode = \"SharpFrameOnly\"",
+        ] {
+            assert!(matches!(
+                ReconstructorConfig::from_toml_str(content),
+                Err(ReconstructorError::ConfigError(_))
+            ));
+        }
+        assert!(ReconstructorConfig::from_toml_str("events_only = true").is_ok());
+    }
+
+    #[test]
+    fn saved_configs_load_back_unchanged() {
+        let config = crate::util::reconstructor::ReconstructorBuilder::new()
+            .config(ReconstructorConfig {
+                output_mode: OutputMode::SharpFrameOnly,
+                latent_carry: LatentCarry::ResetOnSceneChange { threshold: 0.3 },
+                display_views: vec![DisplayView::Latent, DisplayView::Edges],
+                initial_frame_timeout: Some(500.0),
+                ..Default::default()
+            })
+            .start_c(0.2)
+            .output_fps(250.0)
+            .to_config();
+        assert_eq!((config.start_c, config.output_fps), (0.2, 250.0));
+        let expected = config.to_json_string().unwrap();
+
+        for name in ["davis-edi-saved-config.toml", "davis-edi-saved-config.json"] {
+            let path = std::env::temp_dir().join(name);
+            config.save(&path).unwrap();
+            let loaded = ReconstructorConfig::load(&path).unwrap();
+            assert_eq!(loaded.to_json_string().unwrap(), expected);
+        }
+        let toml = config.to_toml_string().unwrap();
+        let loaded = ReconstructorConfig::from_toml_str(&toml).unwrap();
+        assert_eq!(loaded.latent_carry, config.latent_carry);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::util::reconstructor::ReconstructorError;
+use crate::util::reconstructor::ReconstructorError::ArgumentError;
+use opencv::core::{
+    divide2, flip, multiply, sum_elems, Mat, MatTraitConst, Point, BORDER_REFLECT, CV_64F,
+};
+use opencv::imgproc::filter_2d;
+
+/// Keeps the Richardson-Lucy ratio finite where the re-blurred estimate is black
+const EPSILON: f64 = 1e-12;
+
+/// Deblur an APS frame with classic Richardson-Lucy deconvolution, given a known blur kernel
+/// (point spread function). This ignores events entirely, and is meant as a baseline to compare
+/// EDI against on the same frames.
+///
+/// `blurred` should be a single-channel image with intensities in `[0, 1]`. The PSF is
+/// normalized to sum to 1. Returns a `CV_64F` image.
+pub fn deconvolve_classic(
+    blurred: &Mat,
+    psf: &Mat,
+    iterations: usize,
+) -> Result<Mat, ReconstructorError> {
+    if blurred.channels() != 1 || psf.channels() != 1 {
+        return Err(ArgumentError(
+            "Deconvolution requires single-channel images".to_string(),
+        ));
+    }
+    if psf.empty() {
+        return Err(ArgumentError("PSF is empty".to_string()));
+    }
+
+    let mut observed = Mat::default();
+    blurred.convert_to(&mut observed, CV_64F, 1.0, 0.0)?;
+
+    let psf_sum = sum_elems(psf)?[0];
+    if psf_sum <= 0.0 || !psf_sum.is_finite() {
+        return Err(ArgumentError("PSF must have a positive sum".to_string()));
+    }
+    let mut kernel = Mat::default();
+    psf.convert_to(&mut kernel, CV_64F, 1.0 / psf_sum, 0.0)?;
+
+    // Correlating with the flipped kernel is the adjoint of the blur
+    let mut kernel_flipped = Mat::default();
+    flip(&kernel, &mut kernel_flipped, -1)?;
+
+    let mut estimate = observed.clone();
+    for _ in 0..iterations {
+        let reblurred = convolve(&estimate, &kernel)?;
+        let mut reblurred_safe = Mat::default();
+        reblurred.convert_to(&mut reblurred_safe, CV_64F, 1.0, EPSILON)?;
+
+        let mut ratio = Mat::default();
+        divide2(&observed, &reblurred_safe, &mut ratio, 1.0, CV_64F)?;
+
+        let correction = convolve(&ratio, &kernel_flipped)?;
+        let mut next_estimate = Mat::default();
+        multiply(&estimate, &correction, &mut next_estimate, 1.0, CV_64F)?;
+        estimate = next_estimate;
+    }
+
+    Ok(estimate)
+}
+
+fn convolve(image: &Mat, kernel: &Mat) -> Result<Mat, opencv::Error> {
+    let mut out = Mat::default();
+    filter_2d(
+        image,
+        &mut out,
+        CV_64F,
+        kernel,
+        Point::new(-1, -1),
+        0.0,
+        BORDER_REFLECT,
+    )?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::reconstructor::{dmatrix_to_mat, mat_to_dmatrix};
+    use nalgebra::DMatrix;
+
+    #[test]
+    fn deconvolution_recovers_a_box_blurred_square() {
+        let mut sharp = DMatrix::<f64>::from_element(32, 32, 0.2);
+        sharp.view_mut((12, 12), (8, 8)).fill(0.8);
+        let psf = dmatrix_to_mat(&DMatrix::from_element(1, 5, 1.0)).unwrap();
+        let normalized_psf = dmatrix_to_mat(&DMatrix::from_element(1, 5, 0.2)).unwrap();
+        let blurred = convolve(&dmatrix_to_mat(&sharp).unwrap(), &normalized_psf).unwrap();
+
+        let deblurred = deconvolve_classic(&blurred, &psf, 200).unwrap();
+        let error = |image: &Mat| (mat_to_dmatrix(image).unwrap() - &sharp).abs().mean();
+        assert!(error(&deblurred) < 0.5 * error(&blurred));
+    }
+
+    #[test]
+    fn deconvolution_rejects_an_unusable_psf() {
+        let blurred = dmatrix_to_mat(&DMatrix::from_element(8, 8, 0.5)).unwrap();
+        assert!(matches!(
+            deconvolve_classic(&blurred, &Mat::default(), 10),
+            Err(ArgumentError(_))
+        ));
+        let zero_psf = dmatrix_to_mat(&DMatrix::zeros(3, 3)).unwrap();
+        assert!(matches!(
+            deconvolve_classic(&blurred, &zero_psf, 10),
+            Err(ArgumentError(_))
+        ));
+    }
+}
@@ -0,0 +1,168 @@
+use opencv::core::{hconcat, Mat, MatTraitConst, Size, Vector, CV_64F};
+#[cfg(feature = "display")]
+use opencv::highgui;
+use opencv::imgproc::{resize, INTER_LINEAR};
+use serde::{Deserialize, Serialize};
+
+/// The height, in pixels, that each view is scaled to in the composite window
+const DISPLAY_HEIGHT: i32 = 540;
+
+/// A debug view that can be included in the composite display window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisplayView {
+    /// The reconstructed latent image being returned
+    Latent,
+
+    /// The blurred APS frame that was deblurred
+    Blurred,
+
+    /// The edges of the most recent sharp latent image
+    Edges,
+}
+
+/// Renders all the enabled debug views side by side in a single window, so that there's one
+/// `imshow` per frame rather than one per view. This avoids the flicker of updating several
+/// windows separately.
+pub struct DisplayManager {
+    window_name: String,
+    views: Vec<DisplayView>,
+    blurred: Option<Mat>,
+    edges: Option<Mat>,
+}
+
+impl DisplayManager {
+    /// Create a display manager for the given views, shown left to right in the given order
+    pub fn new(window_name: &str, views: &[DisplayView]) -> DisplayManager {
+        DisplayManager {
+            window_name: window_name.to_string(),
+            views: views.to_vec(),
+            blurred: None,
+            edges: None,
+        }
+    }
+
+    pub fn views(&self) -> &[DisplayView] {
+        &self.views
+    }
+
+    pub(crate) fn wants(&self, view: DisplayView) -> bool {
+        self.views.contains(&view)
+    }
+
+    /// Update a view that isn't produced by the caller of [`DisplayManager::show`]
+    pub(crate) fn set_view(&mut self, view: DisplayView, mat: &Mat) -> Result<(), opencv::Error> {
+        let pane = Some(to_pane(mat)?);
+        match view {
+            DisplayView::Latent => {}
+            DisplayView::Blurred => self.blurred = pane,
+            DisplayView::Edges => self.edges = pane,
+        }
+        Ok(())
+    }
+
+    /// Build the composite image of all the enabled views. Views that haven't been produced yet
+    /// are left out.
+    pub fn compose(&self, latent: &Mat) -> Result<Mat, opencv::Error> {
+        let mut panes = Vector::<Mat>::new();
+        for view in &self.views {
+            match view {
+                DisplayView::Latent => panes.push(to_pane(latent)?),
+                DisplayView::Blurred => {
+                    if let Some(pane) = &self.blurred {
+                        panes.push(pane.clone());
+                    }
+                }
+                DisplayView::Edges => {
+                    if let Some(pane) = &self.edges {
+                        panes.push(pane.clone());
+                    }
+                }
+            }
+        }
+        let mut composite = Mat::default();
+        hconcat(&panes, &mut composite)?;
+        Ok(composite)
+    }
+
+    /// Show the composite in the window, and return the key pressed, or -1 if none. Without the
+    /// `display` feature, nothing is shown.
+    pub fn show(&self, latent: &Mat, wait: i32) -> Result<i32, opencv::Error> {
+        #[cfg(feature = "display")]
+        {
+            highgui::imshow(&self.window_name, &self.compose(latent)?)?;
+            highgui::wait_key(wait)
+        }
+        #[cfg(not(feature = "display"))]
+        {
+            let _ = (latent, wait);
+            Ok(-1)
+        }
+    }
+}
+
+/// Scale an image to the display height, as a `CV_64F` image in `[0, 1]`
+fn to_pane(mat: &Mat) -> Result<Mat, opencv::Error> {
+    let mut pane = Mat::default();
+    let scale = match mat.depth() {
+        opencv::core::CV_8U => 1.0 / 255.0,
+        _ => 1.0,
+    };
+    mat.convert_to(&mut pane, CV_64F, scale, 0.0)?;
+    if pane.rows() == DISPLAY_HEIGHT {
+        return Ok(pane);
+    }
+    let factor = pane.rows() as f32 / DISPLAY_HEIGHT as f32;
+    let mut resized = Mat::default();
+    resize(
+        &pane,
+        &mut resized,
+        Size {
+            width: (pane.cols() as f32 / factor) as i32,
+            height: DISPLAY_HEIGHT,
+        },
+        0.0,
+        0.0,
+        INTER_LINEAR,
+    )?;
+    Ok(resized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::reconstructor::{dmatrix_to_mat, mat_to_dmatrix};
+    use nalgebra::DMatrix;
+    use opencv::core::{Scalar, CV_8U};
+
+    #[test]
+    fn views_are_composed_side_by_side_at_the_display_height() {
+        let latent = dmatrix_to_mat(&DMatrix::<f64>::from_element(16, 16, 0.5)).unwrap();
+        let mut display = DisplayManager::new(
+            "test",
+            &[
+                DisplayView::Latent,
+                DisplayView::Blurred,
+                DisplayView::Edges,
+            ],
+        );
+
+        // The views that haven't been produced yet are left out
+        let composite = display.compose(&latent).unwrap();
+        assert_eq!((composite.rows(), composite.cols()), (540, 540));
+
+        let blurred = Mat::new_rows_cols_with_default(16, 32, CV_8U, Scalar::all(255.0)).unwrap();
+        display.set_view(DisplayView::Blurred, &blurred).unwrap();
+        let composite = mat_to_dmatrix(&display.compose(&latent).unwrap()).unwrap();
+        assert_eq!(composite.shape(), (540, 1620));
+        assert!((composite[(270, 100)] - 0.5).abs() < 1e-9);
+        assert!((composite[(270, 1000)] - 1.0).abs() < 1e-9);
+    }
+
+    #[cfg(not(feature = "display"))]
+    #[test]
+    fn headless_builds_show_nothing() {
+        let latent = dmatrix_to_mat(&DMatrix::<f64>::from_element(16, 16, 0.5)).unwrap();
+        let display = DisplayManager::new("test", &[DisplayView::Latent]);
+        assert_eq!(display.show(&latent, 1).unwrap(), -1);
+    }
+}
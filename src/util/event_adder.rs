@@ -1,16 +1,38 @@
+use crate::util::reconstructor::{
+    dmatrix_to_mat, mat_to_dmatrix, EdgeThreshold, EventRepr, GradientOperator, LatentReference,
+    OutputMode, ReconstructorError, DEFAULT_TIMEBASE_HZ,
+};
 use aedat::base::Packet;
 use aedat::events_generated::Event;
-use cv_convert::TryFromCv;
 use nalgebra::{DMatrix, Dyn, OMatrix};
 use opencv::core::{
     create_continuous, mean, no_array, normalize, sqrt, sum_elems, ElemMul, Mat, MatExprTraitConst,
-    BORDER_DEFAULT, CV_64F, NORM_MINMAX,
+    MatTraitConst, Point2f, BORDER_DEFAULT, CV_64F, CV_8U, NORM_MINMAX,
 };
 use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
 use std::mem;
 use std::ops::{AddAssign, DivAssign, MulAssign};
 use std::time::Instant;
 
+/// The number of time bins the exposure is split into to find the instant of least motion
+const MOTION_BINS: usize = 16;
+
+/// The weight of the newest APS frame in the running average event queue lengths
+const QUEUE_LENGTH_SMOOTHING: f64 = 0.2;
+
+/// How much more than the average queue length to reserve, so that most frames fit
+const QUEUE_HEADROOM: f64 = 1.25;
+
+/// How far, in log units, a term of the long-exposure integral may exceed the running scale of
+/// its pixel before the pixel is renormalized. `exp(300)` leaves plenty of headroom below the
+/// largest `f64` for the sum of many terms.
+const RENORMALIZE_LOG_LIMIT: f64 = 300.0;
+
+/// The most points the c-optimization can search, limited by the Fibonacci numbers below
+pub(crate) const MAX_C_SEARCH_POINTS: u32 = 17711;
+
 const FIB: [f64; 22] = [
     1.0, 1.0, 2.0, 3.0, 5.0, 8.0, 13.0, 21.0, 34.0, 55.0, 89.0, 144.0, 233.0, 377.0, 610.0, 987.0,
     1597.0, 2584.0, 4181.0, 6765.0, 10946.0, 17711.0,
@@ -19,14 +41,23 @@ const FIB: [f64; 22] = [
 pub struct DeblurReturn {
     pub(crate) last_interval_start_timestamp: i64,
     pub(crate) ret_vec: Vec<Mat>,
+
+    /// The start timestamp of the interval of each image in `ret_vec`
+    pub(crate) timestamps: Vec<i64>,
     pub(crate) found_c: f64,
 }
 
 #[allow(dead_code)]
+#[derive(Clone)]
 pub struct EventAdder {
     /// The time span of each reconstructed frame
     pub interval_t: i64,
 
+    /// The exact, possibly fractional, time span of each reconstructed frame. `interval_t` is
+    /// this rounded to the timebase. Interval boundaries are placed at multiples of this, so that
+    /// they don't drift from the target frame rate.
+    pub(crate) interval_length: f64,
+
     interval_count: u32,
 
     /// Events occurring before the current blurred image
@@ -37,8 +68,8 @@ pub struct EventAdder {
 
     /// Events occurring after the current blurred image
     pub(crate) event_after_queue: Vec<Event>,
-    height: i32,
-    width: i32,
+    pub(crate) height: i32,
+    pub(crate) width: i32,
     pub(crate) last_interval_start_timestamp: i64,
     pub(crate) latent_image: Mat,
     pub(crate) blur_info: Option<BlurInfo>,
@@ -46,8 +77,100 @@ pub struct EventAdder {
     pub(crate) current_c: f64,
     pub(crate) optimize_c: bool,
     pub(crate) optimize_c_frequency: u32,
-    pub(crate) deblur_only: bool,
+    pub(crate) output_mode: OutputMode,
     pub(crate) events_only: bool,
+
+    /// The weight of the total variation term in the c-optimization energy
+    pub(crate) lambda: f64,
+
+    /// The range that the c-optimization searches, and the resolution of the search. The
+    /// Fibonacci search narrows the range down to about `(max_c - min_c) / n_points`.
+    pub(crate) min_c: f64,
+    pub(crate) max_c: f64,
+    pub(crate) n_points: u32,
+
+    /// Upscaling factor of the reconstruction grid relative to the sensor resolution. Each event is
+    /// splatted onto the fine pixels interpolated from its sensor pixel.
+    pub(crate) super_resolution_factor: f64,
+
+    /// If set, the edge image used for the energy metric is accumulated over windows of this many
+    /// intervals, rather than over the whole exposure. The windowed edge images are combined by
+    /// keeping the strongest response at each pixel.
+    pub(crate) edge_window_intervals: Option<u32>,
+
+    /// The time constant, in ticks, of the decay of each event's weight in the edge image. `None`
+    /// decays over one second, as in the original paper.
+    pub(crate) edge_decay_tau: Option<f64>,
+
+    /// If set, events more than this many intervals before the exposure begins or after it ends
+    /// are discarded as they're sorted
+    pub(crate) event_trim_intervals: Option<i64>,
+
+    /// For rolling-shutter sensors, the delay between the exposure windows of consecutive rows
+    pub(crate) rolling_shutter_line_delay: Option<i64>,
+
+    /// If set, output latent images are clamped to `[0, output_intensity_limit]`
+    pub(crate) output_intensity_limit: Option<f64>,
+
+    /// Keep a log-space copy of the latent image, so that intermediate frames are generated with
+    /// a single exp of `L^tilde(f) + cE(t)`
+    pub(crate) log_space_intermediates: bool,
+
+    /// The minimum number of events during an exposure for c to be optimized on it
+    pub(crate) min_events_for_optimize: usize,
+
+    /// How the reference instant of each exposure is chosen
+    pub(crate) latent_reference: LatentReference,
+
+    /// The (x, y) offsets to apply to the coordinates of the events from each stream
+    pub(crate) stream_offsets: HashMap<u32, (i16, i16)>,
+
+    /// The number of event timestamp ticks per second
+    pub(crate) timebase_hz: f64,
+
+    /// The operator for the image gradients in the c-optimization energy
+    pub(crate) gradient_operator: GradientOperator,
+    pub(crate) edge_threshold: EdgeThreshold,
+    pub(crate) log_polar: bool,
+    pub(crate) reserve_event_queues: bool,
+    pub(crate) c_smoothing: Option<f64>,
+    pub(crate) fill_interval_gaps: bool,
+
+    /// Integrate each pixel relative to a running scale, so that the exponentials of long
+    /// exposures with many events don't overflow
+    pub(crate) long_exposure: bool,
+
+    /// Find the instant of least motion from the gyroscope rather than the event rate
+    pub(crate) use_imu: bool,
+
+    /// The timestamps and angular speeds (in degrees per second) of the IMU samples not yet
+    /// behind the current exposure
+    pub(crate) imu_angular_speed: Vec<(i64, f64)>,
+
+    /// The number of events dropped for arriving after their frames were reconstructed
+    pub(crate) late_events_dropped: u64,
+
+    /// The most recent optimum of c, before any smoothing
+    pub(crate) optimal_c: Option<f64>,
+
+    /// The c-optimization energy at the c applied to the latest APS frame
+    pub(crate) last_energy: Option<f64>,
+
+    /// Apply the next optimum of c directly, without smoothing it from the current c
+    skip_c_smoothing: bool,
+
+    /// Running averages of the during and after queue lengths per APS frame
+    queue_length_average: Option<(f64, f64)>,
+
+    /// The most intermediate frames to generate between two APS exposures
+    pub(crate) max_intermediate_frames: Option<usize>,
+
+    /// Mask the pixels that fire more than this many events per interval
+    pub(crate) hot_pixel_threshold: Option<f64>,
+
+    /// The number of pixels masked as hot for the current APS frame
+    pub(crate) hot_pixels_masked: usize,
+    log_latent_image: Option<DMatrix<f64>>,
 }
 
 unsafe impl Send for EventAdder {}
@@ -57,17 +180,22 @@ impl EventAdder {
     pub fn new(
         height: u16,
         width: u16,
-        output_frame_length: i64,
+        output_frame_length: f64,
         start_c: f64,
         optimize_c: bool,
         optimize_c_frequency: u32,
-        deblur_only: bool,
+        output_mode: OutputMode,
         events_only: bool,
+        lambda: f64,
+        min_c: f64,
+        max_c: f64,
+        n_points: u32,
     ) -> EventAdder {
         let mut continuous_mat = Mat::default();
         create_continuous(height as i32, width as i32, CV_64F, &mut continuous_mat).unwrap();
         EventAdder {
-            interval_t: output_frame_length,
+            interval_t: (output_frame_length.round() as i64).max(1),
+            interval_length: output_frame_length,
             interval_count: 0,
             event_before_queue: Vec::new(),
             event_during_queue: Vec::new(),
@@ -81,44 +209,291 @@ impl EventAdder {
             current_c: start_c,
             optimize_c,
             optimize_c_frequency,
-            deblur_only,
+            output_mode,
             events_only,
+            lambda,
+            min_c,
+            max_c,
+            n_points,
+            super_resolution_factor: 1.0,
+            edge_window_intervals: None,
+            edge_decay_tau: None,
+            event_trim_intervals: None,
+            rolling_shutter_line_delay: None,
+            output_intensity_limit: None,
+            log_space_intermediates: false,
+            min_events_for_optimize: 0,
+            latent_reference: LatentReference::Center,
+            stream_offsets: HashMap::new(),
+            timebase_hz: DEFAULT_TIMEBASE_HZ,
+            gradient_operator: GradientOperator::Sobel,
+            edge_threshold: EdgeThreshold::Fixed,
+            log_polar: false,
+            reserve_event_queues: false,
+            c_smoothing: None,
+            fill_interval_gaps: false,
+            long_exposure: false,
+            use_imu: false,
+            imu_angular_speed: Vec::new(),
+            late_events_dropped: 0,
+            optimal_c: None,
+            last_energy: None,
+            skip_c_smoothing: false,
+            queue_length_average: None,
+            max_intermediate_frames: None,
+            hot_pixel_threshold: None,
+            hot_pixels_masked: 0,
+            log_latent_image: None,
         }
     }
 
-    pub fn sort_events(&mut self, packet: Packet) {
-        let blur_info = match &self.blur_info {
-            None => {
-                panic!("blur_info not initialized")
-            }
-            Some(a) => a,
+    /// Set the exact time span of each reconstructed frame
+    pub(crate) fn set_interval_length(&mut self, interval_length: f64) {
+        self.interval_length = interval_length.max(1.0);
+        self.interval_t = (interval_length.round() as i64).max(1);
+    }
+
+    /// The time constant of the edge image's decay, in ticks
+    fn edge_decay_tau(&self) -> f64 {
+        self.edge_decay_tau.unwrap_or(self.timebase_hz)
+    }
+
+    /// The start of the `index`th interval after `origin_t`. This is computed from the exact
+    /// interval length rather than by accumulating `interval_t`, so rounding errors don't build up.
+    pub(crate) fn interval_timestamp(&self, origin_t: i64, index: usize) -> i64 {
+        origin_t + (index as f64 * self.interval_length).round() as i64
+    }
+
+    /// Change the reconstruction grid to `factor` times the sensor resolution. The current latent
+    /// image is discarded, since it no longer matches the grid.
+    pub(crate) fn set_super_resolution_factor(
+        &mut self,
+        sensor_height: u16,
+        sensor_width: u16,
+        factor: f64,
+    ) {
+        self.super_resolution_factor = factor;
+        self.height = (sensor_height as f64 * factor).round() as i32;
+        self.width = (sensor_width as f64 * factor).round() as i32;
+        let mut continuous_mat = Mat::default();
+        create_continuous(self.height, self.width, CV_64F, &mut continuous_mat).unwrap();
+        self.latent_image = continuous_mat;
+        self.log_latent_image = None;
+    }
+
+    /// Set the latent image that the next intermediate frames are generated from. A
+    /// non-continuous image (e.g., an ROI of a larger one) is copied, since its rows aren't
+    /// contiguous in memory for the conversion to a matrix.
+    pub(crate) fn set_latent_image(&mut self, latent_image: Mat) {
+        let latent_image = match latent_image.is_continuous() {
+            true => latent_image,
+            false => latent_image.try_clone().unwrap(),
         };
+        self.log_latent_image = match self.log_space_intermediates {
+            true => mat_to_dmatrix(&latent_image)
+                .ok()
+                .map(|latent| latent.map(f64::ln)),
+            false => None,
+        };
+        self.latent_image = latent_image;
+    }
+
+    /// Get the reconstruction grid pixels that an event contributes to, with their bilinear
+    /// weights. Entries with a weight of zero should be ignored.
+    fn splat(&self, event: &Event) -> Splat {
+        if self.log_polar {
+            return Splat::Points(self.splat_log_polar(event));
+        }
+        if self.super_resolution_factor == 1.0 {
+            return Splat::Points([
+                (event.y() as usize, event.x() as usize, 1.0),
+                (0, 0, 0.0),
+                (0, 0, 0.0),
+                (0, 0, 0.0),
+            ]);
+        }
+
+        // Every fine pixel interpolated from the event's sensor pixel sees the event, weighted as
+        // in the upscaled blurred image. Splatting only the pixel center would spread a sensor
+        // pixel's events thinly over the fine pixels around it, and leave the rest of it without
+        // any, so that the fine pixels would be barely deblurred.
+        let rows = self.splat_axis(event.y(), self.height);
+        let cols = self.splat_axis(event.x(), self.width);
+        Splat::Footprint(
+            rows.iter()
+                .flat_map(|&(y, wy)| cols.iter().map(move |&(x, wx)| (y, x, wy * wx)))
+                .collect(),
+        )
+    }
+
+    /// Get the fine grid pixels along one axis, of length `fine_len`, that are bilinearly
+    /// interpolated from sensor pixel `coordinate`, with its weight in each. As in the upscaling
+    /// of the blurred image, the fine pixels past the outer sensor pixel centers take the outer
+    /// sensor pixels.
+    fn splat_axis(&self, coordinate: i16, fine_len: i32) -> Vec<(usize, f64)> {
+        let sensor_len = (fine_len as f64 / self.super_resolution_factor).round();
+        let scale = sensor_len / fine_len as f64;
+        let coordinate = coordinate as f64;
+        let first = ((coordinate - 0.5) / scale - 0.5).ceil().max(0.0) as usize;
+        let last = (((coordinate + 1.5) / scale - 0.5).floor() as usize).min(fine_len as usize - 1);
+        (first..=last)
+            .filter_map(|fine| {
+                let source = ((fine as f64 + 0.5) * scale - 0.5).clamp(0.0, sensor_len - 1.0);
+                let weight = 1.0 - (source - coordinate).abs();
+                (weight > 0.0).then_some((fine, weight))
+            })
+            .collect()
+    }
+
+    /// Like [`EventAdder::splat`], but onto the log-polar grid of [`warp_log_polar`]. Rows are
+    /// angles, so they wrap around.
+    fn splat_log_polar(&self, event: &Event) -> [(usize, usize, f64); 4] {
+        let factor = self.super_resolution_factor;
+        let fy = (event.y() as f64 + 0.5) * factor - 0.5;
+        let fx = (event.x() as f64 + 0.5) * factor - 0.5;
+        let (height, width) = (self.height as usize, self.width as usize);
+        let (phi, rho) = log_polar_coordinates(fy, fx, height, width);
+        let (y0, x0) = (phi.floor() as usize % height, rho.floor() as usize);
+        let y1 = (y0 + 1) % height;
+        let x1 = (x0 + 1).min(width - 1);
+        let (ay, ax) = (phi - phi.floor(), rho - rho.floor());
+        [
+            (y0, x0, (1.0 - ay) * (1.0 - ax)),
+            (y0, x1, (1.0 - ay) * ax),
+            (y1, x0, ay * (1.0 - ax)),
+            (y1, x1, ay * ax),
+        ]
+    }
+
+    /// Sort the events of a packet into the before/during/after queues, relative to the exposure
+    /// time of the current blurred image. Returns the number of events sorted, which leaves out
+    /// any that a stream's offset moves off the sensor. A valid packet with no elements is a no-op
+    /// that returns 0; it does not signal the end of the stream. A malformed packet (e.g.,
+    /// truncated in transit) returns a [`ReconstructorError::PacketError`] without sorting any of
+    /// its events.
+    pub fn sort_events(&mut self, packet: Packet) -> Result<usize, ReconstructorError> {
+        if self.blur_info.is_none() {
+            return Err(ReconstructorError::NoFrameBuffered);
+        }
         let event_packet =
-            match aedat::events_generated::size_prefixed_root_as_event_packet(&packet.buffer) {
-                Ok(result) => result,
-                Err(_) => {
-                    panic!("the packet does not have a size prefix");
-                }
-            };
+            aedat::events_generated::size_prefixed_root_as_event_packet(&packet.buffer)
+                .map_err(|e| ReconstructorError::PacketError(e.to_string()))?;
 
         let event_arr = match event_packet.elements() {
-            None => return,
+            None => return Ok(0),
             Some(events) => events,
         };
 
-        for event in event_arr {
-            match event.t() {
-                a if a < blur_info.exposure_begin_t => {
-                    self.event_before_queue.push(*event);
+        match self.stream_offsets.get(&packet.stream_id).copied() {
+            None => {
+                for event in event_arr {
+                    self.sort_event(*event);
                 }
-                a if a > blur_info.exposure_end_t => {
-                    self.event_after_queue.push(*event);
+                Ok(event_arr.len())
+            }
+            Some((offset_x, offset_y)) => {
+                let sensor_height = (self.height as f64 / self.super_resolution_factor).round();
+                let sensor_width = (self.width as f64 / self.super_resolution_factor).round();
+                let mut sorted = 0;
+                for event in event_arr {
+                    // Widened so that an offset near the sensor edge can't overflow
+                    let x = event.x() as i32 + offset_x as i32;
+                    let y = event.y() as i32 + offset_y as i32;
+                    if x < 0 || y < 0 || x as f64 >= sensor_width || y as f64 >= sensor_height {
+                        continue;
+                    }
+                    self.sort_event(Event::new(event.t(), x as i16, y as i16, event.on()));
+                    sorted += 1;
                 }
-                _ => {
-                    self.event_during_queue.push(*event);
+                Ok(sorted)
+            }
+        }
+    }
+
+    /// Sort a single event into the before/during/after queues
+    fn sort_event(&mut self, event: Event) {
+        let blur_info = match &self.blur_info {
+            None => {
+                panic!("blur_info not initialized")
+            }
+            Some(a) => a,
+        };
+
+        // An event older than the last latent image (e.g., out of order on a TCP stream) belongs
+        // to frames that were already reconstructed. Integrating it now would count it twice.
+        if event.t() < self.last_interval_start_timestamp {
+            self.late_events_dropped += 1;
+            return;
+        }
+
+        if let Some(k) = self.event_trim_intervals {
+            if event.t() < blur_info.exposure_begin_t - k * self.interval_t
+                || event.t() > blur_info.exposure_end_t + k * self.interval_t
+            {
+                return;
+            }
+        }
+
+        match event.t() {
+            a if a < blur_info.exposure_begin_t => {
+                self.event_before_queue.push(event);
+            }
+            a if a > blur_info.exposure_end_t => {
+                self.event_after_queue.push(event);
+            }
+            _ => {
+                self.event_during_queue.push(event);
+            }
+        }
+    }
+
+    /// Add events that were already accumulated into a voxel grid by an upstream tool. `grid`
+    /// holds one signed event count matrix (`CV_64F`, at the sensor resolution) per interval,
+    /// with interval `i` starting `i` interval lengths after `interval_start_t`, as for the
+    /// reconstructed frames. The counts are expanded back into events at the middle of their
+    /// intervals, and sorted like any other events. Returns the number of events added.
+    pub fn add_voxel_grid(
+        &mut self,
+        grid: &[Mat],
+        interval_start_t: i64,
+    ) -> Result<usize, ReconstructorError> {
+        if self.blur_info.is_none() {
+            return Err(ReconstructorError::NoFrameBuffered);
+        }
+        if grid.is_empty() {
+            return Err(ReconstructorError::ArgumentError(
+                "Voxel grid has no intervals".to_string(),
+            ));
+        }
+        let sensor_height = (self.height as f64 / self.super_resolution_factor).round() as i32;
+        let sensor_width = (self.width as f64 / self.super_resolution_factor).round() as i32;
+        for mat in grid {
+            if mat.rows() != sensor_height || mat.cols() != sensor_width || mat.typ() != CV_64F {
+                return Err(ReconstructorError::ArgumentError(format!(
+                    "Voxel grid intervals must be {}x{} CV_64F matrices",
+                    sensor_height, sensor_width
+                )));
+            }
+        }
+
+        let mut count = 0;
+        for (interval_idx, mat) in grid.iter().enumerate() {
+            // The same boundaries as the reconstructed intervals, so the fractional part of the
+            // interval length doesn't make the grid drift from the events it was binned from
+            let begin_t = self.interval_timestamp(interval_start_t, interval_idx);
+            let end_t = self.interval_timestamp(interval_start_t, interval_idx + 1);
+            let t = begin_t + (end_t - begin_t) / 2;
+            for y in 0..sensor_height {
+                for x in 0..sensor_width {
+                    let events = mat.at_2d::<f64>(y, x)?.round() as i64;
+                    for _ in 0..events.abs() {
+                        self.sort_event(Event::new(t, x as i16, y as i16, events > 0));
+                    }
+                    count += events.unsigned_abs() as usize;
                 }
             }
         }
+        Ok(count)
     }
 
     pub fn reset_event_queues(&mut self) {
@@ -128,9 +503,94 @@ impl EventAdder {
         // self.event_before_queue.clear();
     }
 
+    /// Drop the events of hot pixels: those that fire more than `hot_pixel_threshold` events per
+    /// interval, on average over the span of the queued events. A real edge passes over a pixel
+    /// in a few intervals at most, but a hot pixel fires constantly and dominates the deblurring.
+    pub(crate) fn mask_hot_pixels(&mut self) {
+        self.hot_pixels_masked = 0;
+        let threshold = match self.hot_pixel_threshold {
+            None => return,
+            Some(threshold) => threshold,
+        };
+        let mut counts: HashMap<(i16, i16), usize> = HashMap::new();
+        let (mut first_t, mut last_t) = (i64::MAX, i64::MIN);
+        for event in self
+            .event_before_queue
+            .iter()
+            .chain(self.event_during_queue.iter())
+            .chain(self.event_after_queue.iter())
+        {
+            *counts.entry((event.x(), event.y())).or_insert(0) += 1;
+            first_t = first_t.min(event.t());
+            last_t = last_t.max(event.t());
+        }
+        if counts.is_empty() {
+            return;
+        }
+        let intervals = ((last_t - first_t) as f64 / self.interval_length).max(1.0);
+        let hot: HashSet<(i16, i16)> = counts
+            .into_iter()
+            .filter(|(_, count)| *count as f64 / intervals > threshold)
+            .map(|(pixel, _)| pixel)
+            .collect();
+        if hot.is_empty() {
+            return;
+        }
+        for queue in [
+            &mut self.event_before_queue,
+            &mut self.event_during_queue,
+            &mut self.event_after_queue,
+        ] {
+            queue.retain(|event| !hot.contains(&(event.x(), event.y())));
+        }
+        self.hot_pixels_masked = hot.len();
+    }
+
+    /// Fold the lengths of the current during and after queues into their running averages. Call
+    /// this before the queues are handed off.
+    pub(crate) fn record_queue_lengths(&mut self) {
+        let lengths = (
+            self.event_during_queue.len() as f64,
+            self.event_after_queue.len() as f64,
+        );
+        self.queue_length_average = Some(match self.queue_length_average {
+            None => lengths,
+            Some((during, after)) => (
+                during + QUEUE_LENGTH_SMOOTHING * (lengths.0 - during),
+                after + QUEUE_LENGTH_SMOOTHING * (lengths.1 - after),
+            ),
+        });
+    }
+
+    /// Set c, and forget the c-optimization history, so that the next APS frame is optimized
+    /// over the full search range and its optimum is applied without smoothing
+    pub(crate) fn reset_c(&mut self, c: f64) {
+        self.current_c = c;
+        self.optimal_c = None;
+        self.skip_c_smoothing = true;
+        // The count is incremented before it's checked, so this optimizes on the next frame
+        self.interval_count = self.optimize_c_frequency.saturating_sub(1);
+    }
+
+    /// Reserve capacity in the (empty) during and after queues for a typical APS frame, so that
+    /// high event rates don't repeatedly reallocate them while sorting
+    pub(crate) fn reserve_queue_capacity(&mut self) {
+        if !self.reserve_event_queues {
+            return;
+        }
+        if let Some((during, after)) = self.queue_length_average {
+            self.event_during_queue
+                .reserve((during * QUEUE_HEADROOM) as usize);
+            self.event_after_queue
+                .reserve((after * QUEUE_HEADROOM) as usize);
+        }
+    }
+
     fn get_intermediate_image(&self, c: f64, timestamp_start: i64) -> Mat {
+        // With no events since the last latent image (e.g., across a gap in the event stream), the
+        // intermediate image is just the latent image
         if self.event_before_queue.is_empty() {
-            panic!("Empty before queue");
+            return self.latent_image.clone();
         }
 
         // TODO: Need to avoid having to traverse the whole queue each time?
@@ -147,18 +607,22 @@ impl EventAdder {
 
         let mut event_counter = DMatrix::<f64>::zeros(self.height as usize, self.width as usize);
 
-        let (mut y, mut x);
         for event in &self.event_before_queue[start_index..end_index] {
-            y = event.y() as usize;
-            x = event.x() as usize;
-            event_counter[(y, x)] += event_polarity_float(event);
+            for &(y, x, w) in self.splat(event).iter().filter(|s| s.2 > 0.0) {
+                event_counter[(y, x)] += w * event_polarity_float(event);
+            }
         }
 
         // L^tilde(t) = L^tilde(f) + cE(t)
         // Take the exp of L^tilde(t) to get L(t), the final latent image
+        if let Some(log_latent_image) = &self.log_latent_image {
+            event_counter.mul_assign(c);
+            event_counter.add_assign(log_latent_image);
+            return dmatrix_to_mat(&event_counter.map(f64::exp)).unwrap();
+        }
         event_counter.mul_assign(c);
         event_counter = event_counter.map(|x: f64| x.exp());
-        let event_counter_mat = Mat::try_from_cv(event_counter).unwrap();
+        let event_counter_mat = dmatrix_to_mat(&event_counter).unwrap();
 
         self.latent_image
             .clone()
@@ -170,13 +634,14 @@ impl EventAdder {
     }
 
     // TODO: Vary the rate of optimizing c based on the reconstruction frame rate (vs the target fps)
-    pub(crate) fn optimize_c(&self, timestamp_start: i64) -> f64 {
+    /// Search for the c that minimizes the energy. Returns the c and its energy.
+    pub(crate) fn optimize_c(&self, timestamp_start: i64) -> (f64, f64) {
         // Fibonacci search
-        let mut a: f64 = 0.1;
-        let mut b: f64 = 0.5;
-        let n_points = 15.0;
+        let mut a: f64 = self.min_c;
+        let mut b: f64 = self.max_c;
+        let n_points = self.n_points as f64;
         let mut fib_index = 3;
-        while FIB[fib_index] < n_points {
+        while fib_index + 1 < FIB.len() && FIB[fib_index] < n_points {
             fib_index += 1;
         }
 
@@ -201,13 +666,154 @@ impl EventAdder {
             }
         }
         if fx1 < fx2 {
-            x1
+            (x1, fx1)
         } else {
-            x2
+            (x2, fx2)
+        }
+    }
+
+    /// Count the events at each pixel during the current exposure, regardless of polarity
+    pub(crate) fn event_count_map(&self) -> DMatrix<f64> {
+        let mut counts = DMatrix::<f64>::zeros(self.height as usize, self.width as usize);
+        for event in &self.event_during_queue {
+            for &(y, x, w) in self.splat(event).iter().filter(|s| s.2 > 0.0) {
+                counts[(y, x)] += w;
+            }
+        }
+        counts
+    }
+
+    /// The median time between consecutive events at the same pixel during the current exposure,
+    /// or `None` if no pixel saw more than one event
+    pub(crate) fn median_inter_event_time(&self) -> Option<f64> {
+        let mut last_t: HashMap<(i16, i16), i64> = HashMap::new();
+        let mut gaps = Vec::new();
+        for event in &self.event_during_queue {
+            if let Some(t) = last_t.insert((event.x(), event.y()), event.t()) {
+                gaps.push((event.t() - t).abs());
+            }
+        }
+        if gaps.is_empty() {
+            return None;
+        }
+        gaps.sort_unstable();
+        Some(gaps[gaps.len() / 2] as f64)
+    }
+
+    /// Build an event representation from all the events currently buffered in the before,
+    /// during, and after queues
+    pub fn event_representation(&self, kind: EventRepr) -> Mat {
+        self.event_representation_of(
+            self.event_before_queue
+                .iter()
+                .chain(self.event_during_queue.iter())
+                .chain(self.event_after_queue.iter()),
+            kind,
+        )
+    }
+
+    pub(crate) fn event_representation_of<'a>(
+        &self,
+        events: impl Iterator<Item = &'a Event>,
+        kind: EventRepr,
+    ) -> Mat {
+        let mut surface = DMatrix::<f64>::zeros(self.height as usize, self.width as usize);
+        let mut latest_t = None;
+        for event in events {
+            for &(y, x, w) in self.splat(event).iter().filter(|s| s.2 > 0.0) {
+                match kind {
+                    EventRepr::EventCount => surface[(y, x)] += w,
+                    EventRepr::TimeSurface | EventRepr::SurfaceOfActiveEvents { .. } => {
+                        surface[(y, x)] = surface[(y, x)].max(event.t() as f64)
+                    }
+                }
+            }
+            latest_t = Some(max(event.t(), latest_t.unwrap_or(event.t())));
         }
+
+        if let (EventRepr::SurfaceOfActiveEvents { tau }, Some(latest_t)) = (kind, latest_t) {
+            for px in surface.iter_mut().filter(|px| **px != 0.0) {
+                *px = (-(latest_t as f64 - *px) / tau).exp();
+            }
+        }
+
+        dmatrix_to_mat(&surface).unwrap()
+    }
+
+    /// The timestamp that c is optimized relative to. In the full-sequence mode, this is the
+    /// start of one of the intervals of the current exposure, chosen by the latent reference
+    /// strategy. In the sharp-frame-only mode, it's also the timestamp of the sharp frame.
+    pub(crate) fn reference_timestamp(&self) -> Option<i64> {
+        let blur_info = self.blur_info.as_ref()?;
+        let begin_t = blur_info.exposure_begin_t;
+        let intervals = match self.output_mode {
+            OutputMode::SharpFrameOnly => 1,
+            OutputMode::FullSequence => {
+                ((blur_info.exposure_end_t - begin_t) as f64 / self.interval_length) as usize + 1
+            }
+        };
+        Some(match (self.latent_reference, self.output_mode) {
+            (LatentReference::Center, _) => self.interval_timestamp(begin_t, intervals / 2),
+            (LatentReference::MinimumMotion, OutputMode::SharpFrameOnly) => {
+                self.minimum_motion_timestamp(blur_info)
+            }
+            (LatentReference::MinimumMotion, OutputMode::FullSequence) => {
+                let index = ((self.minimum_motion_timestamp(blur_info) - begin_t) as f64
+                    / self.interval_length)
+                    .round() as usize;
+                self.interval_timestamp(begin_t, index.min(intervals - 1))
+            }
+        })
+    }
+
+    /// Find the instant of least motion during the exposure, as the middle of the time bin with
+    /// the fewest events, or with the IMU, the lowest mean angular speed
+    fn minimum_motion_timestamp(&self, blur_info: &BlurInfo) -> i64 {
+        let (begin_t, end_t) = (blur_info.exposure_begin_t, blur_info.exposure_end_t);
+        if end_t <= begin_t {
+            return begin_t;
+        }
+        let bin_length = (end_t - begin_t) as f64 / MOTION_BINS as f64;
+
+        // Events also fire for scene motion and flicker, but the gyroscope only measures the
+        // camera's rotation, which is what blurs the whole frame
+        if self.use_imu {
+            let mut speed_sums = [(0.0, 0_usize); MOTION_BINS];
+            for &(t, speed) in &self.imu_angular_speed {
+                if t < begin_t || t > end_t {
+                    continue;
+                }
+                let bin = ((t - begin_t) as f64 / bin_length) as usize;
+                let (sum, count) = &mut speed_sums[bin.min(MOTION_BINS - 1)];
+                *sum += speed;
+                *count += 1;
+            }
+            // Bins without samples are skipped. Without any, fall back to the events.
+            if let Some((min_bin, _)) = speed_sums
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, count))| *count > 0)
+                .map(|(bin, (sum, count))| (bin, sum / *count as f64))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+            {
+                return begin_t + ((min_bin as f64 + 0.5) * bin_length) as i64;
+            }
+        }
+
+        let mut bins = [0_usize; MOTION_BINS];
+        for event in &self.event_during_queue {
+            let bin = ((event.t() - begin_t) as f64 / bin_length) as usize;
+            bins[bin.min(MOTION_BINS - 1)] += 1;
+        }
+        let (min_bin, _) = bins
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, count)| **count)
+            .unwrap();
+        begin_t + ((min_bin as f64 + 0.5) * bin_length) as i64
     }
 
-    fn get_phi(&self, c: f64, timestamp_start: i64) -> f64 {
+    pub(crate) fn get_phi(&self, c: f64, timestamp_start: i64) -> f64 {
         let (latent_image, mt_image) = self.get_latent_and_edge(c, timestamp_start);
         // _show_display_force("mt_image", &mt_image, 1, true);
 
@@ -232,37 +838,43 @@ impl EventAdder {
         // dbg!(phi_tv);
 
         // dbg!(phi);
-        0.15 * phi_tv - phi_edge
-    }
-
-    fn get_gradient_and_edges(&self, image: Mat) -> (Mat, Mat) {
-        let mut image_sobel_x = Mat::default();
-        sobel(
-            &image,
-            &mut image_sobel_x,
-            CV_64F,
-            1,
-            0,
-            3,
-            1.0,
-            0.0,
-            BORDER_DEFAULT,
-        )
-        .expect("Sobel error");
-
-        let mut image_sobel_y = Mat::default();
-        sobel(
-            &image,
-            &mut image_sobel_y,
-            CV_64F,
-            0,
-            1,
-            3,
-            1.0,
-            0.0,
-            BORDER_DEFAULT,
-        )
-        .expect("Sobel error");
+        self.lambda * phi_tv - phi_edge
+    }
+
+    /// Get the first derivative of the image in x (`dx = 1, dy = 0`) or y (`dx = 0, dy = 1`)
+    fn get_derivative(&self, image: &Mat, dx: i32, dy: i32) -> Mat {
+        let mut derivative = Mat::default();
+        match self.gradient_operator {
+            GradientOperator::Sobel => sobel(
+                image,
+                &mut derivative,
+                CV_64F,
+                dx,
+                dy,
+                3,
+                1.0,
+                0.0,
+                BORDER_DEFAULT,
+            )
+            .expect("Sobel error"),
+            GradientOperator::Scharr => scharr(
+                image,
+                &mut derivative,
+                CV_64F,
+                dx,
+                dy,
+                1.0,
+                0.0,
+                BORDER_DEFAULT,
+            )
+            .expect("Scharr error"),
+        }
+        derivative
+    }
+
+    pub(crate) fn get_gradient_and_edges(&self, image: Mat) -> (Mat, Mat) {
+        let image_sobel_x = self.get_derivative(&image, 1, 0);
+        let image_sobel_y = self.get_derivative(&image, 0, 1);
         let tmp = (image_sobel_x.clone().elem_mul(&image_sobel_x)
             + image_sobel_y.clone().elem_mul(&image_sobel_y))
         .into_result()
@@ -285,9 +897,15 @@ impl EventAdder {
         )
         .expect("Norm error");
 
+        let threshold_val = match self.edge_threshold {
+            EdgeThreshold::Fixed => {
+                let mean_val = mean(&grad_norm, &no_array()).unwrap().0[0];
+                mean_val + (1.0 - mean_val) / 3.0
+            }
+            EdgeThreshold::Otsu => otsu_threshold(&grad_norm).unwrap(),
+        };
+
         let mut thresholded = Mat::default();
-        let mut threshold_val = mean(&grad_norm, &no_array()).unwrap().0[0];
-        threshold_val += (1.0 - threshold_val) / 3.0;
         threshold(
             &grad_norm,
             &mut thresholded,
@@ -301,13 +919,16 @@ impl EventAdder {
     }
 
     fn get_latent_and_edge(&self, c: f64, timestamp_start: i64) -> (Mat, Mat) {
+        if let Some(line_delay) = self.rolling_shutter_line_delay {
+            return self.get_latent_and_edge_rolling_shutter(c, timestamp_start, line_delay);
+        }
+
         let mut latent_image = DMatrix::<f64>::zeros(self.height as usize, self.width as usize);
         let mut edge_image = latent_image.clone();
         if self.event_during_queue.is_empty() {
             return (
-                Mat::try_from_cv(self.blur_info.as_ref().unwrap().blurred_image.clone_owned())
-                    .unwrap(),
-                Mat::try_from_cv(edge_image).unwrap(),
+                dmatrix_to_mat(&self.blur_info.as_ref().unwrap().blurred_image).unwrap(),
+                dmatrix_to_mat(&edge_image).unwrap(),
             );
         }
 
@@ -327,33 +948,76 @@ impl EventAdder {
         let mut timestamps = latent_image.clone();
         timestamps.add_scalar_mut(timestamp_start as f64);
 
-        let (mut y, mut x);
+        // The edge image for the current window
+        let mut window_edge = edge_image.clone();
+        let exposure_begin_t = self.blur_info.as_ref().unwrap().exposure_begin_t;
+        let window_index = |t: i64| match self.edge_window_intervals {
+            Some(n) if n > 0 => (t - exposure_begin_t) / (n as i64 * self.interval_t).max(1),
+            _ => 0,
+        };
+        let mut current_window = window_index(self.event_during_queue[start_index].t());
+
+        // In the long-exposure mode, the true integral at each pixel is
+        // `latent_image * exp(log_scale)`
+        let mut log_scale = latent_image.clone();
+        let mut integrate = |latent_image: &mut DMatrix<f64>,
+                             y: usize,
+                             x: usize,
+                             log_term: f64,
+                             dt: f64| {
+            match self.long_exposure {
+                true => accumulate_renormalized(
+                    &mut latent_image[(y, x)],
+                    &mut log_scale[(y, x)],
+                    log_term,
+                    dt,
+                ),
+                false => latent_image[(y, x)] += log_term.exp() * dt,
+            }
+        };
+
         // Events occurring AFTER this timestamp
         for event in &self.event_during_queue[start_index..] {
-            y = event.y() as usize;
-            x = event.x() as usize;
-            latent_image[(y, x)] +=
-                (c * event_counter[(y, x)]).exp() * (event.t() as f64 - timestamps[(y, x)]);
-
-            event_counter[(y, x)] += event_polarity_float(event);
-
-            if self.optimize_c {
-                edge_image[(y, x)] += event_polarity_float(event)
-                    // * c
-                    * (-(event.t() as f64 - timestamps[(y, x)])/1000000.0).exp();
-                // We assume a timescale of microseconds as in the original paper;
-                // i.e., 1e6 microseconds per second
+            for &(y, x, w) in self.splat(event).iter().filter(|s| s.2 > 0.0) {
+                integrate(
+                    &mut latent_image,
+                    y,
+                    x,
+                    c * event_counter[(y, x)],
+                    event.t() as f64 - timestamps[(y, x)],
+                );
+
+                event_counter[(y, x)] += w * event_polarity_float(event);
+
+                if self.optimize_c {
+                    if window_index(event.t()) != current_window {
+                        merge_edge_window(&mut edge_image, &mut window_edge);
+                        current_window = window_index(event.t());
+                    }
+                    window_edge[(y, x)] += w * event_polarity_float(event)
+                        // * c
+                        * (-(event.t() as f64 - timestamps[(y, x)])/self.edge_decay_tau()).exp();
+                    // By default, the decay is over seconds, as in the original paper
+                }
+                timestamps[(y, x)] = event.t() as f64;
             }
-            timestamps[(y, x)] = event.t() as f64;
         }
 
-        event_counter.mul_assign(c);
-        event_counter = event_counter.map(|x: f64| x.exp());
-
         timestamps.mul_assign(-1.0);
         timestamps.add_scalar_mut(self.event_during_queue.last().unwrap().t() as f64);
-        event_counter.component_mul_assign(&timestamps);
-        latent_image.add_assign(&event_counter);
+        if self.long_exposure {
+            for y in 0..event_counter.nrows() {
+                for x in 0..event_counter.ncols() {
+                    let log_term = c * event_counter[(y, x)];
+                    integrate(&mut latent_image, y, x, log_term, timestamps[(y, x)]);
+                }
+            }
+        } else {
+            event_counter.mul_assign(c);
+            event_counter = event_counter.map(|x: f64| x.exp());
+            event_counter.component_mul_assign(&timestamps);
+            latent_image.add_assign(&event_counter);
+        }
 
         // Events occurring BEFORE this timestamp
 
@@ -362,172 +1026,468 @@ impl EventAdder {
         event_counter = DMatrix::<f64>::zeros(self.height as usize, self.width as usize);
 
         for event in &self.event_during_queue[..start_index] {
-            y = event.y() as usize;
-            x = event.x() as usize;
-            latent_image[(y, x)] +=
-                (c * event_counter[(y, x)]).exp() * (timestamps[(y, x)] - event.t() as f64);
-
-            event_counter[(y, x)] -= event_polarity_float(event);
+            for &(y, x, w) in self.splat(event).iter().filter(|s| s.2 > 0.0) {
+                integrate(
+                    &mut latent_image,
+                    y,
+                    x,
+                    c * event_counter[(y, x)],
+                    timestamps[(y, x)] - event.t() as f64,
+                );
+
+                event_counter[(y, x)] -= w * event_polarity_float(event);
+
+                if self.optimize_c {
+                    if window_index(event.t()) != current_window {
+                        merge_edge_window(&mut edge_image, &mut window_edge);
+                        current_window = window_index(event.t());
+                    }
+                    window_edge[(y, x)] -= w * event_polarity_float(event)
+                        // * c
+                        * (-(timestamps[(y, x)] - event.t() as f64)/self.edge_decay_tau()).exp();
+                }
 
-            if self.optimize_c {
-                edge_image[(y, x)] -= event_polarity_float(event)
-                    // * c
-                    * (-(timestamps[(y, x)] - event.t() as f64)/1000000.0).exp();
+                timestamps[(y, x)] = event.t() as f64;
             }
-
-            timestamps[(y, x)] = event.t() as f64;
         }
 
-        event_counter.mul_assign(c);
-        event_counter = event_counter.map(|x: f64| x.exp());
+        merge_edge_window(&mut edge_image, &mut window_edge);
 
         timestamps.add_scalar_mut(-self.event_during_queue[0].t() as f64);
-        event_counter.component_mul_assign(&timestamps);
-        latent_image.add_assign(&event_counter);
+        if self.long_exposure {
+            for y in 0..event_counter.nrows() {
+                for x in 0..event_counter.ncols() {
+                    let log_term = c * event_counter[(y, x)];
+                    integrate(&mut latent_image, y, x, log_term, timestamps[(y, x)]);
+                }
+            }
+        } else {
+            event_counter.mul_assign(c);
+            event_counter = event_counter.map(|x: f64| x.exp());
+            event_counter.component_mul_assign(&timestamps);
+            latent_image.add_assign(&event_counter);
+        }
 
-        latent_image.div_assign(
-            self.event_during_queue.last().unwrap().t() as f64
-                - self.event_during_queue[0].t() as f64,
-        );
+        let exposure_t = self.event_during_queue.last().unwrap().t() as f64
+            - self.event_during_queue[0].t() as f64;
         let blurred_image = &self.blur_info.as_ref().unwrap().blurred_image;
-        latent_image = blurred_image.component_div(&latent_image);
-
-        // The last gathered latent image might get completely black pixels if there are some
-        // negative polarity events right near the end of the exposure time. This looks unreasonably
-        // bad, so I'm fixing it manually here. It's likely due to some DVS pixels firing slightly
-        // sooner than others for the same kind of intensity change.
-        for (latent_px, blurred_px) in latent_image.iter_mut().zip(blurred_image.iter()) {
-            if *latent_px > 1.1 {
-                *latent_px = 1.1;
-            } else if *latent_px <= 0.0 {
-                if *blurred_px == 1.0 {
-                    *latent_px = 1.0;
-                } else {
-                    *latent_px = 0.0;
-                }
+        if self.long_exposure {
+            // Divide in log space, so that the scale never has to be exponentiated on its own
+            for ((latent_px, scale), blurred_px) in latent_image
+                .iter_mut()
+                .zip(log_scale.iter())
+                .zip(blurred_image.iter())
+            {
+                *latent_px = (blurred_px.ln() + exposure_t.ln() - latent_px.ln() - scale).exp();
             }
+        } else {
+            latent_image.div_assign(exposure_t);
+            latent_image = blurred_image.component_div(&latent_image);
         }
+        fix_latent_pixels(&mut latent_image, blurred_image);
 
         // show_display_force("latent", &latent_image, 1, false);
         (
-            Mat::try_from_cv(latent_image).unwrap(),
-            Mat::try_from_cv(edge_image).unwrap(),
+            dmatrix_to_mat(&latent_image).unwrap(),
+            dmatrix_to_mat(&edge_image).unwrap(),
+        )
+    }
+
+    /// Rolling-shutter variant of [`EventAdder::get_latent_and_edge`]. Row `y` of the blurred
+    /// image is exposed from `exposure_begin_t + y * line_delay` to
+    /// `exposure_end_t + y * line_delay`, so each row is integrated over its own window. Every row
+    /// is reconstructed at the same instant, `timestamp_start`, which removes the rolling-shutter
+    /// skew.
+    fn get_latent_and_edge_rolling_shutter(
+        &self,
+        c: f64,
+        timestamp_start: i64,
+        line_delay: i64,
+    ) -> (Mat, Mat) {
+        let blur_info = self.blur_info.as_ref().unwrap();
+        let (height, width) = (self.height as usize, self.width as usize);
+        let row_delay = line_delay as f64 / self.super_resolution_factor;
+        let row_window = |y: usize| {
+            let offset = y as f64 * row_delay;
+            (
+                blur_info.exposure_begin_t as f64 + offset,
+                blur_info.exposure_end_t as f64 + offset,
+            )
+        };
+        // The length of time that the part of the window [a, b] overlapping row y's exposure
+        let overlap = |y: usize, a: f64, b: f64| {
+            let (begin, end) = row_window(y);
+            (b.min(end) - a.max(begin)).max(0.0)
+        };
+
+        // The last rows are exposed after the frame's nominal exposure end, so we need the
+        // events that were sorted into the 'after' queue, too
+        let last_t = row_window(height - 1).1.max(row_window(0).1) as i64;
+        let events: Vec<&Event> = self
+            .event_during_queue
+            .iter()
+            .chain(self.event_after_queue.iter())
+            .filter(|event| event.t() <= last_t)
+            .collect();
+
+        let mut edge_image = DMatrix::<f64>::zeros(height, width);
+        if events.is_empty() {
+            return (
+                dmatrix_to_mat(&blur_info.blurred_image).unwrap(),
+                dmatrix_to_mat(&edge_image).unwrap(),
+            );
+        }
+        let split_index = events.partition_point(|event| event.t() <= timestamp_start);
+
+        let mut latent_image = DMatrix::<f64>::zeros(height, width);
+        let mut event_counter = DMatrix::<f64>::zeros(height, width);
+        let mut timestamps = DMatrix::<f64>::from_element(height, width, timestamp_start as f64);
+
+        // Events occurring AFTER this timestamp
+        for event in &events[split_index..] {
+            let t = event.t() as f64;
+            for &(y, x, w) in self.splat(event).iter().filter(|s| s.2 > 0.0) {
+                latent_image[(y, x)] +=
+                    (c * event_counter[(y, x)]).exp() * overlap(y, timestamps[(y, x)], t);
+                event_counter[(y, x)] += w * event_polarity_float(event);
+                if self.optimize_c {
+                    edge_image[(y, x)] += w
+                        * event_polarity_float(event)
+                        * (-(t - timestamps[(y, x)]) / self.edge_decay_tau()).exp();
+                }
+                timestamps[(y, x)] = t;
+            }
+        }
+        for y in 0..height {
+            for x in 0..width {
+                latent_image[(y, x)] += (c * event_counter[(y, x)]).exp()
+                    * overlap(y, timestamps[(y, x)], row_window(y).1);
+            }
+        }
+
+        // Events occurring BEFORE this timestamp, walking backwards in time
+        event_counter.fill(0.0);
+        timestamps.fill(timestamp_start as f64);
+        for event in events[..split_index].iter().rev() {
+            let t = event.t() as f64;
+            for &(y, x, w) in self.splat(event).iter().filter(|s| s.2 > 0.0) {
+                latent_image[(y, x)] +=
+                    (c * event_counter[(y, x)]).exp() * overlap(y, t, timestamps[(y, x)]);
+                event_counter[(y, x)] -= w * event_polarity_float(event);
+                if self.optimize_c {
+                    edge_image[(y, x)] -= w
+                        * event_polarity_float(event)
+                        * (-(timestamps[(y, x)] - t) / self.edge_decay_tau()).exp();
+                }
+                timestamps[(y, x)] = t;
+            }
+        }
+        for y in 0..height {
+            for x in 0..width {
+                latent_image[(y, x)] += (c * event_counter[(y, x)]).exp()
+                    * overlap(y, row_window(y).0, timestamps[(y, x)]);
+            }
+        }
+
+        latent_image
+            .div_assign(((blur_info.exposure_end_t - blur_info.exposure_begin_t) as f64).max(1.0));
+        latent_image = blur_info.blurred_image.component_div(&latent_image);
+        fix_latent_pixels(&mut latent_image, &blur_info.blurred_image);
+
+        (
+            dmatrix_to_mat(&latent_image).unwrap(),
+            dmatrix_to_mat(&edge_image).unwrap(),
         )
     }
 }
 
-pub fn deblur_image(event_adder: &mut EventAdder) -> Option<DeblurReturn> {
-    if let Some(blur_info) = &event_adder.blur_info {
-        event_adder.interval_count += 1;
-        // The beginning time for interval 0. Probably before the blurred image exposure beginning time
-        // TODO: Why? Events outside the exposure time aren't included then...
-        // let interval_beginning_start =
-        //     ((blur_info.exposure_begin_t) / event_adder.interval_t) * event_adder.interval_t;
-        let interval_beginning_start = blur_info.exposure_begin_t;
-        let interval_end_start =
+/// Deblur the buffered APS frame into its latent images. Fails with
+/// [`ReconstructorError::NoFrameBuffered`] if there's no frame, or with
+/// [`ReconstructorError::DegenerateExposure`] if its exposure ends before it begins. Any other
+/// exposure, even an instantaneous one, has at least one interval to deblur.
+pub fn deblur_image(event_adder: &mut EventAdder) -> Result<DeblurReturn, ReconstructorError> {
+    event_adder.mask_hot_pixels();
+    let blur_info = match &event_adder.blur_info {
+        None => return Err(ReconstructorError::NoFrameBuffered),
+        Some(a) => a,
+    };
+
+    // Naturally handle the case where the input image is relatively sharp
+    if blur_info.exposure_begin_t > blur_info.exposure_end_t {
+        return Err(ReconstructorError::DegenerateExposure {
+            begin_t: blur_info.exposure_begin_t,
+            end_t: blur_info.exposure_end_t,
+        });
+    }
+
+    event_adder.interval_count += 1;
+    // The beginning time for interval 0. Probably before the blurred image exposure beginning time
+    // TODO: Why? Events outside the exposure time aren't included then...
+    // let interval_beginning_start =
+    //     ((blur_info.exposure_begin_t) / event_adder.interval_t) * event_adder.interval_t;
+    let interval_beginning_start = blur_info.exposure_begin_t;
+    let interval_end_start =
             // ((blur_info.exposure_end_t) / event_adder.interval_t) * event_adder.interval_t;
             blur_info.exposure_end_t;
-        let mut ret_vec = Vec::with_capacity(
-            ((interval_end_start - interval_beginning_start) / event_adder.interval_t) as usize * 2,
-        );
-
-        ////////////////////////
-        // First, do the queue'd up events preceding this image. These intermediate images
-        // are based on the most recent deblurred latent image
-        if event_adder.last_interval_start_timestamp > 0 {
-            let mut intermediate_interval_start_timestamps = vec![(
-                event_adder.last_interval_start_timestamp + event_adder.interval_t,
-                Mat::default(),
-            )];
-            let mut current_ts =
-                intermediate_interval_start_timestamps[0].0 + event_adder.interval_t;
-            loop {
-                if current_ts < interval_beginning_start && !event_adder.deblur_only {
-                    intermediate_interval_start_timestamps.push((current_ts, Mat::default()));
-                    current_ts += event_adder.interval_t;
-                } else {
+    let mut ret_vec = Vec::with_capacity(
+        ((interval_end_start - interval_beginning_start) / event_adder.interval_t) as usize * 2,
+    );
+    let mut timestamps = Vec::with_capacity(ret_vec.capacity());
+
+    ////////////////////////
+    // First, do the queue'd up events preceding this image. These intermediate images
+    // are based on the most recent deblurred latent image
+    if event_adder.last_interval_start_timestamp > 0 {
+        let origin_t = event_adder.last_interval_start_timestamp;
+        let mut intermediate_interval_start_timestamps =
+            vec![(event_adder.interval_timestamp(origin_t, 1), Mat::default())];
+        let mut index = 2;
+        loop {
+            let current_ts = event_adder.interval_timestamp(origin_t, index);
+            if current_ts < interval_beginning_start
+                && event_adder.output_mode == OutputMode::FullSequence
+            {
+                if event_adder.max_intermediate_frames.map_or(false, |max| {
+                    intermediate_interval_start_timestamps.len() >= max
+                }) {
+                    // Don't materialize a huge number of frames across a long gap between APS
+                    // frames
+                    eprintln!(
+                        "\nSkipping {} intermediate frames across a gap in the APS frames",
+                        ((interval_beginning_start - current_ts) as f64
+                            / event_adder.interval_length)
+                            .ceil()
+                    );
                     break;
                 }
+                intermediate_interval_start_timestamps.push((current_ts, Mat::default()));
+                index += 1;
+            } else {
+                break;
             }
+        }
 
-            if !event_adder.deblur_only && !event_adder.event_before_queue.is_empty() {
-                intermediate_interval_start_timestamps
-                    .par_iter_mut()
-                    .for_each(|(timestamp_start, mat)| {
-                        // let c = optimize_c()
-                        *mat = event_adder
-                            .get_intermediate_image(event_adder.current_c, *timestamp_start);
-                    });
+        // Without events, the intermediate images are all the last latent image, so they're only
+        // worth producing to keep one frame per interval
+        if event_adder.output_mode == OutputMode::FullSequence
+            && (!event_adder.event_before_queue.is_empty() || event_adder.fill_interval_gaps)
+        {
+            intermediate_interval_start_timestamps
+                .par_iter_mut()
+                .for_each(|(timestamp_start, mat)| {
+                    // let c = optimize_c()
+                    *mat =
+                        event_adder.get_intermediate_image(event_adder.current_c, *timestamp_start);
+                });
+
+            for elem in intermediate_interval_start_timestamps {
+                timestamps.push(elem.0);
+                ret_vec.push(elem.1)
+            }
+        }
+    }
 
-                for elem in intermediate_interval_start_timestamps {
-                    ret_vec.push(elem.1)
+    ////////////////////////
+
+    // Make a vec of these timestamps so we can iterate them concurrently
+    let reference_t = event_adder.reference_timestamp().unwrap();
+    let mut interval_start_timestamps = match event_adder.output_mode {
+        OutputMode::SharpFrameOnly => vec![(reference_t, Mat::default(), 0.0)],
+        OutputMode::FullSequence => vec![(interval_beginning_start, Mat::default(), 0.0)],
+    };
+    let mut index = 1;
+    loop {
+        let current_ts = event_adder.interval_timestamp(interval_beginning_start, index);
+        if current_ts <= interval_end_start && event_adder.output_mode == OutputMode::FullSequence {
+            interval_start_timestamps.push((current_ts, Mat::default(), event_adder.current_c));
+            index += 1;
+        } else {
+            break;
+        }
+    }
+
+    // Optimize c just once, relative to the reference interval of the APS frame (by default, the
+    // temporal middle)
+    // With too few events, the energy is dominated by noise, so just keep the previous c
+    let mut optimum = None;
+    let new_c = match event_adder.optimize_c
+        && event_adder.interval_count % event_adder.optimize_c_frequency == 0
+        && event_adder.event_during_queue.len() >= event_adder.min_events_for_optimize
+    {
+        true => {
+            event_adder.interval_count = 0;
+            let (optimal_c, energy) = event_adder.optimize_c(reference_t);
+            event_adder.optimal_c = Some(optimal_c);
+            optimum = Some((optimal_c, energy));
+            match (
+                event_adder.c_smoothing,
+                mem::take(&mut event_adder.skip_c_smoothing),
+            ) {
+                (Some(alpha), false) => {
+                    event_adder.current_c + alpha * (optimal_c - event_adder.current_c)
                 }
+                _ => optimal_c,
             }
         }
+        false => event_adder.current_c,
+    };
+
+    // Report the energy at the applied c, which needs another evaluation unless it's the optimum.
+    // The edge image is only accumulated while optimizing c.
+    event_adder.last_energy = Some(match optimum {
+        Some((optimal_c, energy)) if optimal_c == new_c => energy,
+        _ => {
+            let optimize_c = mem::replace(&mut event_adder.optimize_c, true);
+            let energy = event_adder.get_phi(new_c, reference_t);
+            event_adder.optimize_c = optimize_c;
+            energy
+        }
+    });
+
+    interval_start_timestamps
+        .par_iter_mut()
+        .for_each(|(timestamp_start, mat, found_c)| {
+            // let c = match event_adder.optimize_c {
+            //     true => {event_adder.optimize_c(*timestamp_start)},
+            //     false => {event_adder.current_c}
+            // };
+            *found_c = new_c;
+            *mat = event_adder
+                .get_latent_and_edge(*found_c, *timestamp_start)
+                .0
+        });
+
+    let mut last_interval = interval_start_timestamps.last().unwrap().clone();
+    if event_adder.output_mode == OutputMode::SharpFrameOnly {
+        assert_eq!(interval_start_timestamps.len(), 1);
+        last_interval.0 = event_adder.interval_timestamp(last_interval.0, 1);
+    }
 
-        ////////////////////////
+    for elem in interval_start_timestamps {
+        timestamps.push(elem.0);
+        ret_vec.push(elem.1)
+    }
 
-        // Naturally handle the case where the input image is relatively sharp
-        if interval_beginning_start > blur_info.exposure_end_t {
-            println!("Bad interval");
-            return None;
+    if let Some(limit) = event_adder.output_intensity_limit {
+        for mat in ret_vec.iter_mut() {
+            *mat = clamp_intensity(mat, limit)?;
         }
+    }
 
-        // Make a vec of these timestamps so we can iterate them concurrently
-        let mut interval_start_timestamps = vec![(interval_beginning_start, Mat::default(), 0.0)];
-        let mut current_ts = interval_beginning_start + event_adder.interval_t;
-        loop {
-            if current_ts <= interval_end_start && !event_adder.deblur_only {
-                interval_start_timestamps.push((current_ts, Mat::default(), event_adder.current_c));
-                current_ts += event_adder.interval_t;
+    Ok(DeblurReturn {
+        last_interval_start_timestamp: last_interval.0,
+        ret_vec,
+        timestamps,
+        found_c: last_interval.2,
+    })
+}
+
+/// Clamp the intensities of an image to `[0, limit]`
+fn clamp_intensity(mat: &Mat, limit: f64) -> Result<Mat, opencv::Error> {
+    let mut truncated = Mat::default();
+    threshold(mat, &mut truncated, limit, limit, THRESH_TRUNC)?;
+    let mut clamped = Mat::default();
+    threshold(&truncated, &mut clamped, 0.0, 0.0, THRESH_TOZERO)?;
+    Ok(clamped)
+}
+
+/// The center and maximum radius of the log-polar transform of a `height` x `width` grid
+fn log_polar_geometry(height: usize, width: usize) -> ((f64, f64), f64) {
+    let (cy, cx) = (height as f64 / 2.0, width as f64 / 2.0);
+    ((cy, cx), cy.hypot(cx))
+}
+
+/// Map a point on the Cartesian grid to its (row, column) = (angle, log-radius) position on the
+/// log-polar grid of the same size, matching [`warp_log_polar`]. The column is clamped to the
+/// grid.
+fn log_polar_coordinates(y: f64, x: f64, height: usize, width: usize) -> (f64, f64) {
+    let ((cy, cx), max_radius) = log_polar_geometry(height, width);
+    let (dy, dx) = (y - cy, x - cx);
+    let k_log = width as f64 / max_radius.ln();
+    let rho = (k_log * dy.hypot(dx).max(1.0).ln()).clamp(0.0, (width - 1) as f64);
+    let angle = dy.atan2(dx).rem_euclid(2.0 * std::f64::consts::PI);
+    let phi = angle * height as f64 / (2.0 * std::f64::consts::PI);
+    (phi.min(height as f64 - f64::EPSILON), rho)
+}
+
+/// Warp an image between Cartesian and log-polar coordinates about its center. Rotation about
+/// the center becomes a vertical translation in log-polar space, so for rotational motion the
+/// events at each log-polar pixel come from a single edge rather than being smeared across
+/// several.
+pub(crate) fn warp_log_polar(mat: &Mat, inverse: bool) -> Result<Mat, opencv::Error> {
+    let ((cy, cx), max_radius) = log_polar_geometry(mat.rows() as usize, mat.cols() as usize);
+    let mut flags = INTER_LINEAR + WARP_FILL_OUTLIERS + WARP_POLAR_LOG;
+    if inverse {
+        flags += WARP_INVERSE_MAP;
+    }
+    let mut warped = Mat::default();
+    warp_polar(
+        mat,
+        &mut warped,
+        mat.size()?,
+        Point2f::new(cx as f32, cy as f32),
+        max_radius,
+        flags,
+    )?;
+    Ok(warped)
+}
+
+/// Otsu's threshold for a normalized `[0, 1]` image. OpenCV only supports Otsu on 8-bit images,
+/// so the threshold is found on a quantized copy and scaled back.
+fn otsu_threshold(grad_norm: &Mat) -> Result<f64, opencv::Error> {
+    let mut quantized = Mat::default();
+    grad_norm.convert_to(&mut quantized, CV_8U, 255.0, 0.0)?;
+    let mut unused = Mat::default();
+    let threshold_val = threshold(
+        &quantized,
+        &mut unused,
+        0.0,
+        255.0,
+        THRESH_BINARY | THRESH_OTSU,
+    )?;
+    Ok(threshold_val / 255.0)
+}
+
+/// The last gathered latent image might get completely black pixels if there are some negative
+/// polarity events right near the end of the exposure time. This looks unreasonably bad, so I'm
+/// fixing it manually here. It's likely due to some DVS pixels firing slightly sooner than others
+/// for the same kind of intensity change.
+fn fix_latent_pixels(latent_image: &mut DMatrix<f64>, blurred_image: &DMatrix<f64>) {
+    for (latent_px, blurred_px) in latent_image.iter_mut().zip(blurred_image.iter()) {
+        if *latent_px > 1.1 {
+            *latent_px = 1.1;
+        } else if *latent_px <= 0.0 {
+            if *blurred_px == 1.0 {
+                *latent_px = 1.0;
             } else {
-                break;
+                *latent_px = 0.0;
             }
         }
+    }
+}
 
-        // Optimize c just once, relative to the temporal middle of the APS frame
-        let new_c = match event_adder.optimize_c
-            && event_adder.interval_count % event_adder.optimize_c_frequency == 0
-        {
-            true => {
-                event_adder.interval_count = 0;
-                event_adder
-                    .optimize_c(interval_start_timestamps[interval_start_timestamps.len() / 2].0)
-            }
-            false => event_adder.current_c,
-        };
+/// Add `exp(log_term) * weight` to a pixel's integral, which is stored as `accumulator` relative
+/// to `exp(scale)`. When the term is far larger than the scale, or the integral is still empty,
+/// the pixel is rescaled to the term first, so that neither overflows.
+fn accumulate_renormalized(accumulator: &mut f64, scale: &mut f64, log_term: f64, weight: f64) {
+    if *accumulator == 0.0 {
+        *scale = log_term;
+    } else if log_term - *scale > RENORMALIZE_LOG_LIMIT {
+        *accumulator *= (*scale - log_term).exp();
+        *scale = log_term;
+    }
+    *accumulator += (log_term - *scale).exp() * weight;
+}
 
-        interval_start_timestamps
-            .par_iter_mut()
-            .for_each(|(timestamp_start, mat, found_c)| {
-                // let c = match event_adder.optimize_c {
-                //     true => {event_adder.optimize_c(*timestamp_start)},
-                //     false => {event_adder.current_c}
-                // };
-                *found_c = new_c;
-                *mat = event_adder
-                    .get_latent_and_edge(*found_c, *timestamp_start)
-                    .0
-            });
-
-        let mut last_interval = interval_start_timestamps.last().unwrap().clone();
-        if event_adder.deblur_only {
-            assert_eq!(interval_start_timestamps.len(), 1);
-            last_interval.0 += event_adder.interval_t;
-        }
-
-        for elem in interval_start_timestamps {
-            ret_vec.push(elem.1)
-        }
-
-        Some(DeblurReturn {
-            last_interval_start_timestamp: last_interval.0,
-            ret_vec,
-            found_c: last_interval.2,
-        })
-    } else {
-        None
+/// Fold a windowed edge image into the combined edge image, keeping the strongest response at
+/// each pixel, then clear the window for reuse
+fn merge_edge_window(edge_image: &mut DMatrix<f64>, window_edge: &mut DMatrix<f64>) {
+    for (edge_px, window_px) in edge_image.iter_mut().zip(window_edge.iter()) {
+        if window_px.abs() > edge_px.abs() {
+            *edge_px = *window_px;
+        }
     }
+    window_edge.fill(0.0);
 }
 
 fn event_polarity_float(event: &Event) -> f64 {
@@ -537,8 +1497,30 @@ fn event_polarity_float(event: &Event) -> f64 {
     }
 }
 
-use opencv::imgproc::{sobel, threshold, THRESH_BINARY};
+use opencv::imgproc::{
+    scharr, sobel, threshold, warp_polar, INTER_LINEAR, THRESH_BINARY, THRESH_OTSU, THRESH_TOZERO,
+    THRESH_TRUNC, WARP_FILL_OUTLIERS, WARP_INVERSE_MAP, WARP_POLAR_LOG,
+};
+
+/// The reconstruction grid pixels that an event contributes to. See [`EventAdder::splat`].
+enum Splat {
+    /// Up to four pixels, at the sensor resolution or on the log-polar grid
+    Points([(usize, usize, f64); 4]),
+
+    /// The fine pixels around the event's sensor pixel, with super-resolution
+    Footprint(Vec<(usize, usize, f64)>),
+}
+
+impl Splat {
+    fn iter(&self) -> std::slice::Iter<'_, (usize, usize, f64)> {
+        match self {
+            Splat::Points(points) => points.iter(),
+            Splat::Footprint(footprint) => footprint.iter(),
+        }
+    }
+}
 
+#[derive(Clone)]
 pub struct BlurInfo {
     pub blurred_image: OMatrix<f64, Dyn, Dyn>,
     pub exposure_begin_t: i64,
@@ -562,4 +1544,897 @@ impl BlurInfo {
             packet_timestamp,
         }
     }
+
+    /// Combine this frame with a later one into a single exposure spanning both. A blurred image
+    /// is the mean intensity over its exposure, so the images are weighted by their exposure
+    /// times. The gap between the exposures is treated as exposed.
+    pub(crate) fn merge(self, later: BlurInfo) -> BlurInfo {
+        let weight = (self.exposure_end_t - self.exposure_begin_t).max(1) as f64;
+        let later_weight = (later.exposure_end_t - later.exposure_begin_t).max(1) as f64;
+        BlurInfo {
+            blurred_image: (self.blurred_image * weight + later.blurred_image * later_weight)
+                / (weight + later_weight),
+            exposure_begin_t: self.exposure_begin_t,
+            exposure_end_t: later.exposure_end_t,
+            init: self.init,
+            // The merged frame can't be deblurred before the later frame arrives
+            packet_timestamp: later.packet_timestamp,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::reconstructor::DEFAULT_LAMBDA;
+    use crate::util::test_support::{self, Scene, EXPOSURES};
+
+    /// Deblur the buffered frame, and get its latent images
+    fn latents(event_adder: &mut EventAdder) -> Vec<DMatrix<f64>> {
+        deblur_image(event_adder)
+            .unwrap()
+            .ret_vec
+            .iter()
+            .map(|latent| mat_to_dmatrix(latent).unwrap())
+            .collect()
+    }
+
+    /// A bright bar, one column wide, moving right across a dark scene, so that each column fires
+    /// ON events as the bar arrives and OFF events as it leaves
+    fn moving_bar(exposures: &[(i64, i64)]) -> Scene {
+        let events = (0..test_support::WIDTH as i16)
+            .flat_map(|x| {
+                let arrival_t = test_support::edge_crossing_t(x);
+                let departure_t = arrival_t + test_support::EDGE_COLUMN_TICKS;
+                let mut events = test_support::column_events(x, arrival_t, true);
+                events.extend(test_support::column_events(x, departure_t, false));
+                events
+            })
+            .chain(test_support::exposure_anchors(exposures))
+            .collect();
+        Scene::new(
+            DMatrix::from_element(
+                test_support::HEIGHT as usize,
+                test_support::WIDTH as usize,
+                test_support::DARK,
+            ),
+            events,
+        )
+    }
+
+    #[test]
+    fn empty_event_packets_sort_nothing() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        let empty = test_support::event_packet(&[]);
+        assert_eq!(event_adder.sort_events(empty).unwrap(), 0);
+        let elementless = test_support::elementless_event_packet();
+        assert_eq!(event_adder.sort_events(elementless).unwrap(), 0);
+        assert!(event_adder.event_before_queue.is_empty());
+        assert!(event_adder.event_during_queue.is_empty());
+        assert!(event_adder.event_after_queue.is_empty());
+
+        let packet = test_support::event_packet(&scene.events);
+        assert_eq!(event_adder.sort_events(packet).unwrap(), scene.events.len());
+    }
+
+    #[test]
+    fn windowed_edges_keep_motion_that_cancels_over_the_exposure() {
+        let exposure = (2000, 17000);
+        let scene = moving_bar(&[exposure]);
+        let edges = |window_intervals| {
+            let mut event_adder = test_support::event_adder(&scene, exposure.0, exposure.1);
+            event_adder.optimize_c = true;
+            event_adder.edge_window_intervals = window_intervals;
+            let packet = test_support::event_packet(&scene.events);
+            event_adder.sort_events(packet).unwrap();
+            let (_, edges) = event_adder.get_latent_and_edge(test_support::C, 9000);
+            mat_to_dmatrix(&edges).unwrap()
+        };
+        let full = edges(None);
+        let windowed = edges(Some(1));
+
+        // The bar passes columns 1 to 5 entirely within the exposure. Row 14 holds the events
+        // around the reference time, which are integrated out of order.
+        for y in 0..14 {
+            for x in 1..=5 {
+                assert!(full[(y, x)].abs() < 0.1, "{}", full[(y, x)]);
+                assert!(windowed[(y, x)].abs() > 3.0, "{}", windowed[(y, x)]);
+            }
+        }
+    }
+
+    #[test]
+    fn deblur_image_reports_why_it_cant_deblur() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        event_adder.blur_info = None;
+        assert!(matches!(
+            deblur_image(&mut event_adder),
+            Err(ReconstructorError::NoFrameBuffered)
+        ));
+
+        let mut event_adder = test_support::event_adder(&scene, 7000, 2000);
+        assert!(matches!(
+            deblur_image(&mut event_adder),
+            Err(ReconstructorError::DegenerateExposure {
+                begin_t: 7000,
+                end_t: 2000
+            })
+        ));
+
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        let packet = test_support::event_packet(&scene.events);
+        event_adder.sort_events(packet).unwrap();
+        let deblur_return = deblur_image(&mut event_adder).unwrap();
+        assert_eq!(
+            deblur_return.timestamps,
+            vec![2000, 3000, 4000, 5000, 6000, 7000]
+        );
+        assert_eq!(deblur_return.ret_vec.len(), 6);
+        // An instantaneous exposure is still one interval
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        event_adder.blur_info.as_mut().unwrap().exposure_end_t = 2000;
+        let deblur_return = deblur_image(&mut event_adder).unwrap();
+        assert_eq!(deblur_return.timestamps, vec![2000]);
+        assert_eq!(deblur_return.ret_vec.len(), 1);
+    }
+
+    #[test]
+    fn trimming_drops_far_events_without_changing_the_latents() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let deblur = |trim_intervals| {
+            let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+            event_adder.event_trim_intervals = trim_intervals;
+            let packet = test_support::event_packet(&scene.events);
+            event_adder.sort_events(packet).unwrap();
+            let queue_lengths = (
+                event_adder.event_before_queue.len(),
+                event_adder.event_during_queue.len(),
+                event_adder.event_after_queue.len(),
+            );
+            (queue_lengths, latents(&mut event_adder))
+        };
+        let (full_lengths, full) = deblur(None);
+        let (trimmed_lengths, trimmed) = deblur(Some(1));
+
+        // The edge reaches column 0 an interval before the exposure, and column 3 more than an
+        // interval after it
+        let column = (test_support::HEIGHT as usize - 1) * test_support::EDGE_EVENTS as usize;
+        assert_eq!(full_lengths, (column, 2 * column + 2, 13 * column));
+        assert_eq!(trimmed_lengths, (column, 2 * column + 2, 0));
+        assert_eq!(trimmed, full);
+    }
+
+    #[test]
+    fn rolling_shutter_rows_are_integrated_over_their_own_exposures() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let line_delay = 200;
+        let mut blurred =
+            DMatrix::<f64>::zeros(test_support::HEIGHT as usize, test_support::WIDTH as usize);
+        for y in 0..blurred.nrows() {
+            let offset = y as i64 * line_delay;
+            let row_blurred = scene.blurred(2000 + offset, 7000 + offset);
+            blurred.set_row(y, &row_blurred.row(y));
+        }
+
+        let error = |line_delay| {
+            let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+            event_adder.blur_info.as_mut().unwrap().blurred_image = blurred.clone();
+            event_adder.rolling_shutter_line_delay = line_delay;
+            let packet = test_support::event_packet(&scene.events);
+            event_adder.sort_events(packet).unwrap();
+            let (latent, _) = event_adder.get_latent_and_edge(test_support::C, 5000);
+            let latent = mat_to_dmatrix(&latent).unwrap();
+
+            // The bottom row only holds the exposure anchors
+            let rows = test_support::HEIGHT as usize - 1;
+            (latent - scene.intensity_at(5000))
+                .rows(0, rows)
+                .abs()
+                .mean()
+        };
+        let global = error(None);
+        let rolling = error(Some(line_delay));
+        assert!(rolling < 0.001, "{}", rolling);
+        assert!(global > 0.005, "{}", global);
+    }
+
+    #[test]
+    fn voxel_grid_reconstructs_like_its_events() {
+        let (height, width) = (test_support::HEIGHT as usize, test_support::WIDTH as usize);
+        let (begin_t, end_t) = EXPOSURES[0];
+
+        // An edge reaching a column in the middle of each interval, where the grid places events
+        let mut counts = vec![DMatrix::<f64>::zeros(height, width); 5];
+        let mut events = vec![];
+        for (index, counts) in counts.iter_mut().enumerate() {
+            let t = begin_t + 1000 * index as i64 + 500;
+            let x = index as i16 + 1;
+            for y in 0..height as i16 - 1 {
+                for _ in 0..test_support::EDGE_EVENTS {
+                    events.push(Event::new(t, x, y, true));
+                }
+                counts[(y as usize, x as usize)] = test_support::EDGE_EVENTS as f64;
+            }
+        }
+        let anchors = test_support::exposure_anchors(&[(begin_t, end_t)]);
+        let scene = Scene::new(
+            DMatrix::from_element(height, width, test_support::DARK),
+            events.iter().chain(&anchors).copied().collect(),
+        );
+
+        let mut from_events = test_support::event_adder(&scene, begin_t, end_t);
+        let packet = test_support::event_packet(&scene.events);
+        from_events.sort_events(packet).unwrap();
+
+        let mut from_grid = test_support::event_adder(&scene, begin_t, end_t);
+        let grid: Vec<Mat> = counts
+            .iter()
+            .map(|counts| dmatrix_to_mat(counts).unwrap())
+            .collect();
+        from_grid
+            .sort_events(test_support::event_packet(&anchors[..1]))
+            .unwrap();
+        assert_eq!(
+            from_grid.add_voxel_grid(&grid, begin_t).unwrap(),
+            events.len()
+        );
+        from_grid
+            .sort_events(test_support::event_packet(&anchors[1..]))
+            .unwrap();
+
+        assert_eq!(from_grid.event_during_queue, from_events.event_during_queue);
+        assert_eq!(latents(&mut from_grid), latents(&mut from_events));
+    }
+
+    #[test]
+    fn fractional_interval_lengths_dont_drift() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        event_adder.set_interval_length(1.0e6 / 29.97);
+        assert_eq!(event_adder.interval_t, 33367);
+
+        // A hundred seconds in, accumulating the rounded length would be 899 ticks late
+        assert_eq!(event_adder.interval_timestamp(0, 2997), 100_000_000);
+
+        let exposure = (2000, 17000);
+        let scene = test_support::moving_edge(&[exposure]);
+        let mut event_adder = test_support::event_adder(&scene, exposure.0, exposure.1);
+        event_adder.set_interval_length(1.0e6 / 300.0);
+        let packet = test_support::event_packet(&scene.events);
+        event_adder.sort_events(packet).unwrap();
+        let timestamps = deblur_image(&mut event_adder).unwrap().timestamps;
+        let offsets: Vec<i64> = timestamps.iter().map(|t| t - timestamps[0]).collect();
+        assert_eq!(offsets, [0, 3333, 6667, 10000, 13333]);
+    }
+
+    #[test]
+    fn event_representations_summarize_each_pixels_events() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        let packet = test_support::event_packet(&scene.events);
+        event_adder.sort_events(packet).unwrap();
+        let representation =
+            |kind| mat_to_dmatrix(&event_adder.event_representation(kind)).unwrap();
+        let last_edge_t = test_support::edge_crossing_t(15) + test_support::EDGE_EVENTS - 1;
+
+        let counts = representation(EventRepr::EventCount);
+        assert_eq!(counts[(0, 4)], test_support::EDGE_EVENTS as f64);
+        assert_eq!(counts[(15, 15)], 2.0);
+        assert_eq!(counts[(15, 0)], 0.0);
+        assert_eq!(counts.sum(), scene.events.len() as f64);
+
+        let time_surface = representation(EventRepr::TimeSurface);
+        assert_eq!(time_surface[(0, 15)], last_edge_t as f64);
+        assert_eq!(time_surface[(15, 15)], 7000.0);
+        assert_eq!(time_surface[(15, 0)], 0.0);
+
+        let tau = test_support::EDGE_COLUMN_TICKS as f64;
+        let sae = representation(EventRepr::SurfaceOfActiveEvents { tau });
+        assert_eq!(sae[(0, 15)], 1.0);
+        assert!((sae[(0, 14)] - (-1.0f64).exp()).abs() < 1e-12);
+        assert_eq!(sae[(15, 0)], 0.0);
+    }
+
+    #[test]
+    fn c_is_only_optimized_with_enough_events() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let deblur = |min_events| {
+            let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+            event_adder.optimize_c = true;
+            event_adder.current_c = 0.4;
+            event_adder.min_events_for_optimize = min_events;
+            let packet = test_support::event_packet(&scene.events);
+            event_adder.sort_events(packet).unwrap();
+            let during = event_adder.event_during_queue.len();
+            let found_c = deblur_image(&mut event_adder).unwrap().found_c;
+            (during, event_adder.optimal_c, found_c)
+        };
+
+        let (during, optimal_c, found_c) = deblur(0);
+        assert_eq!(optimal_c, Some(found_c));
+
+        let (_, optimal_c, found_c) = deblur(during);
+        assert_eq!(optimal_c, Some(found_c));
+
+        let (_, optimal_c, found_c) = deblur(during + 1);
+        assert_eq!(optimal_c, None);
+        assert_eq!(found_c, 0.4);
+    }
+
+    #[test]
+    fn non_continuous_latent_images_are_copied() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let sensor =
+            DMatrix::<f64>::from_fn(20, 20, |row, col| (row * 20 + col + 1) as f64 / 400.0);
+        let expected = sensor.view((2, 2), (16, 16)).into_owned();
+        let sensor = dmatrix_to_mat(&sensor).unwrap();
+
+        let intermediates: Vec<DMatrix<f64>> = [false, true]
+            .into_iter()
+            .map(|log_space_intermediates| {
+                let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+                event_adder.log_space_intermediates = log_space_intermediates;
+                let roi = Mat::roi(&sensor, opencv::core::Rect::new(2, 2, 16, 16)).unwrap();
+                assert!(!roi.is_continuous());
+                event_adder.set_latent_image(roi);
+                assert!(event_adder.latent_image.is_continuous());
+                assert_eq!(mat_to_dmatrix(&event_adder.latent_image).unwrap(), expected);
+                if log_space_intermediates {
+                    assert_eq!(event_adder.log_latent_image, Some(expected.map(f64::ln)));
+                }
+
+                event_adder.event_before_queue = vec![
+                    Event::new(100, 3, 4, true),
+                    Event::new(200, 3, 4, true),
+                    Event::new(5000, 0, 0, true),
+                ];
+                mat_to_dmatrix(&event_adder.get_intermediate_image(test_support::C, 0)).unwrap()
+            })
+            .collect();
+        assert!((&intermediates[0] - &intermediates[1]).abs().max() < 1e-12);
+        assert!(intermediates[0][(4, 3)] > expected[(4, 3)]);
+        assert_eq!(intermediates[0][(8, 8)], expected[(8, 8)]);
+    }
+
+    #[test]
+    fn intermediates_without_events_are_the_latent_image() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        let latent = scene.intensity_at(1000);
+        event_adder.set_latent_image(dmatrix_to_mat(&latent).unwrap());
+        assert!(event_adder.event_before_queue.is_empty());
+        let intermediate = event_adder.get_intermediate_image(test_support::C, 1000);
+        assert_eq!(mat_to_dmatrix(&intermediate).unwrap(), latent);
+    }
+
+    #[test]
+    fn minimum_motion_reference_is_the_quietest_part_of_the_exposure() {
+        // Events throughout the exposure, except in the twelfth of its 16 bins
+        let exposure = (2000, 17000);
+        let bin_length = (exposure.1 - exposure.0) as f64 / MOTION_BINS as f64;
+        let events = (0..MOTION_BINS as i16)
+            .filter(|bin| *bin != 11)
+            .flat_map(|bin| {
+                let t = exposure.0 + (bin as f64 * bin_length) as i64 + 400;
+                (0..3).map(move |step| Event::new(t + step, bin, 0, true))
+            })
+            .collect();
+        let scene = Scene::new(
+            DMatrix::from_element(
+                test_support::HEIGHT as usize,
+                test_support::WIDTH as usize,
+                test_support::DARK,
+            ),
+            events,
+        );
+        let reference_t = |latent_reference, output_mode| {
+            let mut event_adder = test_support::event_adder(&scene, exposure.0, exposure.1);
+            event_adder.latent_reference = latent_reference;
+            event_adder.output_mode = output_mode;
+            let packet = test_support::event_packet(&scene.events);
+            event_adder.sort_events(packet).unwrap();
+            event_adder.reference_timestamp().unwrap()
+        };
+
+        assert_eq!(
+            reference_t(LatentReference::Center, OutputMode::FullSequence),
+            10000
+        );
+        assert_eq!(
+            reference_t(LatentReference::MinimumMotion, OutputMode::SharpFrameOnly),
+            12781
+        );
+        assert_eq!(
+            reference_t(LatentReference::MinimumMotion, OutputMode::FullSequence),
+            13000
+        );
+    }
+
+    #[test]
+    fn stream_offsets_move_events_onto_the_sensor() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        let stream_id = aedat::base::StreamContent::Events as u32;
+        event_adder.stream_offsets.insert(stream_id, (10, -2));
+        let packet = test_support::event_packet(&[
+            Event::new(3000, 1, 5, true),
+            Event::new(3001, 5, 5, true),
+            Event::new(3002, 1, 1, false),
+        ]);
+
+        // Only the first lands on the sensor
+        assert_eq!(event_adder.sort_events(packet).unwrap(), 1);
+        let sorted: Vec<_> = event_adder
+            .event_during_queue
+            .iter()
+            .map(|event| (event.t(), event.x(), event.y(), event.on()))
+            .collect();
+        assert_eq!(sorted, [(3000, 11, 3, true)]);
+    }
+
+    #[test]
+    fn deblurring_needs_a_buffered_frame() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        event_adder.blur_info = None;
+        assert!(matches!(
+            deblur_image(&mut event_adder),
+            Err(ReconstructorError::NoFrameBuffered)
+        ));
+        let packet = test_support::event_packet(&scene.events);
+        assert!(matches!(
+            event_adder.sort_events(packet),
+            Err(ReconstructorError::NoFrameBuffered)
+        ));
+    }
+
+    #[test]
+    fn gradient_operators_weigh_a_step_edge_by_their_kernels() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let step = DMatrix::<f64>::from_fn(16, 16, |_, col| if col < 8 { 0.0 } else { 1.0 });
+        for (gradient_operator, kernel_sum) in [
+            (GradientOperator::Sobel, 4.0),
+            (GradientOperator::Scharr, 16.0),
+        ] {
+            let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+            event_adder.gradient_operator = gradient_operator;
+            let (grad, edges) = event_adder.get_gradient_and_edges(dmatrix_to_mat(&step).unwrap());
+            let (grad, edges) = (
+                mat_to_dmatrix(&grad).unwrap(),
+                mat_to_dmatrix(&edges).unwrap(),
+            );
+            assert_eq!(grad[(8, 7)], kernel_sum);
+            assert_eq!(grad[(8, 8)], kernel_sum);
+            assert_eq!(grad[(8, 3)], 0.0);
+            assert_eq!((edges[(8, 7)], edges[(8, 3)]), (1.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn otsu_threshold_separates_the_two_modes() {
+        let bimodal = DMatrix::<f64>::from_fn(16, 16, |_, col| if col < 8 { 0.2 } else { 0.8 });
+        let threshold_val = otsu_threshold(&dmatrix_to_mat(&bimodal).unwrap()).unwrap();
+        assert!((0.2..0.8).contains(&threshold_val));
+
+        // Only the pixels either side of a step are edges
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        event_adder.edge_threshold = EdgeThreshold::Otsu;
+        let step = DMatrix::<f64>::from_fn(16, 16, |_, col| if col < 8 { 0.0 } else { 1.0 });
+        let (_, edges) = event_adder.get_gradient_and_edges(dmatrix_to_mat(&step).unwrap());
+        let edges = mat_to_dmatrix(&edges).unwrap();
+        assert_eq!(edges.sum(), 2.0 * 16.0);
+        assert_eq!(edges.column(7).sum() + edges.column(8).sum(), 2.0 * 16.0);
+    }
+
+    #[test]
+    fn log_polar_events_land_where_the_warp_maps_their_pixel() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let event_adder = test_support::event_adder(&scene, 2000, 7000);
+        let (height, width) = (test_support::HEIGHT as usize, test_support::WIDTH as usize);
+        let (phi, rho) = log_polar_coordinates(12.0, 3.0, height, width);
+
+        let splat = event_adder.splat_log_polar(&Event::new(3000, 3, 12, true));
+        assert!((splat.iter().map(|(_, _, weight)| weight).sum::<f64>() - 1.0).abs() < 1e-12);
+        let centroid = |index: fn(&(usize, usize, f64)) -> usize| {
+            splat
+                .iter()
+                .map(|entry| index(entry) as f64 * entry.2)
+                .sum::<f64>()
+        };
+        assert!((centroid(|entry| entry.0) - phi).abs() < 1e-9);
+        assert!((centroid(|entry| entry.1) - rho).abs() < 1e-9);
+
+        // A bright patch around the same pixel is warped to around the same place
+        let image = DMatrix::from_fn(height, width, |row, col| {
+            match (11..=13).contains(&row) && (2..=4).contains(&col) {
+                true => 1.0,
+                false => 0.0,
+            }
+        });
+        let warped =
+            mat_to_dmatrix(&warp_log_polar(&dmatrix_to_mat(&image).unwrap(), false).unwrap())
+                .unwrap();
+        let total = warped.sum();
+        let (mut row_centroid, mut col_centroid) = (0.0, 0.0);
+        for ((row, col), value) in warped
+            .iter()
+            .enumerate()
+            .map(|(index, value)| ((index % height, index / height), value))
+        {
+            row_centroid += row as f64 * value / total;
+            col_centroid += col as f64 * value / total;
+        }
+        assert!((row_centroid - phi).abs() < 1.5);
+        assert!((col_centroid - rho).abs() < 1.5);
+    }
+
+    #[test]
+    fn queue_capacity_is_reserved_for_the_average_frame() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        let events = |count: usize| vec![Event::new(3000, 0, 0, true); count];
+        event_adder.event_during_queue = events(80);
+        event_adder.event_after_queue = events(40);
+        event_adder.record_queue_lengths();
+        event_adder.event_during_queue = events(160);
+        event_adder.event_after_queue = vec![];
+        event_adder.record_queue_lengths();
+
+        // Fresh queues, as though they had been handed off
+        event_adder.event_during_queue = vec![];
+        event_adder.event_after_queue = vec![];
+        event_adder.reserve_queue_capacity();
+        assert_eq!(event_adder.event_during_queue.capacity(), 0);
+
+        // The averages are 96 and 32 events
+        event_adder.reserve_event_queues = true;
+        event_adder.reserve_queue_capacity();
+        assert!(event_adder.event_during_queue.capacity() >= 120);
+        assert!(event_adder.event_after_queue.capacity() >= 40);
+    }
+
+    #[test]
+    fn smoothed_c_moves_part_way_to_the_optimum() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let deblur = |c_smoothing| {
+            let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+            event_adder.optimize_c = true;
+            event_adder.current_c = 0.4;
+            event_adder.c_smoothing = c_smoothing;
+            let packet = test_support::event_packet(&scene.events);
+            event_adder.sort_events(packet).unwrap();
+            let found_c = deblur_image(&mut event_adder).unwrap().found_c;
+            (event_adder.optimal_c.unwrap(), found_c)
+        };
+
+        let (optimal_c, found_c) = deblur(None);
+        assert_eq!(found_c, optimal_c);
+        assert_ne!(optimal_c, 0.4);
+        let (smoothed_optimal_c, found_c) = deblur(Some(0.25));
+        assert_eq!(smoothed_optimal_c, optimal_c);
+        assert!((found_c - (0.4 + 0.25 * (optimal_c - 0.4))).abs() < 1e-12);
+        assert_eq!(deblur(Some(1.0)).1, optimal_c);
+    }
+
+    #[test]
+    fn truncated_packets_are_rejected_without_sorting_any_events() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        let mut packet = test_support::event_packet(&scene.events);
+        packet.buffer.truncate(packet.buffer.len() / 2);
+        assert!(matches!(
+            event_adder.sort_events(packet),
+            Err(ReconstructorError::PacketError(_))
+        ));
+        assert!(event_adder.event_before_queue.is_empty());
+        assert!(event_adder.event_during_queue.is_empty());
+        assert!(event_adder.event_after_queue.is_empty());
+
+        // The adder is still usable
+        let packet = test_support::event_packet(&scene.events);
+        assert_eq!(event_adder.sort_events(packet).unwrap(), scene.events.len());
+    }
+
+    #[test]
+    fn lambda_weighs_the_total_variation_in_the_energy() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        let packet = test_support::event_packet(&scene.events);
+        event_adder.sort_events(packet).unwrap();
+        let mut phi = |lambda| {
+            event_adder.lambda = lambda;
+            event_adder.get_phi(test_support::C, 4500)
+        };
+        let (phi_0, phi_default, phi_double) =
+            (phi(0.0), phi(DEFAULT_LAMBDA), phi(2.0 * DEFAULT_LAMBDA));
+
+        // The total variation of the latent image is positive, and the energy is linear in lambda
+        assert!(phi_default > phi_0);
+        assert!((phi_double - phi_0 - 2.0 * (phi_default - phi_0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn c_search_stays_within_its_range() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        let packet = test_support::event_packet(&scene.events);
+        event_adder.sort_events(packet).unwrap();
+        for (min_c, max_c, n_points) in [
+            (0.1, 0.5, 15),
+            (0.2, 0.3, 15),
+            (0.24, 0.26, 3),
+            (0.2, 0.3, MAX_C_SEARCH_POINTS),
+        ] {
+            event_adder.min_c = min_c;
+            event_adder.max_c = max_c;
+            event_adder.n_points = n_points;
+            let (c, energy) = event_adder.optimize_c(4500);
+            assert!((min_c..=max_c).contains(&c));
+            assert_eq!(energy, event_adder.get_phi(c, 4500));
+        }
+    }
+
+    #[test]
+    fn median_inter_event_time_is_over_repeated_pixels() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        assert_eq!(event_adder.median_inter_event_time(), None);
+
+        // Each pixel fires once, so there's no time between events to measure
+        event_adder.event_during_queue = (0..4).map(|x| Event::new(3000, x, 0, true)).collect();
+        assert_eq!(event_adder.median_inter_event_time(), None);
+
+        // The gaps are 1000 and 2500 at (0, 0), and 3000 at (1, 0)
+        event_adder.event_during_queue = vec![
+            Event::new(3000, 0, 0, true),
+            Event::new(3000, 1, 0, true),
+            Event::new(4000, 0, 0, false),
+            Event::new(6000, 1, 0, false),
+            Event::new(6500, 0, 0, true),
+        ];
+        assert_eq!(event_adder.median_inter_event_time(), Some(2500.0));
+
+        // The edge fires each pixel's events a tick apart
+        let packet = test_support::event_packet(&scene.events);
+        event_adder.event_during_queue.clear();
+        event_adder.sort_events(packet).unwrap();
+        assert_eq!(event_adder.median_inter_event_time(), Some(1.0));
+    }
+
+    #[test]
+    fn renormalized_integrals_hold_terms_past_the_f64_range() {
+        let integral = |terms: &[(f64, f64)]| {
+            let (mut accumulator, mut scale) = (0.0, 0.0);
+            for &(log_term, weight) in terms {
+                accumulate_renormalized(&mut accumulator, &mut scale, log_term, weight);
+            }
+            accumulator.ln() + scale
+        };
+
+        let terms = [(0.5, 2.0), (-1.0, 3.0), (2.0, 1.0)];
+        let expected = terms.iter().map(|(t, w)| t.exp() * w).sum::<f64>().ln();
+        assert!((integral(&terms) - expected).abs() < 1e-12);
+
+        // exp(800) overflows on its own, and the small first term vanishes beside it
+        let log_integral = integral(&[(1.0, 1.0), (800.0, 2.0), (800.0, 3.0)]);
+        assert!((log_integral - (800.0 + 5f64.ln())).abs() < 1e-9);
+    }
+
+    #[test]
+    fn long_exposure_integration_matches_the_default() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let latents = |long_exposure| {
+            let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+            event_adder.long_exposure = long_exposure;
+            let packet = test_support::event_packet(&scene.events);
+            event_adder.sort_events(packet).unwrap();
+            deblur_image(&mut event_adder)
+                .unwrap()
+                .ret_vec
+                .iter()
+                .map(|latent| mat_to_dmatrix(latent).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let expected = latents(false);
+        let renormalized = latents(true);
+        assert_eq!(renormalized.len(), expected.len());
+        for (latent, expected) in renormalized.iter().zip(&expected) {
+            let relative = (latent - expected).component_div(expected);
+            assert!(relative.amax() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn imu_reference_is_the_bin_of_least_rotation() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        let packet = test_support::event_packet(&scene.events);
+        event_adder.sort_events(packet).unwrap();
+        let blur_info = event_adder.blur_info.clone().unwrap();
+        let from_events = event_adder.minimum_motion_timestamp(&blur_info);
+
+        // Without samples in the exposure, the event rate decides
+        event_adder.use_imu = true;
+        event_adder.imu_angular_speed = vec![(1000, 0.0), (8000, 0.0)];
+        assert_eq!(
+            event_adder.minimum_motion_timestamp(&blur_info),
+            from_events
+        );
+
+        // The bins are 312.5 ticks long, and the slowest holds 4000 and 4100
+        event_adder
+            .imu_angular_speed
+            .extend([(2100, 10.0), (4000, 2.0), (4100, 0.0), (6900, 5.0)]);
+        assert_eq!(event_adder.minimum_motion_timestamp(&blur_info), 4031);
+
+        event_adder.use_imu = false;
+        assert_eq!(
+            event_adder.minimum_motion_timestamp(&blur_info),
+            from_events
+        );
+    }
+
+    #[test]
+    fn reset_c_optimizes_the_next_frame_without_smoothing() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let deblur = |reset: bool| {
+            let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+            event_adder.optimize_c = true;
+            event_adder.optimize_c_frequency = 10;
+            event_adder.c_smoothing = Some(0.25);
+            event_adder.current_c = 0.5;
+            event_adder.optimal_c = Some(1.0);
+            if reset {
+                event_adder.reset_c(0.4);
+                assert_eq!((event_adder.current_c, event_adder.optimal_c), (0.4, None));
+            }
+            let packet = test_support::event_packet(&scene.events);
+            event_adder.sort_events(packet).unwrap();
+            let found_c = deblur_image(&mut event_adder).unwrap().found_c;
+            assert!(!event_adder.skip_c_smoothing);
+            (event_adder.optimal_c, found_c)
+        };
+
+        // Otherwise, the first frame isn't due for optimizing
+        assert_eq!(deblur(false), (Some(1.0), 0.5));
+        let (optimal_c, found_c) = deblur(true);
+        assert_ne!(found_c, 0.4);
+        assert_eq!(optimal_c, Some(found_c));
+    }
+
+    #[test]
+    fn hot_pixels_are_masked_from_every_queue() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let hot_pixel = (3, test_support::HEIGHT as i16 - 1);
+        let mut events = scene.events.clone();
+        events
+            .extend((0..=50).map(|step| {
+                Event::new(2000 + 100 * step, hot_pixel.0, hot_pixel.1, step % 2 == 0)
+            }));
+        events.sort_by_key(|event| event.t());
+        let queued = |event_adder: &EventAdder| {
+            event_adder
+                .event_before_queue
+                .iter()
+                .chain(&event_adder.event_during_queue)
+                .chain(&event_adder.event_after_queue)
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        let sorted = |threshold| {
+            let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+            event_adder.hot_pixel_threshold = threshold;
+            event_adder
+                .sort_events(test_support::event_packet(&events))
+                .unwrap();
+            event_adder
+        };
+
+        let mut event_adder = sorted(None);
+        event_adder.mask_hot_pixels();
+        assert_eq!(event_adder.hot_pixels_masked, 0);
+        assert_eq!(queued(&event_adder).len(), events.len());
+
+        // The edge fires each pixel 4 times, but the hot pixel about 9 times per interval
+        let mut event_adder = sorted(Some(2.0));
+        event_adder.mask_hot_pixels();
+        assert_eq!(event_adder.hot_pixels_masked, 1);
+        let remaining = queued(&event_adder);
+        assert_eq!(remaining.len(), events.len() - 51);
+        assert!(remaining
+            .iter()
+            .all(|event| (event.x(), event.y()) != hot_pixel));
+    }
+
+    #[test]
+    fn frames_without_events_deblur_with_hot_pixel_masking() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        let expected = latents(&mut event_adder);
+
+        // With every queue empty, there's nothing to mask and nothing to measure the span of
+        let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+        event_adder.hot_pixel_threshold = Some(2.0);
+        assert_eq!(latents(&mut event_adder), expected);
+        assert_eq!(event_adder.hot_pixels_masked, 0);
+    }
+
+    #[test]
+    fn edge_decay_tau_only_weighs_the_edges() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let latent_and_edges = |tau| {
+            let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+            event_adder.optimize_c = true;
+            event_adder.edge_decay_tau = tau;
+            let packet = test_support::event_packet(&scene.events);
+            event_adder.sort_events(packet).unwrap();
+            let (latent, edges) = event_adder.get_latent_and_edge(test_support::C, 3000);
+            (
+                mat_to_dmatrix(&latent).unwrap(),
+                mat_to_dmatrix(&edges).unwrap(),
+            )
+        };
+
+        // The default is one second of the timebase
+        let (latent, edges) = latent_and_edges(None);
+        assert_eq!(latent_and_edges(Some(1e6)), (latent.clone(), edges.clone()));
+
+        // Column 1 fires 750 ticks after the reference, which decays much more over 100 ticks
+        let (short_latent, short_edges) = latent_and_edges(Some(100.0));
+        assert_eq!(short_latent, latent);
+        assert!(edges[(0, 1)].abs() > 0.0);
+        assert!(short_edges[(0, 1)].abs() < edges[(0, 1)].abs());
+    }
+
+    #[test]
+    fn energy_is_reported_at_the_applied_c() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let sorted = |optimize_c| {
+            let mut event_adder = test_support::event_adder(&scene, 2000, 7000);
+            event_adder.optimize_c = optimize_c;
+            let packet = test_support::event_packet(&scene.events);
+            event_adder.sort_events(packet).unwrap();
+            event_adder
+        };
+        let energy_at = |c| {
+            let event_adder = sorted(true);
+            event_adder.get_phi(c, event_adder.reference_timestamp().unwrap())
+        };
+
+        // A fixed c is evaluated without turning on the optimization
+        let mut event_adder = sorted(false);
+        assert_eq!(event_adder.last_energy, None);
+        deblur_image(&mut event_adder).unwrap();
+        assert!(!event_adder.optimize_c);
+        assert_eq!(event_adder.last_energy, Some(energy_at(test_support::C)));
+
+        let mut event_adder = sorted(true);
+        let found_c = deblur_image(&mut event_adder).unwrap().found_c;
+        assert_eq!(event_adder.last_energy, Some(energy_at(found_c)));
+    }
+
+    #[test]
+    fn merged_frames_average_over_both_exposures() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let blur_info = |begin_t, end_t| {
+            test_support::event_adder(&scene, begin_t, end_t)
+                .blur_info
+                .unwrap()
+        };
+        let (first, later) = (blur_info(2000, 3000), blur_info(3500, 7000));
+        let expected = (&first.blurred_image * 1000.0 + &later.blurred_image * 3500.0) / 4500.0;
+        let packet_timestamp = later.packet_timestamp;
+
+        let merged = first.merge(later);
+        assert_eq!(
+            (merged.exposure_begin_t, merged.exposure_end_t),
+            (2000, 7000)
+        );
+        assert!((merged.blurred_image - expected).amax() < 1e-12);
+        assert_eq!(merged.packet_timestamp, packet_timestamp);
+    }
 }
@@ -1,3 +1,9 @@
+pub mod config;
+pub mod deconvolution;
+pub mod display;
 pub(crate) mod event_adder;
 pub mod reconstructor;
-mod threaded_decoder;
\ No newline at end of file
+pub mod stereo;
+#[cfg(test)]
+pub(crate) mod test_support;
+mod threaded_decoder;
@@ -1,57 +1,445 @@
-use crate::util::event_adder::{deblur_image, BlurInfo, EventAdder};
-use aedat::base::{Decoder, ParseError, Stream, StreamContent};
+use crate::output::MmapFrameWriter;
+use crate::util::config::{InputSource, ReconstructorConfig};
+use crate::util::display::{DisplayManager, DisplayView};
+use crate::util::event_adder::{
+    deblur_image, warp_log_polar, BlurInfo, DeblurReturn, EventAdder, MAX_C_SEARCH_POINTS,
+};
+use aedat::base::{Decoder, Packet, ParseError, Stream, StreamContent};
 
 use crate::util::reconstructor::ReconstructorError::ArgumentError;
-use crate::util::threaded_decoder::{setup_packet_threads, PacketReceiver, TimestampedPacket};
+use crate::util::threaded_decoder::{
+    setup_packet_threads, setup_serial_packets, PacketReceiver, TimestampedPacket,
+};
 use aedat::events_generated::Event;
+use byteorder::{LittleEndian, WriteBytesExt};
+use crossbeam::channel::Sender;
 use cv_convert::TryFromCv;
 use nalgebra::DMatrix;
+use ndarray::{Array1, Array3};
+use ndarray_npy::NpzWriter;
 use num_traits::FromPrimitive;
-use opencv::core::{Mat, MatTrait, MatTraitConst, Size, CV_8S, NORM_MINMAX};
+use opencv::core::{
+    absdiff, add_weighted, count_non_zero, in_range, log, mean, no_array, Mat, MatTraitConst, Rect,
+    Scalar, Size, CV_32F, CV_64F,
+};
+#[cfg(feature = "display")]
 use opencv::highgui;
-use opencv::imgproc::resize;
-use simple_error::SimpleError;
+use opencv::imgproc::{resize, INTER_LINEAR};
+use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
 use std::cmp::max;
 use std::collections::VecDeque;
+use std::fs::File;
 use std::io::Write;
 use std::path::Path;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::{io, mem};
 use thiserror::Error;
 
 pub type IterVal = (
     Mat,
     Option<Instant>,
-    Option<(f64, Vec<Event>, Vec<Event>, i64, i64)>,
+    Option<(f64, Vec<EdiEvent>, Vec<EdiEvent>, i64, i64)>,
     Option<u128>,
 );
 pub type IterRet = Option<Result<IterVal, ReconstructionError>>;
 
+/// An owned copy of a DVS event, independent of the aedat flatbuffer types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EdiEvent {
+    pub t: i64,
+    pub x: u16,
+    pub y: u16,
+
+    /// True for an increase in brightness
+    pub polarity: bool,
+}
+
+impl From<&Event> for EdiEvent {
+    fn from(event: &Event) -> Self {
+        EdiEvent {
+            t: event.t(),
+            x: event.x() as u16,
+            y: event.y() as u16,
+            polarity: event.on(),
+        }
+    }
+}
+
+/// A reconstructed frame, with its events and timing. See [`Reconstructor::next_frame`].
+#[derive(Debug)]
+pub struct ReconstructedFrame {
+    pub image: Mat,
+
+    /// The start of the frame's interval, or the reference instant of its exposure in
+    /// [`OutputMode::SharpFrameOnly`]. See [`Reconstructor::last_returned_timestamp`].
+    pub timestamp: Option<i64>,
+
+    /// When the packet of the APS frame that this frame was reconstructed from was received
+    pub packet_timestamp: Option<Instant>,
+
+    /// The contrast threshold used for the reconstruction
+    pub contrast_c: Option<f64>,
+
+    /// The events before the APS exposure, in [`OutputMode::SharpFrameOnly`]
+    pub events_before: Option<Vec<EdiEvent>>,
+
+    /// The events during and after the APS exposure in [`OutputMode::SharpFrameOnly`], or the
+    /// events in the frame's own interval in [`OutputMode::FullSequence`]
+    pub events_after: Option<Vec<EdiEvent>>,
+
+    /// The start of the exposure in [`OutputMode::SharpFrameOnly`], or of the frame's interval in
+    /// [`OutputMode::FullSequence`]
+    pub exposure_begin_t: Option<i64>,
+
+    /// The end of the latest reconstructed interval in [`OutputMode::SharpFrameOnly`], or of the
+    /// frame's interval in [`OutputMode::FullSequence`]
+    pub last_interval_start_t: Option<i64>,
+
+    /// The time taken to deblur the APS frame, for the first frame reconstructed from it
+    pub latency_ms: Option<u128>,
+
+    /// The c-optimization energy of the APS frame that this frame was reconstructed from, at the
+    /// applied c. Lower is sharper, so a jump flags a failed reconstruction. See
+    /// [`Reconstructor::last_energy`].
+    pub energy: Option<f64>,
+}
+
+impl From<IterVal> for ReconstructedFrame {
+    fn from((image, packet_timestamp, events, latency_ms): IterVal) -> Self {
+        let (contrast_c, events_before, events_after, exposure_begin_t, last_interval_start_t) =
+            match events {
+                Some((c, before, after, begin_t, end_t)) => (
+                    Some(c),
+                    Some(before),
+                    Some(after),
+                    Some(begin_t),
+                    Some(end_t),
+                ),
+                None => (None, None, None, None, None),
+            };
+        ReconstructedFrame {
+            image,
+            timestamp: None,
+            packet_timestamp,
+            contrast_c,
+            events_before,
+            events_after,
+            exposure_begin_t,
+            last_interval_start_t,
+            latency_ms,
+            energy: None,
+        }
+    }
+}
+
+/// Whether the contrast threshold c is being optimized. This is the single source of truth for
+/// the optimization controller; the event adder's flag just mirrors [`COptimizationState::optimizes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum COptimizationState {
+    /// The user disabled c-optimization, so `start_c` is used for every frame. The controller
+    /// never leaves this state.
+    Disabled,
+
+    /// c is optimized every `optimize_c_frequency` APS frames
+    Active,
+
+    /// The controller suspended c-optimization, because the latency exceeded the target
+    Suspended,
+}
+
+impl COptimizationState {
+    /// Get the next state, given whether the latency of the latest frame exceeded the target.
+    /// `Active` becomes `Suspended` when over the target, and `Suspended` becomes `Active` once the
+    /// latency is back within the target.
+    pub fn on_latency(self, over_target: bool) -> Self {
+        match (self, over_target) {
+            (COptimizationState::Disabled, _) => COptimizationState::Disabled,
+            (_, true) => COptimizationState::Suspended,
+            (_, false) => COptimizationState::Active,
+        }
+    }
+
+    /// Whether c should be optimized in this state
+    pub fn optimizes(self) -> bool {
+        self == COptimizationState::Active
+    }
+}
+
+/// A per-pixel summary of a set of events, for feeding to learning-based methods. Each is a
+/// single-channel `CV_64F` image on the reconstruction grid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventRepr {
+    /// The timestamp of the most recent event at each pixel, or 0 where there are none
+    TimeSurface,
+
+    /// The number of events at each pixel, regardless of polarity
+    EventCount,
+
+    /// The surface of active events: `exp(-(t_last - t) / tau)`, where `t` is the timestamp of the
+    /// most recent event at the pixel and `t_last` is the timestamp of the most recent event
+    /// overall. Pixels with no events are 0.
+    SurfaceOfActiveEvents { tau: f64 },
+}
+
+/// The most events a pixel can have between two output frames and still count as static in
+/// [`Reconstructor::temporal_consistency`], to tolerate sensor noise
+pub const STATIC_PIXEL_MAX_EVENTS: f64 = 1.0;
+
+/// The default weight of the total variation regularization in the c-optimization energy
+pub const DEFAULT_LAMBDA: f64 = 0.15;
+
+/// The default range and resolution of the c-optimization search
+pub const DEFAULT_MIN_C: f64 = 0.1;
+pub const DEFAULT_MAX_C: f64 = 0.5;
+pub const DEFAULT_N_POINTS: u32 = 15;
+
+/// The default number of event timestamp ticks per second (i.e., microsecond timestamps)
+pub const DEFAULT_TIMEBASE_HZ: f64 = 1_000_000.0;
+
+/// Describes the bytes returned by [`Reconstructor::next_raw`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameHeader {
+    pub width: u32,
+    pub height: u32,
+
+    /// The start timestamp of the frame's interval
+    pub timestamp: i64,
+
+    /// The contrast threshold used for the frame
+    pub c: f64,
+}
+
+/// Statistics about the deblurring of an APS frame
+#[derive(Debug, Clone)]
+pub struct FrameStats {
+    pub exposure_begin_t: i64,
+    pub exposure_end_t: i64,
+
+    /// The contrast threshold used for the frame
+    pub c: f64,
+    pub events_during_exposure: usize,
+
+    /// The mean intensity of the blurred input image
+    pub blurred_mean: f64,
+
+    /// The mean intensity of the latent images during the exposure
+    pub latent_mean: f64,
+
+    /// Whether the latent and blurred means diverge beyond the quality check threshold, in which
+    /// case c was likely wrong. See [`Reconstructor::set_quality_check_threshold`].
+    pub quality_warning: bool,
+
+    /// Whether the events ended before the exposure did, so the frame was reconstructed from
+    /// incomplete events. See [`Reconstructor::set_truncated_exposure`].
+    pub exposure_truncated: bool,
+}
+
+/// A stream of the source, as described in its header. See [`Reconstructor::streams`].
+#[derive(Debug, Clone, Copy)]
+pub struct StreamInfo {
+    /// The stream's ID in the source
+    pub id: u32,
+    pub content: StreamContent,
+
+    /// The resolution of the sensor that the stream came from
+    pub width: u16,
+    pub height: u16,
+}
+
+/// An inertial measurement from the camera's IMU stream. See [`Reconstructor::drain_imu`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImuSample {
+    pub timestamp: i64,
+
+    /// Acceleration in x, y, and z, in g
+    pub accel: [f32; 3],
+
+    /// Angular velocity about x, y, and z, in degrees per second
+    pub gyro: [f32; 3],
+}
+
+/// The most IMU samples kept for [`Reconstructor::drain_imu`]. Past this, the oldest are dropped,
+/// so a caller that never drains them doesn't grow the queue without bound.
+const IMU_QUEUE_CAPACITY: usize = 100_000;
+
+/// The operator for the image gradients that the c-optimization compares edges with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum GradientOperator {
+    /// 3x3 Sobel kernels
+    #[default]
+    Sobel,
+
+    /// 3x3 Scharr kernels, which are more rotationally accurate, so diagonal edges are weighted
+    /// closer to horizontal and vertical ones
+    Scharr,
+}
+
+/// How the gradient magnitudes are binarized into edge maps for the c-optimization
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum EdgeThreshold {
+    /// A fixed cutoff a third of the way from the mean normalized gradient to the maximum
+    #[default]
+    Fixed,
+
+    /// Otsu's method, which picks the cutoff that best separates a bimodal gradient histogram.
+    /// Adapts to scenes where the fixed cutoff keeps too many or too few edges.
+    Otsu,
+}
+
+/// What the latent image for the events between two APS frames starts from. Carrying the last
+/// latent image forward keeps the output smooth, but after a scene cut or a large illumination
+/// change it leaves a ghost of the old scene that takes a while to correct.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum LatentCarry {
+    /// Always start from the last latent image of the previous APS frame
+    #[default]
+    Always,
+
+    /// Start from the next blurred APS frame instead when the mean absolute difference between
+    /// consecutive blurred frames exceeds the threshold (in normalized intensity units)
+    ResetOnSceneChange { threshold: f64 },
+
+    /// Always start from the next blurred APS frame
+    Never,
+}
+
+/// What to do when the reconstruction intervals are shorter than the time between events at a
+/// pixel. Most intervals then hold no events, so the output mostly repeats the latent image and
+/// the extra frames just cost compute. Checked once, on the events of the first APS exposure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FpsResolutionCheck {
+    /// Don't check
+    Off,
+
+    /// Print a warning suggesting a lower `output_fps`
+    #[default]
+    Warn,
+
+    /// Print a warning, and lengthen the intervals to the median time between events
+    Cap,
+}
+
+/// What to do with the final APS frame when the events end before its exposure does. Its
+/// deblurring is then under-determined, since the events of the rest of the exposure are missing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TruncatedExposure {
+    /// Drop the frame
+    Skip,
+
+    /// Reconstruct the frame from the events available, and flag it with
+    /// [`FrameStats::exposure_truncated`]
+    #[default]
+    BestEffort,
+}
+
+/// How to choose the reference instant within each APS exposure. c is optimized relative to the
+/// latent image at this instant, and in [`OutputMode::SharpFrameOnly`], it's the instant of the
+/// sharp frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LatentReference {
+    /// The temporal middle of the exposure in the full-sequence mode, or its beginning in the
+    /// sharp-frame-only mode
+    #[default]
+    Center,
+
+    /// The instant with the fewest events per unit time, which tends to be perceived as the
+    /// sharpest
+    MinimumMotion,
+}
+
+/// What the reconstructor produces for each blurred APS frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OutputMode {
+    /// Only the sharp latent image at the start of each APS frame's exposure, so there's one
+    /// output frame per APS frame. This is what the `deblur_only` flag selects. The interval
+    /// length from `output_fps` is shortened to the exposure time when that's shorter, and the
+    /// reported output frame rate follows the APS exposures rather than `output_fps`.
+    SharpFrameOnly,
+
+    /// The full sequence of latent images at `output_fps`, covering both the APS exposures and
+    /// the gaps between them
+    #[default]
+    FullSequence,
+}
+
 #[derive(Default)]
 pub struct BlurredInput {
     pub image: Mat,
     pub exposure_begin_t: i64,
     pub exposure_end_t: i64,
 }
+
+/// A lower frame rate view of the output sequence, sent to a channel. See
+/// [`Reconstructor::add_output_tap`].
+struct OutputTap {
+    /// Ticks between tapped frames
+    interval: f64,
+    next_t: Option<f64>,
+    sink: Sender<(Mat, i64)>,
+}
+
 unsafe impl Sync for Reconstructor {}
 unsafe impl Send for Reconstructor {}
 
 pub struct Reconstructor {
     show_display: bool,
+    debug_display: bool,
     show_blurred_display: bool,
     packet_receiver: PacketReceiver,
     pub height: u16,
     pub width: u16,
     packet_queue: VecDeque<TimestampedPacket>,
     pub event_adder: EventAdder,
-    latent_image_queue: VecDeque<Mat>,
+    latent_image_queue: VecDeque<(Mat, i64)>,
     pub output_fps: f64,
-    optimize_c: bool,
+    c_optimization: COptimizationState,
     optimize_controller: bool,
     pub target_latency: f64,
     mode: String,
     events_return_before: Vec<Event>, // Events occurring before the deblurred frame
     events_return_after: Vec<Event>,  // Events occurring during & after the deblurred frame
+    output_event_counts: bool,
+    last_event_count_map: Option<Mat>,
+    estimate_variance: bool,
+    last_variance_map: Option<Mat>,
+    keep_log_latents: bool,
+    last_log_latents: Option<Vec<Mat>>,
+    fps_resolution_check: FpsResolutionCheck,
+    fps_resolution_checked: bool,
+    input_histogram_bins: Option<usize>,
+    last_input_histogram: Option<Vec<u32>>,
+    imu_queue: VecDeque<ImuSample>,
+    max_desync: Option<i64>,
+    latest_event_t: Option<i64>,
+    desync_dropped_frames: u64,
+    min_frame_gap: Option<i64>,
+    pending_frame: Option<(BlurInfo, VecDeque<TimestampedPacket>)>,
+    merged_frames: u64,
+    output_crop_margin: u32,
+    supersamples: u32,
+    shutter_angle: f64,
+    supersample_buffer: Vec<(Mat, i64)>,
+    display_manager: Option<DisplayManager>,
+    quality_check_threshold: Option<f64>,
+    last_frame_stats: Option<FrameStats>,
+    input_temporal_average: usize,
+    input_history: VecDeque<DMatrix<f64>>,
+    trigger_window: Option<i64>,
+    trigger_timestamps: VecDeque<i64>,
+    previous_returned: Option<(Mat, i64)>,
+    last_returned: Option<(Mat, i64)>,
+    reverse: bool,
+    latent_carry: LatentCarry,
+    density_blend: Option<f64>,
+    parallel_frames: usize,
+    end_of_stream: bool,
+    truncated_exposure: TruncatedExposure,
+    input_bytes: Option<u64>,
+    streams: Vec<StreamInfo>,
+    output_taps: Vec<OutputTap>,
+    retain_frame_events: bool,
+    output_depth: i32,
 }
 
 #[allow(missing_docs)]
@@ -65,6 +453,27 @@ pub enum ReconstructorError {
 
     #[error("Argument error: `{0}`")]
     ArgumentError(String),
+
+    #[error("Conversion error: `{0}`")]
+    ConversionError(String),
+
+    #[error("Export error: `{0}`")]
+    ExportError(String),
+
+    #[error("Config error: `{0}`")]
+    ConfigError(String),
+
+    #[error("Packet error: `{0}`")]
+    PacketError(String),
+
+    #[error("No APS frame is buffered to deblur")]
+    NoFrameBuffered,
+
+    #[error("Degenerate exposure: begins at {begin_t} and ends at {end_t}")]
+    DegenerateExposure { begin_t: i64, end_t: i64 },
+
+    #[error("End of aedat stream")]
+    EndOfStream,
 }
 
 impl Reconstructor {
@@ -85,7 +494,57 @@ impl Reconstructor {
         target_latency: f64,
         simulate_latency: bool,
     ) -> Result<Reconstructor, ReconstructorError> {
-        // assert!(!(deblur_only && events_only));
+        if deblur_only && events_only {
+            return Err(ArgumentError(
+                "deblur_only and events_only are mutually exclusive".to_string(),
+            ));
+        }
+        ReconstructorBuilder::new()
+            .directory(directory)
+            .aedat_filename_0(aedat_filename_0)
+            .aedat_filename_1(aedat_filename_1)
+            .mode(mode)
+            .start_c(start_c)
+            .optimize_c(optimize_c, optimize_c_frequency)
+            .optimize_controller(optimize_controller)
+            .show_display(display)
+            .show_blurred_display(blurred_display)
+            .output_fps(output_fps)
+            .output_mode(match deblur_only {
+                true => OutputMode::SharpFrameOnly,
+                false => OutputMode::FullSequence,
+            })
+            .events_only(events_only)
+            .target_latency(target_latency)
+            .simulate_latency(simulate_latency)
+            .build()
+            .await
+    }
+
+    /// Build a reconstructor from a [`ReconstructorConfig`], reading from the given source
+    pub async fn from_config(
+        config: ReconstructorConfig,
+        source: InputSource,
+    ) -> Result<Reconstructor, ReconstructorError> {
+        config.validate()?;
+        let InputSource {
+            mode,
+            directory,
+            aedat_filename_0,
+            aedat_filename_1,
+        } = source;
+        if config.deterministic && mode != "file" {
+            return Err(ArgumentError(
+                "Deterministic mode requires a file source".to_string(),
+            ));
+        }
+        // Only a file has a known length to measure progress against
+        let input_bytes = match mode.as_str() {
+            "file" => std::fs::metadata(directory.clone() + "/" + &aedat_filename_0)
+                .ok()
+                .map(|metadata| metadata.len()),
+            _ => None,
+        };
         let mut decoder_0 = match mode.as_str() {
             "file" => {
                 Decoder::new_from_file(Path::new(&(directory.clone() + "/" + &aedat_filename_0)))?
@@ -98,7 +557,6 @@ impl Reconstructor {
             _ => return Err(ArgumentError("Invalid source mode".to_string())),
         };
 
-        assert!(target_latency > 0.0);
         let (height, width) = split_camera_info(&decoder_0.id_to_stream[&0]);
 
         let decoder_1 = match mode.as_str() {
@@ -113,44 +571,90 @@ impl Reconstructor {
             _ => return Err(ArgumentError("Invalid source mode".to_string())),
         };
 
-        let mut event_counter = Mat::default();
-
-        // Signed integers, to allow for negative polarities dominating the interval
-        unsafe {
-            event_counter.create_rows_cols(height as i32, width as i32, CV_8S)?;
-        }
-
-        let packet_queue: VecDeque<TimestampedPacket> = VecDeque::new();
-        let output_frame_length = (1000000.0 / output_fps) as i64;
-        println!(
-            "EDI output frame length: {} microseconds",
-            output_frame_length
-        );
-
-        // Get the first frame and ignore events before it
-        if decoder_1.is_none() {
+        // Get the first frame and ignore events before it. If the caller guarantees the file starts
+        // with a frame, skip this so that the first frame and the events after it are kept. Live
+        // sources always keep their events, since the first frame may take a while to arrive.
+        if decoder_1.is_none() && !config.skip_priming && config.require_initial_frame {
             loop {
-                if let Ok(p) = decoder_0.next().unwrap() {
-                    if matches!(
-                        decoder_0.id_to_stream.get(&p.stream_id).unwrap().content,
-                        StreamContent::Frame
-                    ) {
-                        match aedat::frame_generated::size_prefixed_root_as_frame(&p.buffer) {
-                            Ok(result) => result,
-                            Err(_) => {
-                                panic!("the packet does not have a size prefix");
-                            }
-                        };
-                        break;
-                    }
+                let p = match decoder_0.next() {
+                    Some(result) => result?,
+                    None => return Err(ReconstructorError::EndOfStream),
+                };
+                if matches!(
+                    decoder_0
+                        .id_to_stream
+                        .get(&p.stream_id)
+                        .map(|stream| stream.content),
+                    Some(StreamContent::Frame)
+                ) {
+                    aedat::frame_generated::size_prefixed_root_as_frame(&p.buffer)
+                        .map_err(|e| ReconstructorError::PacketError(e.to_string()))?;
+                    break;
                 }
             }
         }
 
+        // The decoders are handed off to the packet threads, so keep their stream tables
+        let mut streams: Vec<StreamInfo> = decoder_0
+            .id_to_stream
+            .iter()
+            .chain(
+                decoder_1
+                    .iter()
+                    .flat_map(|decoder| decoder.id_to_stream.iter()),
+            )
+            .map(|(id, stream)| StreamInfo {
+                id: *id,
+                content: stream.content,
+                width: stream.width,
+                height: stream.height,
+            })
+            .collect();
+        streams.sort_by_key(|stream| stream.id);
+
+        let packet_receiver = match config.deterministic {
+            true => setup_serial_packets(decoder_0),
+            false => setup_packet_threads(
+                decoder_0,
+                decoder_1,
+                config.simulate_latency,
+                config
+                    .end_of_stream_grace
+                    .map(|grace_ms| Duration::from_secs_f64(grace_ms / 1000.0)),
+            ),
+        };
+
+        Reconstructor::from_packet_receiver(
+            config,
+            mode,
+            packet_receiver,
+            (height, width),
+            streams,
+            input_bytes,
+        )
+        .await
+    }
+
+    /// Build a reconstructor around packets that are already being received, such as from the
+    /// packet threads of [`Reconstructor::from_config`], which has validated `config`. The
+    /// events before the first APS frame are queued up and sorted against it.
+    pub(crate) async fn from_packet_receiver(
+        config: ReconstructorConfig,
+        mode: String,
+        packet_receiver: PacketReceiver,
+        (height, width): (u16, u16),
+        streams: Vec<StreamInfo>,
+        input_bytes: Option<u64>,
+    ) -> Result<Reconstructor, ReconstructorError> {
+        let packet_queue: VecDeque<TimestampedPacket> = VecDeque::new();
+        let output_frame_length = config.timebase_hz / config.output_fps;
+        println!("EDI output frame length: {:.3} ticks", output_frame_length);
+
         let mut r = Reconstructor {
-            show_display: display,
-            show_blurred_display: blurred_display,
-            packet_receiver: setup_packet_threads(decoder_0, decoder_1, simulate_latency),
+            show_display: config.show_display,
+            debug_display: config.debug_display,
+            show_blurred_display: config.show_blurred_display,
+            packet_receiver,
             height,
             width,
             packet_queue,
@@ -158,34 +662,143 @@ impl Reconstructor {
                 height,
                 width,
                 output_frame_length,
-                start_c,
-                optimize_c,
-                optimize_c_frequency,
-                deblur_only,
-                events_only,
+                config.start_c,
+                config.optimize_c,
+                config.optimize_c_frequency,
+                config.output_mode,
+                config.events_only,
+                config.lambda,
+                config.min_c,
+                config.max_c,
+                config.n_points,
             ),
             latent_image_queue: Default::default(),
-            output_fps,
-            optimize_c,
-            optimize_controller,
-            target_latency,
+            output_fps: config.output_fps,
+            c_optimization: match config.optimize_c {
+                true => COptimizationState::Active,
+                false => COptimizationState::Disabled,
+            },
+            optimize_controller: config.optimize_controller,
+            target_latency: config.target_latency,
             mode,
             events_return_before: vec![],
             events_return_after: vec![],
+            output_event_counts: false,
+            last_event_count_map: None,
+            estimate_variance: false,
+            last_variance_map: None,
+            keep_log_latents: false,
+            last_log_latents: None,
+            fps_resolution_check: FpsResolutionCheck::Warn,
+            fps_resolution_checked: false,
+            input_histogram_bins: None,
+            last_input_histogram: None,
+            imu_queue: VecDeque::new(),
+            max_desync: None,
+            latest_event_t: None,
+            desync_dropped_frames: 0,
+            min_frame_gap: None,
+            pending_frame: None,
+            merged_frames: 0,
+            output_crop_margin: 0,
+            supersamples: 1,
+            shutter_angle: 360.0,
+            supersample_buffer: vec![],
+            display_manager: None,
+            quality_check_threshold: None,
+            last_frame_stats: None,
+            input_temporal_average: 1,
+            input_history: VecDeque::new(),
+            trigger_window: None,
+            trigger_timestamps: VecDeque::new(),
+            previous_returned: None,
+            last_returned: None,
+            reverse: false,
+            latent_carry: LatentCarry::Always,
+            density_blend: None,
+            parallel_frames: 1,
+            end_of_stream: false,
+            truncated_exposure: TruncatedExposure::BestEffort,
+            input_bytes,
+            streams,
+            output_taps: vec![],
+            retain_frame_events: true,
+            output_depth: CV_64F,
         };
-        let blur_info = fill_packet_queue_to_frame(
-            &mut r.packet_receiver,
-            &mut r.packet_queue,
-            r.height as i32,
-            r.width as i32,
-        )
-        .await
-        .unwrap();
+        r.event_adder.timebase_hz = config.timebase_hz;
+        r.set_edge_window_intervals(config.edge_window_intervals);
+        r.set_edge_decay_tau(config.edge_decay_tau)?;
+        r.set_event_trim_intervals(config.event_trim_intervals);
+        r.set_rolling_shutter_line_delay(config.rolling_shutter_line_delay);
+        r.set_output_event_counts(config.output_event_counts);
+        r.set_estimate_variance(config.estimate_variance);
+        r.set_keep_log_latents(config.keep_log_latents);
+        r.set_fps_resolution_check(config.fps_resolution_check);
+        r.set_truncated_exposure(config.truncated_exposure);
+        r.set_input_histogram_bins(config.input_histogram_bins)?;
+        r.set_output_intensity_limit(config.output_intensity_limit);
+        r.set_log_space_intermediates(config.log_space_intermediates);
+        r.set_max_desync(config.max_desync);
+        r.set_min_frame_gap(config.min_frame_gap)?;
+        r.set_min_events_for_optimize(config.min_events_for_optimize);
+        r.set_latent_reference(config.latent_reference);
+        r.set_use_imu(config.use_imu);
+        r.set_gradient_operator(config.gradient_operator);
+        r.set_edge_threshold(config.edge_threshold);
+        r.set_quality_check_threshold(config.quality_check_threshold);
+        r.set_max_intermediate_frames(config.max_intermediate_frames)?;
+        r.set_input_temporal_average(config.input_temporal_average)?;
+        r.set_display_views(&config.display_views);
+        r.set_trigger_gating(config.trigger_window);
+        r.set_reverse(config.reverse);
+        r.set_latent_carry(config.latent_carry)?;
+        r.set_log_polar(config.log_polar)?;
+        r.set_reserve_event_queues(config.reserve_event_queues);
+        r.set_c_smoothing(config.c_smoothing)?;
+        r.set_hot_pixel_threshold(config.hot_pixel_threshold)?;
+        r.set_fill_interval_gaps(config.fill_interval_gaps);
+        r.set_long_exposure(config.long_exposure);
+        r.set_density_blend(config.density_blend)?;
+        r.set_retain_frame_events(config.retain_frame_events);
+        r.set_output_depth(if config.single_precision_output {
+            CV_32F
+        } else {
+            CV_64F
+        })?;
+        if config.super_resolution_factor != 1.0 {
+            r.set_super_resolution_factor(config.super_resolution_factor)?;
+        }
+        r.set_temporal_supersampling(config.supersamples, config.shutter_angle)?;
+        r.set_output_crop_margin(config.output_crop_margin)?;
+        r.set_parallel_frames(config.parallel_frames)?;
+
+        // A source without any APS frames can't be deblurred. The events that arrive before the
+        // first frame are queued up, and sorted against it.
+        let blur_info = match config.initial_frame_timeout {
+            None => r.fill_packet_queue_to_frame().await,
+            Some(timeout_ms) => tokio::time::timeout(
+                Duration::from_secs_f64(timeout_ms / 1000.0),
+                r.fill_packet_queue_to_frame(),
+            )
+            .await
+            .map_err(|_| {
+                ArgumentError(format!(
+                    "No APS frame arrived within the initial frame timeout of {} ms",
+                    timeout_ms
+                ))
+            })?,
+        }
+        .map_err(|e| match e {
+            ReconstructorError::EndOfStream => ReconstructorError::NoFrameBuffered,
+            e => e,
+        })?;
 
         let frame_exp_dt = blur_info.exposure_end_t - blur_info.exposure_begin_t;
-        if frame_exp_dt < r.event_adder.interval_t && r.event_adder.deblur_only {
-            r.event_adder.interval_t = max(frame_exp_dt, 1);
-            r.output_fps = 1.0e6 / frame_exp_dt as f64;
+        if frame_exp_dt < r.event_adder.interval_t
+            && r.event_adder.output_mode == OutputMode::SharpFrameOnly
+        {
+            r.event_adder.set_interval_length(frame_exp_dt as f64);
+            r.output_fps = config.timebase_hz / frame_exp_dt as f64;
         }
         r.event_adder.blur_info = Some(blur_info);
 
@@ -193,457 +806,2419 @@ impl Reconstructor {
     }
 
     pub fn set_optimize_c(&mut self, optimize: bool, frequency: u32) {
-        self.optimize_c = optimize;
-        self.event_adder.optimize_c = optimize;
+        self.c_optimization = match optimize {
+            true => COptimizationState::Active,
+            false => COptimizationState::Disabled,
+        };
+        self.event_adder.optimize_c = self.c_optimization.optimizes();
         self.event_adder.optimize_c_frequency = frequency;
     }
 
-    /// Get the next reconstructed image
-    pub async fn next(&mut self, with_events: bool) -> IterRet {
-        if with_events {
-            assert!(self.event_adder.deblur_only);
+    /// Get whether c is currently being optimized, and if not, why
+    pub fn c_optimization_state(&self) -> COptimizationState {
+        self.c_optimization
+    }
+
+    /// Accumulate the edge image for the c-optimization energy over windows of `intervals`
+    /// reconstruction intervals, rather than over the whole exposure. This keeps fast motion from
+    /// smearing the reference edges during long exposures. `None` uses the whole exposure.
+    pub fn set_edge_window_intervals(&mut self, intervals: Option<u32>) {
+        self.event_adder.edge_window_intervals = intervals;
+    }
+
+    /// Set the time constant, in ticks, over which an event's weight in the c-optimization edge
+    /// image decays with its distance in time from the previous event at its pixel. A shorter
+    /// time constant concentrates the edges for fast motion. `None` (the default) decays over one
+    /// second, as in the original paper.
+    pub fn set_edge_decay_tau(&mut self, tau: Option<f64>) -> Result<(), ReconstructorError> {
+        if let Some(tau) = tau {
+            if !tau.is_finite() || tau <= 0.0 {
+                return Err(ArgumentError(
+                    "Edge decay time constant must be positive".to_string(),
+                ));
+            }
         }
-        return match self.latent_image_queue.pop_front() {
-            // If we have a queue of images already, just return the next one
-            Some(image) => Some(Ok((image, None, None, None))), // TODO: what about event queues?
+        self.event_adder.edge_decay_tau = tau;
+        Ok(())
+    }
 
-            // Else we need to rebuild the queue
-            _ => {
-                let now = Instant::now();
+    /// Discard events more than `intervals` reconstruction intervals before or after the exposure
+    /// of the blurred image they're sorted against, to save memory and time. Note that in the
+    /// full-sequence mode, the events after an exposure are used to generate the intermediate
+    /// frames before the next one, so trimming them leaves those frames without events. `None`
+    /// (the default) keeps all events.
+    pub fn set_event_trim_intervals(&mut self, intervals: Option<i64>) {
+        self.event_adder.event_trim_intervals = intervals;
+    }
 
-                if self.event_adder.next_blur_info.is_some() {
-                    mem::swap(
-                        &mut self.event_adder.blur_info,
-                        &mut self.event_adder.next_blur_info,
-                    );
-                    self.event_adder.next_blur_info = None;
-                }
+    /// Treat the APS frames as rolling-shutter exposures, where each row's exposure window starts
+    /// `line_delay` ticks after the previous row's. Every row of a latent image is then
+    /// reconstructed at the same instant, removing the skew. `None` (the default) treats the
+    /// frames as global-shutter exposures.
+    /// Rolling-shutter correction is unavailable in log-polar mode (see
+    /// [`Reconstructor::set_log_polar`]), since the rows of the log-polar grid aren't sensor rows.
+    pub fn set_rolling_shutter_line_delay(&mut self, line_delay: Option<i64>) {
+        self.event_adder.rolling_shutter_line_delay = line_delay;
+    }
 
-                // let join_handle: thread::JoinHandle<_> = thread::spawn(|| {
-                let latency = match self.get_more_images().await {
-                    Ok(a) => a,
-                    Err(_) => return None,
-                };
-                // });
-                let running_fps = self.latent_image_queue.len() as f64
-                    / now.elapsed().as_millis() as f64
-                    * 1000.0;
-                print!(
-                    "\r{} frames in  {}ms -- Current FPS: {:.2}, Current c: {:.5}",
-                    self.latent_image_queue.len(),
-                    now.elapsed().as_millis(),
-                    running_fps,
-                    self.event_adder.current_c
-                );
-                if self.optimize_controller
-                    && ((1000000.0 / running_fps) as i64 - self.event_adder.interval_t).abs()
-                        > 1000000 / 50000
-                {
-                    // self.event_adder.interval_t =
-                    //     (1000000.0 / running_fps).max(1000000.0 / self.output_fps) as i64;
-                    // print!(" Target FPS: {}", 1000000 / self.event_adder.interval_t);
-                    // self.event_adder.optimize_c = false;
-                } else {
-                    // self.event_adder.optimize_c = self.optimize_c;
-                }
-                io::stdout().flush().unwrap();
-                match self.latent_image_queue.pop_front() {
-                    None => {
-                        panic!("No images in the returned queue")
-                    }
-                    Some(image) => {
-                        // TODO: handle error
-                        // debug_assert!(
-                        //     self.event_adder
-                        //         .blur_info
-                        //         .as_ref()
-                        //         .unwrap()
-                        //         .exposure_begin_t
-                        //         < self.event_adder.last_interval_start_timestamp
-                        // );
-                        //
-                        // debug_assert!({
-                        //     let true_frame_dt =
-                        //         self.event_adder.blur_info.as_ref().unwrap().exposure_end_t
-                        //             - self
-                        //                 .event_adder
-                        //                 .blur_info
-                        //                 .as_ref()
-                        //                 .unwrap()
-                        //                 .exposure_begin_t;
-                        //     let img_dt_secs = true_frame_dt as f64 / 1000000.0;
-                        //     let frame_length_secs = 1.0 / self.output_fps as f64;
-                        //     img_dt_secs >= frame_length_secs
-                        // });
+    /// Keep a map of how many events occurred at each pixel during the exposure of the most
+    /// recently deblurred frame. Pixels with few events are mostly interpolated from the blurred
+    /// input, so this is useful for masking low-confidence regions downstream.
+    pub fn set_output_event_counts(&mut self, output_event_counts: bool) {
+        self.output_event_counts = output_event_counts;
+        if !output_event_counts {
+            self.last_event_count_map = None;
+        }
+    }
 
-                        return match with_events {
-                            true => Some(Ok((
-                                image,
-                                Some(
-                                    self.event_adder
-                                        .blur_info
-                                        .as_ref()
-                                        .unwrap()
-                                        .packet_timestamp,
-                                ),
-                                Some((
-                                    self.event_adder.current_c,
-                                    self.events_return_before.clone(),
-                                    self.events_return_after.clone(),
-                                    self.event_adder
-                                        .blur_info
-                                        .as_ref()
-                                        .unwrap()
-                                        .exposure_begin_t,
-                                    self.event_adder.last_interval_start_timestamp,
-                                )),
-                                Some(latency),
-                            ))),
-                            false => Some(Ok((
-                                image,
-                                Some(
-                                    self.event_adder
-                                        .blur_info
-                                        .as_ref()
-                                        .unwrap()
-                                        .packet_timestamp,
-                                ),
-                                None,
-                                Some(latency),
-                            ))),
-                        };
-                    }
-                }
-            }
+    /// Get the per-pixel event counts for the most recently deblurred frame, if enabled with
+    /// [`Reconstructor::set_output_event_counts`]
+    pub fn last_event_count_map(&self) -> Option<&Mat> {
+        self.last_event_count_map.as_ref()
+    }
+
+    /// Estimate the per-pixel uncertainty of the reconstruction of each APS frame. See
+    /// [`Reconstructor::last_variance_map`].
+    pub fn set_estimate_variance(&mut self, estimate_variance: bool) {
+        self.estimate_variance = estimate_variance;
+        if !estimate_variance {
+            self.last_variance_map = None;
+        }
+    }
+
+    /// Get the per-pixel variance estimate for the most recently deblurred frame, if enabled with
+    /// [`Reconstructor::set_estimate_variance`]. It's the variance of each pixel's latent
+    /// intensity across the intervals of the frame, plus a prior of `(c * mean)^2 / (1 + n)` for a
+    /// pixel with `n` events during the exposure. Pixels with many events and a consistent
+    /// integration are thus the most confident. The map has the same geometry as the output
+    /// frames.
+    pub fn last_variance_map(&self) -> Option<Mat> {
+        self.last_variance_map.clone()
+    }
+
+    fn variance_map(&self, deblur_return: &DeblurReturn) -> Result<Mat, ReconstructorError> {
+        let counts = self.event_adder.event_count_map();
+        let mut sum = DMatrix::<f64>::zeros(counts.nrows(), counts.ncols());
+        let mut sum_squares = sum.clone();
+        for mat in &deblur_return.ret_vec {
+            let latent = mat_to_dmatrix(mat)?;
+            sum_squares += latent.component_mul(&latent);
+            sum += latent;
+        }
+        let n = deblur_return.ret_vec.len().max(1) as f64;
+        let mean = sum / n;
+        let spread = (sum_squares / n - mean.component_mul(&mean)).map(|v| v.max(0.0));
+        let c = deblur_return.found_c;
+        let prior = mean.zip_map(&counts, |mean, count| (c * mean).powi(2) / (1.0 + count));
+
+        let variance = dmatrix_to_mat(&(spread + prior))?;
+        let variance = match self.event_adder.log_polar {
+            true => warp_log_polar(&variance, true)?,
+            false => variance,
         };
+        self.crop_output(variance)
     }
 
-    /// Generates reconstructed images from the next packet of events
-    async fn get_more_images(&mut self) -> Result<u128, SimpleError> {
-        while let Some(p) = self.packet_queue.pop_front() {
-            match FromPrimitive::from_u32(p.packet.stream_id) {
-                Some(StreamContent::Frame) => {
-                    panic!("Unhandled frame?")
-                }
-                Some(StreamContent::Events) => {
-                    self.event_adder.sort_events(p.packet);
-                }
-                _ => {
-                    println!("debug 2")
-                }
-            }
+    /// Diagnostic: keep the log of each interval's latent image for the most recently deblurred
+    /// frame, to study the integration trajectory. See [`Reconstructor::last_log_latents`].
+    pub fn set_keep_log_latents(&mut self, keep: bool) {
+        self.keep_log_latents = keep;
+        if !keep {
+            self.last_log_latents = None;
         }
+    }
 
-        let deblur_res = {
-            if self.show_blurred_display {
-                let tmp_blurred_mat =
-                    Mat::try_from_cv(&self.event_adder.blur_info.as_ref().unwrap().blurred_image)
-                        .unwrap();
-                _show_display_force("blurred input", &tmp_blurred_mat, 1, false);
+    /// Get the log latent images of each interval of the most recently deblurred frame, in
+    /// order, if enabled with [`Reconstructor::set_keep_log_latents`]. These are on the
+    /// reconstruction grid, before any density blending, log-polar unwarping, or cropping, so
+    /// exponentiating one gives the corresponding latent image as deblurred. Pixels that
+    /// reconstruct to black are `-inf`.
+    pub fn last_log_latents(&self) -> Option<&[Mat]> {
+        self.last_log_latents.as_deref()
+    }
+
+    /// Keep a histogram of the pixel values of each blurred APS frame, with `bins` equal-width bins
+    /// over the 8-bit range. The first and last bins show how much of the input is underexposed or
+    /// saturated, which limits what deblurring can recover. `None` (the default) disables it.
+    pub fn set_input_histogram_bins(
+        &mut self,
+        bins: Option<usize>,
+    ) -> Result<(), ReconstructorError> {
+        if let Some(bins) = bins {
+            if bins == 0 || bins > 256 {
+                return Err(ArgumentError(
+                    "Input histogram must have between 1 and 256 bins".to_string(),
+                ));
             }
-            deblur_image(&mut self.event_adder)
-        };
+        }
+        self.input_histogram_bins = bins;
+        self.last_input_histogram = None;
+        Ok(())
+    }
 
-        let latency = (Instant::now()
-            - self
-                .event_adder
-                .blur_info
-                .as_ref()
-                .unwrap()
-                .packet_timestamp)
-            .as_millis();
-        println!("  Latency is {}ms", latency);
+    /// Get the histogram of the most recently read blurred APS frame, if enabled with
+    /// [`Reconstructor::set_input_histogram_bins`]. Only the pixels of the frame's region of
+    /// interest are counted.
+    pub fn last_input_histogram(&self) -> Option<Vec<u32>> {
+        self.last_input_histogram.clone()
+    }
 
-        match (
-            self.mode.as_str(),
-            self.optimize_controller,
-            self.optimize_c,
-            latency > self.target_latency as u128,
-            self.event_adder.optimize_c,
-        ) {
-            ("file", _, _, _, _) => {
-                // Don't do anything, since latency doesn't make sense in this context. (File reads
-                // happen instantaneously)
-            }
-            (_, true, true, true, true) => {
-                println!("DISABLING C-OPTIMIZATION");
-                self.event_adder.optimize_c = false;
-            }
-            (_, true, true, false, false) => {
-                println!("ENABLING C-OPTIMIZATION");
-                self.event_adder.optimize_c = true;
-            }
-            (_, _, _, _, _) => {}
-        }
-
-        let next_blur_info = match fill_packet_queue_to_frame(
-            &mut self.packet_receiver,
-            &mut self.packet_queue,
-            self.height as i32,
-            self.width as i32,
-        )
-        .await
-        {
-            Ok(blur_info) => {
-                let frame_exp_dt = blur_info.exposure_end_t - blur_info.exposure_begin_t;
-                if frame_exp_dt < self.event_adder.interval_t && self.event_adder.deblur_only {
-                    self.event_adder.interval_t = max(frame_exp_dt, 1);
-                    self.output_fps = 1.0e6 / frame_exp_dt as f64;
-                }
-                Some(blur_info)
-            }
+    /// Take the IMU samples received since the last call, in order. They're read alongside the
+    /// APS frames, so they arrive up to one frame ahead of the reconstructed output.
+    pub fn drain_imu(&mut self) -> Vec<ImuSample> {
+        self.imu_queue.drain(..).collect()
+    }
+
+    /// Clamp the output latent images to `[0, limit]`, to avoid blown-out pixels where events
+    /// over-integrate. `None` (the default) preserves the full dynamic range.
+    pub fn set_output_intensity_limit(&mut self, limit: Option<f64>) {
+        self.event_adder.output_intensity_limit = limit;
+    }
+
+    /// Generate the intermediate frames between APS frames in log space, adding the scaled event
+    /// counts to the log of the latent image and taking a single exp, rather than multiplying the
+    /// latent image by the exp of the scaled event counts.
+    pub fn set_log_space_intermediates(&mut self, log_space: bool) {
+        self.event_adder.log_space_intermediates = log_space;
+        let latent_image = self.event_adder.latent_image.clone();
+        self.event_adder.set_latent_image(latent_image);
+    }
+
+    /// Choose between one sharp frame per APS frame and the full high-rate latent sequence. This
+    /// takes effect on the next APS frame.
+    pub fn set_output_mode(&mut self, output_mode: OutputMode) {
+        self.event_adder.output_mode = output_mode;
+    }
+
+    pub fn output_mode(&self) -> OutputMode {
+        self.event_adder.output_mode
+    }
+
+    /// When reading the events and frames from two separate streams, drop any APS frame whose
+    /// exposure ended more than `max_desync` microseconds before the latest event received. This
+    /// happens when the frame stream lags behind (e.g., from dropped packets on its socket), and
+    /// the events for that frame have already been consumed. Dropping the stale frames lets the
+    /// two streams re-synchronize. `None` (the default) keeps every frame. Has no effect when
+    /// reading from a file.
+    pub fn set_max_desync(&mut self, max_desync: Option<i64>) {
+        self.max_desync = max_desync;
+    }
+
+    /// Get the number of APS frames dropped because their stream fell too far behind the event
+    /// stream. See [`Reconstructor::set_max_desync`].
+    pub fn desync_dropped_frames(&self) -> u64 {
+        self.desync_dropped_frames
+    }
+
+    /// Merge APS frames whose exposure begins less than `min_frame_gap` microseconds after the
+    /// previous frame's exposure ends, and reconstruct them as one longer exposure. Reconstructing
+    /// each of a burst of close frames is wasteful, and each has few events to optimize c with.
+    /// The merged blurred image averages the frames, weighted by their exposure times. `None`
+    /// (the default) reconstructs every frame. Takes effect from the next frame read.
+    pub fn set_min_frame_gap(
+        &mut self,
+        min_frame_gap: Option<i64>,
+    ) -> Result<(), ReconstructorError> {
+        if min_frame_gap.map_or(false, |gap| gap <= 0) {
+            return Err(ArgumentError(
+                "Minimum frame gap must be positive".to_string(),
+            ));
+        }
+        self.min_frame_gap = min_frame_gap;
+        Ok(())
+    }
+
+    /// Get the number of APS frames merged into the frame before them. See
+    /// [`Reconstructor::set_min_frame_gap`].
+    pub fn merged_frames(&self) -> u64 {
+        self.merged_frames
+    }
+
+    /// Get the number of events dropped because they arrived out of order, with timestamps before
+    /// the latest reconstructed latent image
+    pub fn late_events_dropped(&self) -> u64 {
+        self.event_adder.late_events_dropped
+    }
+
+    /// Skip the c-optimization for APS frames with fewer than `min_events` events during their
+    /// exposure, reusing the previous c instead. The energy is too noisy to find c reliably with
+    /// only a few events. Defaults to 0 (always optimize).
+    pub fn set_min_events_for_optimize(&mut self, min_events: usize) {
+        self.event_adder.min_events_for_optimize = min_events;
+    }
 
-            Err(_) => None,
+    /// Show the given debug views side by side in a single window, rather than in separate
+    /// windows. An empty list goes back to separate windows.
+    pub fn set_display_views(&mut self, views: &[DisplayView]) {
+        self.display_manager = match views.is_empty() {
+            true => None,
+            false => Some(DisplayManager::new("EDI", views)),
         };
+    }
 
-        match (deblur_res, next_blur_info) {
-            (None, _) => {
-                panic!("No images returned from deblur call")
+    /// Choose the reference instant within each APS exposure. Takes effect on the next APS frame.
+    pub fn set_latent_reference(&mut self, latent_reference: LatentReference) {
+        self.event_adder.latent_reference = latent_reference;
+    }
+
+    /// Use the gyroscope of the camera's IMU stream, time-aligned with each exposure, to find the
+    /// instant of least motion for [`LatentReference::MinimumMotion`]. The event rate also rises
+    /// with motion in the scene, while the angular speed measures only the camera's rotation.
+    /// Exposures without IMU samples fall back to the event rate.
+    pub fn set_use_imu(&mut self, use_imu: bool) {
+        self.event_adder.use_imu = use_imu;
+        if !use_imu {
+            self.event_adder.imu_angular_speed.clear();
+        }
+    }
+
+    /// Offset the coordinates of the events from the given stream, e.g., for a region-of-interest
+    /// event stream whose coordinates are relative to its region. Events that land outside the
+    /// sensor are discarded. The offsets of APS frames are read from the frames themselves.
+    pub fn set_stream_offset(&mut self, stream_id: u32, offset_x: i16, offset_y: i16) {
+        match (offset_x, offset_y) {
+            (0, 0) => self.event_adder.stream_offsets.remove(&stream_id),
+            offset => self.event_adder.stream_offsets.insert(stream_id, offset),
+        };
+    }
+
+    /// Check each deblurred frame against its blurred input. Deblurring preserves the total light,
+    /// so the mean intensity of the latent images during the exposure should be close to that of
+    /// the blurred image. Frames where the relative difference exceeds `threshold` are flagged in
+    /// their [`FrameStats`], which usually means a badly chosen c. `None` (the default) disables
+    /// the check.
+    pub fn set_quality_check_threshold(&mut self, threshold: Option<f64>) {
+        self.quality_check_threshold = threshold;
+    }
+
+    /// Get the fraction of the input file consumed so far, in `[0, 1]`, for progress reporting.
+    /// This is measured by the bytes of the packets read, so it runs slightly ahead of the frames
+    /// returned. Returns `None` for live sources, which have no known length.
+    pub fn progress(&self) -> Option<f64> {
+        match self.input_bytes {
+            Some(total) if total > 0 => {
+                Some((self.packet_receiver.bytes_received as f64 / total as f64).min(1.0))
             }
-            (Some(deblur_return), Some(next_blur_info)) => {
-                self.event_adder.latent_image = deblur_return.ret_vec.last().unwrap().clone();
-                self.event_adder.last_interval_start_timestamp =
-                    deblur_return.last_interval_start_timestamp;
-                self.latent_image_queue
-                    .append(&mut VecDeque::from(deblur_return.ret_vec));
+            _ => None,
+        }
+    }
 
-                {
-                    /*
-                    set the 'return after' queue. Keeps the events from during the exposure time,
-                    and the events occurring afterwards.
-                    */
-                    let mut tmp_vec = vec![];
-                    mem::swap(&mut tmp_vec, &mut self.event_adder.event_during_queue);
-                    self.events_return_after = tmp_vec;
-                    self.events_return_after
-                        .append(&mut self.event_adder.event_after_queue.clone());
-                }
+    /// Get the statistics of the most recently deblurred APS frame
+    pub fn last_frame_stats(&self) -> Option<&FrameStats> {
+        self.last_frame_stats.as_ref()
+    }
 
-                {
-                    // set the 'return before' queue
-                    let mut tmp_vec = vec![];
-                    mem::swap(&mut tmp_vec, &mut self.event_adder.event_before_queue);
-                    self.events_return_before = tmp_vec;
-                }
+    fn frame_stats(&self, deblur_return: &DeblurReturn) -> Result<FrameStats, ReconstructorError> {
+        let blur_info = self
+            .event_adder
+            .blur_info
+            .as_ref()
+            .ok_or(ReconstructorError::NoFrameBuffered)?;
+        let blurred_mean = blur_info.blurred_image.mean();
 
-                self.event_adder.reset_event_queues();
-                self.event_adder.next_blur_info = Some(next_blur_info);
-                self.event_adder.current_c = deblur_return.found_c;
+        // Only the latents within the exposure are comparable to the blurred image
+        let mut latent_means = vec![];
+        for (mat, t) in deblur_return
+            .ret_vec
+            .iter()
+            .zip(deblur_return.timestamps.iter())
+        {
+            if *t >= blur_info.exposure_begin_t {
+                latent_means.push(mean(mat, &no_array())?[0]);
+            }
+        }
+        let latent_mean = latent_means.iter().sum::<f64>() / latent_means.len().max(1) as f64;
+
+        let quality_warning = match self.quality_check_threshold {
+            Some(threshold)
+                if (latent_mean - blurred_mean).abs() > threshold * blurred_mean.max(1e-6) =>
+            {
+                eprintln!(
+                    "\nQuality warning: latent mean {:.4} vs. blurred mean {:.4} (c = {:.5})",
+                    latent_mean, blurred_mean, deblur_return.found_c
+                );
+                true
             }
-            _ => return Err(SimpleError::new("End of aedat file")),
+            _ => false,
         };
 
-        Ok(latency)
+        Ok(FrameStats {
+            exposure_begin_t: blur_info.exposure_begin_t,
+            exposure_end_t: blur_info.exposure_end_t,
+            c: deblur_return.found_c,
+            events_during_exposure: self.event_adder.event_during_queue.len(),
+            blurred_mean,
+            latent_mean,
+            quality_warning,
+            exposure_truncated: self.exposure_truncated(),
+        })
     }
-}
 
-/// Read packets until the next APS frame is reached (inclusive)
-async fn fill_packet_queue_to_frame(
-    packet_receiver: &mut PacketReceiver,
-    packet_queue: &mut VecDeque<TimestampedPacket>,
-    height: i32,
-    width: i32,
-) -> Result<BlurInfo, SimpleError> {
-    let blur_info = loop {
-        match packet_receiver.next().await {
-            Some(p) => {
-                if matches!(
-                    FromPrimitive::from_u32(p.packet.stream_id),
-                    Some(StreamContent::Frame)
-                ) {
-                    let frame =
-                        match aedat::frame_generated::size_prefixed_root_as_frame(&p.packet.buffer)
-                        {
-                            Ok(result) => result,
-                            Err(_) => {
-                                panic!("the packet does not have a size prefix");
-                            }
-                        };
+    /// Whether the stream has ended before the current frame's exposure did. Before the end of
+    /// the stream, the events after the exposure just haven't been sorted into this frame yet.
+    fn exposure_truncated(&self) -> bool {
+        let exposure_end_t = match self.event_adder.blur_info.as_ref() {
+            Some(blur_info) => blur_info.exposure_end_t,
+            None => return false,
+        };
+        let last_event_t = self
+            .event_adder
+            .event_after_queue
+            .last()
+            .or_else(|| self.event_adder.event_during_queue.last())
+            .map(|event| event.t());
+        self.end_of_stream && last_event_t.map_or(true, |t| t < exposure_end_t)
+    }
 
-                    let frame_px = frame.pixels().unwrap();
-                    let mut image = DMatrix::<f64>::zeros(height as usize, width as usize);
-                    for (row_idx, mut im_row) in image.row_iter_mut().enumerate() {
-                        for (col_idx, im_px) in im_row.iter_mut().enumerate() {
-                            *im_px = frame_px[row_idx * width as usize + col_idx] as f64 / 255.0;
-                        }
-                    }
+    /// Choose what to do with the final APS frame when the events end before its exposure does.
+    /// A final frame whose exposure is covered by the events is always reconstructed. Defaults to
+    /// [`TruncatedExposure::BestEffort`].
+    pub fn set_truncated_exposure(&mut self, truncated_exposure: TruncatedExposure) {
+        self.truncated_exposure = truncated_exposure;
+    }
 
-                    let blur_info = BlurInfo::new(
-                        image,
-                        frame.exposure_begin_t(),
-                        frame.exposure_end_t(),
-                        p.timestamp,
-                    );
+    /// Get the number of event timestamp ticks per second
+    pub fn timebase_hz(&self) -> f64 {
+        self.event_adder.timebase_hz
+    }
 
-                    break blur_info;
-                } else if matches!(
-                    FromPrimitive::from_u32(p.packet.stream_id),
-                    Some(StreamContent::Events)
-                ) {
-                    packet_queue.push_back(p);
-                }
-            }
-            None => return Err(SimpleError::new("End of aedat file")),
+    /// Set the number of event timestamp ticks per second, for sources whose timestamps aren't in
+    /// microseconds. This rescales the interval length for `output_fps`, so call it before
+    /// reconstructing. The latency simulation for file sources still assumes microseconds.
+    pub fn set_timebase_hz(&mut self, timebase_hz: f64) -> Result<(), ReconstructorError> {
+        if !timebase_hz.is_finite() || timebase_hz <= 0.0 {
+            return Err(ArgumentError("Timebase must be positive".to_string()));
+        }
+        self.event_adder.timebase_hz = timebase_hz;
+        self.event_adder
+            .set_interval_length(timebase_hz / (self.output_fps * self.supersamples as f64));
+        Ok(())
+    }
+
+    /// Choose the operator for the image gradients in the c-optimization energy
+    pub fn set_gradient_operator(&mut self, gradient_operator: GradientOperator) {
+        self.event_adder.gradient_operator = gradient_operator;
+    }
+
+    /// Choose how gradient magnitudes are thresholded into edges for the c-optimization energy
+    pub fn set_edge_threshold(&mut self, edge_threshold: EdgeThreshold) {
+        self.event_adder.edge_threshold = edge_threshold;
+    }
+
+    /// Generate at most `max` intermediate frames between two APS exposures. If there's a long gap
+    /// between APS frames (e.g., the frame stream dropped out), the intermediate frames stop
+    /// after `max`, rather than filling the whole gap at `output_fps`. `None` (the default) has no
+    /// limit.
+    pub fn set_max_intermediate_frames(
+        &mut self,
+        max: Option<usize>,
+    ) -> Result<(), ReconstructorError> {
+        if max == Some(0) {
+            return Err(ArgumentError(
+                "Must allow at least one intermediate frame".to_string(),
+            ));
         }
-    };
+        self.event_adder.max_intermediate_frames = max;
+        Ok(())
+    }
+
+    /// Average each blurred APS frame with the `frames - 1` frames before it, before deblurring.
+    /// This reduces the noise of the frames in low light, at the cost of some temporal blur in the
+    /// blurred image the latents are anchored to. Defaults to 1 (no averaging).
+    pub fn set_input_temporal_average(&mut self, frames: usize) -> Result<(), ReconstructorError> {
+        if frames == 0 {
+            return Err(ArgumentError(
+                "Must average at least one input frame".to_string(),
+            ));
+        }
+        self.input_temporal_average = frames;
+        self.input_history.truncate(frames - 1);
+        Ok(())
+    }
+
+    /// Average a new blurred image with the most recent ones
+    fn average_input(&mut self, image: DMatrix<f64>) -> DMatrix<f64> {
+        let mut average = image.clone();
+        for previous in &self.input_history {
+            average += previous;
+        }
+        average /= (self.input_history.len() + 1) as f64;
+
+        self.input_history.push_front(image);
+        self.input_history.truncate(self.input_temporal_average - 1);
+        average
+    }
 
-    match packet_receiver.next().await {
-        Some(p) => {
-            if matches!(
-                FromPrimitive::from_u32(p.packet.stream_id),
-                Some(StreamContent::Events)
-            ) {
-                packet_queue.push_back(p);
-            } else if p.packet.stream_id == 2 || p.packet.stream_id == 3 {
-                // Do nothing
-            } else {
-                return Err(SimpleError::new("TODO handle sparse events"));
+    /// Only reconstruct around the timestamps of the external trigger stream. APS frames whose
+    /// exposure isn't within `window` ticks of a trigger are skipped without deblurring, and only
+    /// output frames within `window` of a trigger are returned. Triggers must arrive before the
+    /// APS frames they mark. `None` (the default) reconstructs everything.
+    pub fn set_trigger_gating(&mut self, window: Option<i64>) {
+        self.trigger_window = window;
+        self.trigger_timestamps.clear();
+    }
+
+    /// Emit the latent images of each APS frame in reverse order, from the end of its intervals
+    /// back to the start. The frames themselves are still deblurred in file order; see
+    /// [`Reconstructor::reconstruct_reversed`] to reverse a whole recording.
+    pub fn set_reverse(&mut self, reverse: bool) {
+        self.reverse = reverse;
+    }
+
+    /// Choose whether the last latent image of each APS frame carries forward to the next one
+    pub fn set_latent_carry(
+        &mut self,
+        latent_carry: LatentCarry,
+    ) -> Result<(), ReconstructorError> {
+        if let LatentCarry::ResetOnSceneChange { threshold } = latent_carry {
+            if !threshold.is_finite() || threshold < 0.0 {
+                return Err(ArgumentError(
+                    "Scene change threshold must be non-negative".to_string(),
+                ));
             }
         }
-        None => return Err(SimpleError::new("End of aedat file")),
-    };
+        self.latent_carry = latent_carry;
+        Ok(())
+    }
 
-    Ok(blur_info)
-}
+    /// Deblur `frames` APS frames of a file in parallel, rather than one at a time. This speeds up
+    /// offline deblur-only runs on machines with more cores than a single frame can use. It
+    /// requires the frames to be independent: the "file" mode, [`OutputMode::SharpFrameOnly`],
+    /// [`LatentCarry::Never`], and c either fixed or optimized on every frame without smoothing.
+    /// Set those first. The output is the same as deblurring the frames one at a time, except that
+    /// the frames' events aren't retained, and the timing returned with each frame is that of
+    /// the last frame of its batch. `1` (the default) disables it.
+    pub fn set_parallel_frames(&mut self, frames: usize) -> Result<(), ReconstructorError> {
+        if frames == 0 {
+            return Err(ArgumentError(
+                "Must deblur at least one frame at a time".to_string(),
+            ));
+        }
+        if frames > 1 {
+            let independent_c = !self.event_adder.optimize_c
+                || (self.event_adder.optimize_c_frequency == 1
+                    && self.event_adder.c_smoothing.is_none());
+            if self.mode != "file"
+                || self.event_adder.output_mode != OutputMode::SharpFrameOnly
+                || self.latent_carry != LatentCarry::Never
+                || !independent_c
+            {
+                return Err(ArgumentError(
+                    "Parallel frames must be independent; see set_parallel_frames".to_string(),
+                ));
+            }
+            self.events_return_before = vec![];
+            self.events_return_after = vec![];
+        }
+        self.parallel_frames = frames;
+        Ok(())
+    }
 
-#[derive(Debug)]
-pub struct ReconstructionError {
-    message: String,
-}
+    /// Whether the latent image should restart from the next blurred frame, per the carry policy
+    fn resets_latent(&self, next_blur_info: &BlurInfo) -> bool {
+        match self.latent_carry {
+            LatentCarry::Always => false,
+            LatentCarry::Never => true,
+            LatentCarry::ResetOnSceneChange { threshold } => {
+                match self.event_adder.blur_info.as_ref() {
+                    Some(blur_info)
+                        if blur_info.blurred_image.shape()
+                            == next_blur_info.blurred_image.shape() =>
+                    {
+                        let difference =
+                            (&blur_info.blurred_image - &next_blur_info.blurred_image).abs();
+                        difference.mean() > threshold
+                    }
+                    _ => false,
+                }
+            }
+        }
+    }
 
-impl ReconstructionError {
-    pub fn _new(message: &str) -> ReconstructionError {
-        ReconstructionError {
-            message: message.to_string(),
+    /// Experimental: accumulate events and deblur in log-polar coordinates about the center of
+    /// the frame, then warp the latent images back to Cartesian coordinates for output. Rotation
+    /// about the center becomes translation in log-polar space, so this reduces the radial smearing
+    /// of edges for predominantly rotational camera motion. Resolution is lost towards the corners
+    /// of the frame. Takes effect on the next APS frame. Not compatible with rolling-shutter
+    /// correction.
+    pub fn set_log_polar(&mut self, enabled: bool) -> Result<(), ReconstructorError> {
+        if enabled && self.event_adder.rolling_shutter_line_delay.is_some() {
+            return Err(ArgumentError(
+                "Log-polar reconstruction does not support rolling-shutter correction".to_string(),
+            ));
         }
+        self.event_adder.log_polar = enabled;
+        Ok(())
     }
-}
 
-impl std::fmt::Display for ReconstructionError {
-    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(formatter, "{}", self.message)
+    /// Reserve capacity in the event queues for the running average number of events per APS
+    /// frame, rather than growing them from empty every frame. Saves repeated reallocation for
+    /// high event rate recordings, at the cost of holding the memory between frames.
+    pub fn set_reserve_event_queues(&mut self, reserve: bool) {
+        self.event_adder.reserve_event_queues = reserve;
     }
-}
 
-impl std::convert::From<ParseError> for ReconstructionError {
-    fn from(error: ParseError) -> Self {
-        ReconstructionError {
-            message: error.to_string(),
+    /// Smooth the optimized c over time with an exponential moving average, so that the applied
+    /// contrast threshold moves only `alpha` of the way to each new optimum. This avoids brightness
+    /// and contrast pops between frames when the optimum jumps around. `alpha` must be in `(0, 1]`;
+    /// `None` (the default) applies each optimum directly.
+    pub fn set_c_smoothing(&mut self, alpha: Option<f64>) -> Result<(), ReconstructorError> {
+        if let Some(alpha) = alpha {
+            if !(alpha > 0.0 && alpha <= 1.0) {
+                return Err(ArgumentError(
+                    "c smoothing alpha must be in (0, 1]".to_string(),
+                ));
+            }
         }
+        self.event_adder.c_smoothing = alpha;
+        Ok(())
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct LatentImage {
-    pub frame: Mat,
-}
+    /// Mask hot pixels, which fire constantly regardless of the scene: each APS frame, the events
+    /// of any pixel averaging more than `threshold` events per output interval are dropped before
+    /// deblurring. `None` (the default) keeps every pixel.
+    pub fn set_hot_pixel_threshold(
+        &mut self,
+        threshold: Option<f64>,
+    ) -> Result<(), ReconstructorError> {
+        if let Some(threshold) = threshold {
+            if !threshold.is_finite() || threshold <= 0.0 {
+                return Err(ArgumentError(
+                    "Hot pixel threshold must be positive".to_string(),
+                ));
+            }
+        }
+        self.event_adder.hot_pixel_threshold = threshold;
+        Ok(())
+    }
 
-// use async_trait::async_trait;
-//
-// #[async_trait]
-// impl Iterator for Reconstructor {
-//     type Item = Result<Mat, ReconstructionError>;
-//
-//     /// Get the next reconstructed image
-//     async fn next(&mut self) -> Option<Self::Item> {
-//         return match self.latent_image_queue.pop_front() {
-//             // If we have a queue of images already, just return the next one
-//             Some(image) => Some(Ok(image)),
-//
-//             // Else we need to rebuild the queue
-//             _ => {
-//                 let now = Instant::now();
-//
-//                 if self.event_adder.next_blur_info.is_some() {
-//                     mem::swap(&mut self.event_adder.blur_info, &mut self.event_adder.next_blur_info);
-//                     self.event_adder.next_blur_info = None;
-//                 }
-//                 //
-//                 //     self.fill_packet_queue_to_frame()
-//
-//
-//                 // let join_handle: thread::JoinHandle<_> = thread::spawn(|| {
-//                 match self.get_more_images().await {
-//                     Ok(_) => {}
-//                     Err(_) => return None
-//                 };
-//                 // });
-//                 let running_fps = self.latent_image_queue.len() as f64
-//                     / now.elapsed().as_millis() as f64 * 1000.0;
-//                 print!(
-//                     "\r{} frames in  {}ms -- Current FPS: {:.2}, Current c: {:.5}",
-//                     self.latent_image_queue.len(),
-//                     now.elapsed().as_millis(),
-//                     running_fps,
-//                     self.event_adder.current_c
-//                 );
-//                 io::stdout().flush().unwrap();
-//                 match self.latent_image_queue.pop_front() {
-//                     None => {
-//                         panic!("No images in the returned queue")
-//                     }
-//                     Some(image) => {
-//                         return Some(Ok(image));
-//                     }
-//                 }
-//             }
-//         };
-//     }
-// }
+    /// Get the number of pixels masked as hot for the latest APS frame. See
+    /// [`Reconstructor::set_hot_pixel_threshold`].
+    pub fn hot_pixels_masked(&self) -> usize {
+        self.event_adder.hot_pixels_masked
+    }
 
-fn split_camera_info(stream: &Stream) -> (u16, u16) {
-    (stream.height, stream.width)
-}
+    /// Reset the contrast threshold to `c`, e.g., after a scene change, without reopening the
+    /// source. If c is being optimized, the next APS frame is optimized regardless of the
+    /// optimization frequency, over the full search range, and its optimum is applied without
+    /// [`Reconstructor::set_c_smoothing`] smoothing.
+    pub fn reset_contrast_threshold(&mut self, c: f64) -> Result<(), ReconstructorError> {
+        if !c.is_finite() || c <= 0.0 {
+            return Err(ArgumentError("c must be positive".to_string()));
+        }
+        self.event_adder.reset_c(c);
+        Ok(())
+    }
 
-/// If [`MyArgs`]`.show_display`, shows the given [`Mat`] in an OpenCV window
-pub fn show_display(window_name: &str, mat: &Mat, wait: i32, reconstructor: &Reconstructor) -> i32 {
-    if reconstructor.show_display {
-        let mut tmp = Mat::default();
+    /// Get the most recent optimum of c found by the c-optimization, before smoothing. The applied
+    /// c is returned with each frame.
+    pub fn last_optimal_c(&self) -> Option<f64> {
+        self.event_adder.optimal_c
+    }
 
-        if mat.rows() != 540 {
-            let factor = mat.rows() as f32 / 540.0;
-            resize(
-                mat,
-                &mut tmp,
-                Size {
-                    width: (mat.cols() as f32 / factor) as i32,
-                    height: 540,
-                },
-                0.0,
-                0.0,
-                0,
-            )
-            .unwrap();
-            highgui::imshow(window_name, &tmp).unwrap();
-        } else {
-            highgui::imshow(window_name, mat).unwrap();
+    /// Get the c-optimization energy of the latest APS frame, at the c applied to it. It's computed
+    /// even when c isn't optimized, so it can monitor the reconstruction quality over time.
+    pub fn last_energy(&self) -> Option<f64> {
+        self.event_adder.last_energy
+    }
+
+    /// Set the weight of the total variation regularization in the c-optimization energy. Higher
+    /// values favor smoother latent images, which suits noisier scenes. Takes effect on the next
+    /// APS frame.
+    pub fn set_lambda(&mut self, lambda: f64) -> Result<(), ReconstructorError> {
+        if !lambda.is_finite() || lambda < 0.0 {
+            return Err(ArgumentError("Lambda must be non-negative".to_string()));
         }
-        return highgui::wait_key(wait).unwrap();
+        self.event_adder.lambda = lambda;
+        Ok(())
     }
-    -1
-}
 
-/// TODO: Remove. Just for debugging.
-pub fn _show_display_force(window_name: &str, mat: &Mat, wait: i32, normalize: bool) {
-    let mut normed = mat.clone();
-    let mut tmp = Mat::default();
-    if normalize {
-        opencv::core::normalize(
-            &mat,
-            &mut normed,
-            0.0,
-            1.0,
-            NORM_MINMAX,
-            -1,
-            &opencv::core::no_array(),
-        )
-        .unwrap();
+    /// Choose what to do when `output_fps` asks for intervals shorter than the median time
+    /// between events at a pixel. See [`FpsResolutionCheck`].
+    pub fn set_fps_resolution_check(&mut self, check: FpsResolutionCheck) {
+        self.fps_resolution_check = check;
     }
 
-    if mat.rows() != 540 {
-        let factor = mat.rows() as f32 / 540.0;
-        resize(
-            &normed,
+    /// Compare the interval length to the median time between events at a pixel, using the
+    /// events of the current exposure, and warn or cap as configured
+    fn check_fps_resolution(&mut self) {
+        if self.fps_resolution_check == FpsResolutionCheck::Off {
+            return;
+        }
+        let median_dt = match self.event_adder.median_inter_event_time() {
+            Some(dt) => dt,
+            None => return,
+        };
+        self.fps_resolution_checked = true;
+        let interval_length = self.event_adder.interval_length;
+        if interval_length >= median_dt {
+            return;
+        }
+
+        // The output frame rate that gives intervals of the median inter-event time
+        let suggested_fps = self.output_fps * interval_length / median_dt;
+        eprintln!(
+            "Warning: the interval length of {:.1} ticks is shorter than the median time of {:.1} \
+             ticks between events at a pixel. Consider an output_fps of at most {:.1}.",
+            interval_length, median_dt, suggested_fps
+        );
+        if self.fps_resolution_check == FpsResolutionCheck::Cap {
+            eprintln!("Capping output_fps to {:.1}", suggested_fps);
+            self.output_fps = suggested_fps;
+            self.event_adder.set_interval_length(median_dt);
+        }
+    }
+
+    /// Integrate each exposure with per-pixel renormalization, for long exposures (e.g., a second
+    /// or more) with many events. The latent image at a pixel is the blurred value divided by an
+    /// integral of `exp(c * E(t))`, which overflows to infinity once a pixel sees a few thousand
+    /// more events of one polarity than the other. This keeps the integral relative to a running
+    /// scale instead, at some cost in speed. Not applied with rolling-shutter correction.
+    pub fn set_long_exposure(&mut self, long_exposure: bool) {
+        self.event_adder.long_exposure = long_exposure;
+    }
+
+    /// Guarantee exactly one output frame per interval in [`OutputMode::FullSequence`]. By
+    /// default, the intervals between two APS exposures are skipped when there are no events
+    /// between them, leaving a gap in the timestamps. With gap filling, those intervals repeat the
+    /// nearest latent image instead, so the number of frames always matches the span of the
+    /// recording divided by the interval length. Frames past the
+    /// [`Reconstructor::set_max_intermediate_frames`] cap, and frames dropped by trigger gating,
+    /// are still left out.
+    pub fn set_fill_interval_gaps(&mut self, fill: bool) {
+        self.event_adder.fill_interval_gaps = fill;
+    }
+
+    /// Blend each output frame with the blurred APS frame, per pixel, by the density of events
+    /// during the exposure. A pixel with `n` events takes `n / (n + half_density)` of its intensity
+    /// from the latent image, and the rest from the blurred frame. So the output is sharp where
+    /// there are many events, and smooth where there are few, rather than amplifying noise there.
+    /// A larger `half_density` leans towards the blurred frame. The latent image carried to the
+    /// next APS frame is unaffected. `None` (the default) outputs the latent images as they are.
+    pub fn set_density_blend(
+        &mut self,
+        half_density: Option<f64>,
+    ) -> Result<(), ReconstructorError> {
+        if let Some(half_density) = half_density {
+            if !half_density.is_finite() || half_density <= 0.0 {
+                return Err(ArgumentError(
+                    "Density blend strength must be positive".to_string(),
+                ));
+            }
+        }
+        self.density_blend = half_density;
+        Ok(())
+    }
+
+    fn blend_by_event_density(
+        &self,
+        frames: Vec<Mat>,
+        half_density: f64,
+    ) -> Result<Vec<Mat>, ReconstructorError> {
+        let blurred = &self
+            .event_adder
+            .blur_info
+            .as_ref()
+            .ok_or(ReconstructorError::NoFrameBuffered)?
+            .blurred_image;
+        let latent_weight = self
+            .event_adder
+            .event_count_map()
+            .map(|count| count / (count + half_density));
+        let blurred_part = blurred.component_mul(&latent_weight.map(|weight| 1.0 - weight));
+        frames
+            .iter()
+            .map(|frame| {
+                let latent = mat_to_dmatrix(frame)?;
+                dmatrix_to_mat(&(latent.component_mul(&latent_weight) + &blurred_part))
+            })
+            .collect()
+    }
+
+    /// Set the range `[min_c, max_c]` that the c-optimization searches, and its resolution in
+    /// `n_points`. The search takes about `log(n_points)` energy evaluations. Takes effect on the
+    /// next optimization.
+    pub fn set_c_search(
+        &mut self,
+        min_c: f64,
+        max_c: f64,
+        n_points: u32,
+    ) -> Result<(), ReconstructorError> {
+        if !(min_c.is_finite() && max_c.is_finite() && min_c > 0.0 && min_c < max_c) {
+            return Err(ArgumentError(
+                "c search range must satisfy 0 < min_c < max_c".to_string(),
+            ));
+        }
+        if !(3..=MAX_C_SEARCH_POINTS).contains(&n_points) {
+            return Err(ArgumentError(format!(
+                "c search points must be between 3 and {}",
+                MAX_C_SEARCH_POINTS
+            )));
+        }
+        self.event_adder.min_c = min_c;
+        self.event_adder.max_c = max_c;
+        self.event_adder.n_points = n_points;
+        Ok(())
+    }
+
+    /// Reconstruct all the remaining frames of a file, blocking until the end of the file. Only
+    /// the images are kept, so memory is bounded by the output, but that can still be large for
+    /// long recordings; see [`Reconstructor::write_mmap`] for those.
+    ///
+    /// This can be called from synchronous code, or from a task on a multi-threaded Tokio
+    /// runtime. It can't block a current-thread runtime, so it returns an error there; use
+    /// [`Reconstructor::next_frame`] instead. Unless in deterministic mode, the file is read by a
+    /// task on the runtime that built the reconstructor, so that runtime must still be running.
+    ///
+    /// Each APS frame is dropped once it's deblurred, and the last one at the end of the file.
+    pub fn reconstruct_all(&mut self) -> Result<Vec<Mat>, ReconstructionError> {
+        if self.mode != "file" {
+            return Err(ArgumentError(
+                "Can only reconstruct all the frames of a file source".to_string(),
+            )
+            .into());
+        }
+        let handle = tokio::runtime::Handle::try_current().ok();
+        if let Some(handle) = handle.as_ref() {
+            if handle.runtime_flavor() != tokio::runtime::RuntimeFlavor::MultiThread {
+                return Err(ArgumentError(
+                    "Can't block a current-thread runtime to reconstruct all the frames"
+                        .to_string(),
+                )
+                .into());
+            }
+        }
+        let drain = async {
+            let mut frames = Vec::new();
+            while let Some(result) = self.next_iter_val(false).await {
+                frames.push(result?.0);
+            }
+            // Nothing more will be deblurred, so don't hold on to the last frame
+            self.event_adder.blur_info = None;
+            self.event_adder.next_blur_info = None;
+            Ok::<_, ReconstructionError>(frames)
+        };
+        match handle {
+            Some(handle) => tokio::task::block_in_place(|| handle.block_on(drain)),
+            None => tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| ReconstructionError::_new(&e.to_string()))?
+                .block_on(drain),
+        }
+    }
+
+    /// Reconstruct all the remaining frames into a memory-mapped file at `path`, which can be read
+    /// back in any order with [`MmapFrameReader`](crate::output::MmapFrameReader). Returns the
+    /// number of frames written.
+    pub async fn write_mmap(&mut self, path: &Path) -> Result<u64, ReconstructorError> {
+        let mut writer = MmapFrameWriter::new(path, self.output_dimensions(), self.output_depth)?;
+        while let Some(frame) = self.next_frame().await {
+            let frame = frame.map_err(|e| ReconstructorError::ExportError(e.to_string()))?;
+            writer.write(&frame.image)?;
+        }
+        let frame_count = writer.frame_count();
+        writer.finish()?;
+        Ok(frame_count)
+    }
+
+    /// Also send the output frames, with their timestamps, to `sink` at the lower rate of `fps`.
+    /// Each tap takes the first output frame at or after each of its own frame times, so a single
+    /// pass can feed, e.g., a low frame rate preview alongside the full rate output. Taps at or
+    /// above the output frame rate get every frame. A tap is removed when its receiver is dropped.
+    pub fn add_output_tap(
+        &mut self,
+        fps: f64,
+        sink: Sender<(Mat, i64)>,
+    ) -> Result<(), ReconstructorError> {
+        if !fps.is_finite() || fps <= 0.0 {
+            return Err(ArgumentError("Tap frame rate must be positive".to_string()));
+        }
+        self.output_taps.push(OutputTap {
+            interval: self.event_adder.timebase_hz / fps,
+            next_t: None,
+            sink,
+        });
+        Ok(())
+    }
+
+    fn feed_output_taps(&mut self, frame: &Mat, timestamp: i64) {
+        let t = timestamp as f64;
+        self.output_taps.retain_mut(|tap| {
+            if tap.next_t.map_or(false, |next_t| t < next_t) {
+                return true;
+            }
+            let mut next_t = tap.next_t.unwrap_or(t);
+            while next_t <= t {
+                next_t += tap.interval;
+            }
+            tap.next_t = Some(next_t);
+            tap.sink.send((frame.clone(), timestamp)).is_ok()
+        });
+    }
+
+    /// Keep the events around each deblurred APS frame after its reconstruction (the default).
+    /// They're returned by [`Reconstructor::next`] when events are requested, and used by
+    /// [`Reconstructor::energy_for_c`], [`Reconstructor::event_representation`], and
+    /// [`Reconstructor::temporal_consistency`]. Callers that need none of these can disable it
+    /// to save copying the event queues for every frame; those then see no events.
+    pub fn set_retain_frame_events(&mut self, retain: bool) {
+        self.retain_frame_events = retain;
+        if !retain {
+            self.events_return_before = vec![];
+            self.events_return_after = vec![];
+        }
+    }
+
+    /// Set the depth of the returned frames: `CV_64F` (the default) or `CV_32F`. Single precision
+    /// halves the memory and bandwidth of the queued frames, which matters for high resolution
+    /// sensors at high frame rates. The reconstruction itself is always computed in double
+    /// precision.
+    pub fn set_output_depth(&mut self, depth: i32) -> Result<(), ReconstructorError> {
+        if depth != CV_64F && depth != CV_32F {
+            return Err(ArgumentError(
+                "Output depth must be CV_64F or CV_32F".to_string(),
+            ));
+        }
+        self.output_depth = depth;
+        Ok(())
+    }
+
+    /// Crop `margin` pixels from each side of the output frames. The Sobel filtering for the
+    /// c-optimization reflects the image at its borders, so the outermost few pixels of the latent
+    /// images are less reliable. Defaults to 0 (no crop).
+    pub fn set_output_crop_margin(&mut self, margin: u32) -> Result<(), ReconstructorError> {
+        let (height, width) = (self.event_adder.height, self.event_adder.width);
+        if 2 * margin as i32 >= height.min(width) {
+            return Err(ArgumentError(format!(
+                "Crop margin {} leaves nothing of a {}x{} frame",
+                margin, width, height
+            )));
+        }
+        self.output_crop_margin = margin;
+        Ok(())
+    }
+
+    /// Get the current output frame rate. In [`OutputMode::SharpFrameOnly`], this changes to match
+    /// any blurred frame whose exposure is shorter than the configured output interval, so it may
+    /// vary from frame to frame. Check it after each frame when encoding.
+    pub fn current_output_fps(&self) -> f64 {
+        self.output_fps
+    }
+
+    /// Get the streams of the source: events, frames, IMU samples, and triggers. For a live source,
+    /// this includes the streams of both the events and frames sockets.
+    pub fn streams(&self) -> Vec<StreamInfo> {
+        self.streams.clone()
+    }
+
+    /// Get the (height, width) of the returned frames, for sizing an encoder. This accounts for
+    /// super-resolution and cropping, so it may differ from the sensor's `height` and `width`.
+    pub fn dimensions(&self) -> (u16, u16) {
+        let (height, width) = self.output_dimensions();
+        (height as u16, width as u16)
+    }
+
+    /// Get the (height, width) of the output frames, after any super-resolution and cropping
+    pub fn output_dimensions(&self) -> (i32, i32) {
+        let margin = 2 * self.output_crop_margin as i32;
+        (
+            self.event_adder.height - margin,
+            self.event_adder.width - margin,
+        )
+    }
+
+    fn crop_output(&self, frame: Mat) -> Result<Mat, ReconstructorError> {
+        if self.output_crop_margin == 0 {
+            return Ok(frame);
+        }
+        let (height, width) = self.output_dimensions();
+        if height <= 0 || width <= 0 {
+            return Err(ArgumentError(
+                "Crop margin is too large for the output frames".to_string(),
+            ));
+        }
+        let margin = self.output_crop_margin as i32;
+        let roi = Mat::roi(&frame, Rect::new(margin, margin, width, height))?;
+        Ok(roi.try_clone()?)
+    }
+
+    /// Reconstruct `samples` sub-frame latents per output frame, and average them to synthesize
+    /// natural motion blur, as with a film camera's rotary shutter. A `shutter_angle` of 360°
+    /// averages all the sub-frames in each output frame, 180° averages the first half, and so on.
+    /// At least one sub-frame is always used. Only applies to [`OutputMode::FullSequence`], and
+    /// takes effect on the next APS frame.
+    pub fn set_temporal_supersampling(
+        &mut self,
+        samples: u32,
+        shutter_angle: f64,
+    ) -> Result<(), ReconstructorError> {
+        if samples == 0 {
+            return Err(ArgumentError(
+                "Must reconstruct at least one sub-frame per output frame".to_string(),
+            ));
+        }
+        if !(shutter_angle > 0.0 && shutter_angle <= 360.0) {
+            return Err(ArgumentError(
+                "Shutter angle must be in (0, 360] degrees".to_string(),
+            ));
+        }
+        self.supersamples = samples;
+        self.shutter_angle = shutter_angle;
+        self.supersample_buffer.clear();
+        if self.event_adder.output_mode == OutputMode::FullSequence {
+            self.event_adder.set_interval_length(
+                self.event_adder.timebase_hz / (self.output_fps * samples as f64),
+            );
+        }
+        Ok(())
+    }
+
+    /// The number of sub-frames averaged into each output frame
+    fn sub_frames_per_shutter(&self) -> usize {
+        ((self.supersamples as f64 * self.shutter_angle / 360.0).round() as usize)
+            .clamp(1, self.supersamples as usize)
+    }
+
+    /// Reconstruct onto a grid `factor` times finer than the sensor resolution. The blurred APS
+    /// frames are bilinearly upscaled, and each event counts toward the fine pixels interpolated
+    /// from its sensor pixel, with the same weights. The returned latent images have the upscaled
+    /// dimensions, rather than `height` x `width`.
+    pub fn set_super_resolution_factor(&mut self, factor: f64) -> Result<(), ReconstructorError> {
+        if !factor.is_finite() || factor < 1.0 {
+            return Err(ArgumentError(
+                "Super resolution factor must be at least 1.0".to_string(),
+            ));
+        }
+        self.event_adder
+            .set_super_resolution_factor(self.height, self.width, factor);
+        self.input_history.clear();
+
+        // Upscale any frames we've already buffered
+        let (height, width) = (
+            self.event_adder.height as usize,
+            self.event_adder.width as usize,
+        );
+        if let Some(blur_info) = self.event_adder.blur_info.as_mut() {
+            blur_info.blurred_image = resize_dmatrix(&blur_info.blurred_image, height, width)?;
+        }
+        if let Some(blur_info) = self.event_adder.next_blur_info.as_mut() {
+            blur_info.blurred_image = resize_dmatrix(&blur_info.blurred_image, height, width)?;
+        }
+        Ok(())
+    }
+
+    /// Build an event representation from the events around the most recently deblurred frame:
+    /// both those before its exposure and those during and after it
+    pub fn event_representation(&self, kind: EventRepr) -> Mat {
+        self.event_adder.event_representation_of(
+            self.events_return_before
+                .iter()
+                .chain(self.events_return_after.iter()),
+            kind,
+        )
+    }
+
+    /// Compute the energy that the c-optimization minimizes, for the given contrast threshold `c`,
+    /// on the most recently deblurred frame. This does not alter the reconstruction state, so
+    /// it's safe to call repeatedly (e.g., for plotting the energy landscape) between calls to
+    /// [`Reconstructor::next`]. The frame's events must be retained; see
+    /// [`Reconstructor::set_retain_frame_events`].
+    pub fn energy_for_c(&mut self, c: f64) -> Result<f64, ReconstructorError> {
+        if !self.retain_frame_events || self.parallel_frames > 1 {
+            return Err(ArgumentError(
+                "The frame's events aren't retained; see set_retain_frame_events".to_string(),
+            ));
+        }
+        let exposure_end_t = match self.event_adder.blur_info.as_ref() {
+            Some(blur_info) => blur_info.exposure_end_t,
+            None => return Err(ReconstructorError::NoFrameBuffered),
+        };
+
+        // The events during the exposure are at the front of the 'return after' queue. Borrow them
+        // as the event adder's during queue, then put its own queue back. The reference time is
+        // found with them in place, since it can depend on the events (see `LatentReference`).
+        let mut during_queue: Vec<Event> = self
+            .events_return_after
+            .iter()
+            .take_while(|event| event.t() <= exposure_end_t)
+            .copied()
+            .collect();
+        // The edge image is only accumulated while optimizing c
+        let optimize_c = self.event_adder.optimize_c;
+        self.event_adder.optimize_c = true;
+        mem::swap(&mut during_queue, &mut self.event_adder.event_during_queue);
+        let energy = self
+            .event_adder
+            .reference_timestamp()
+            .map(|timestamp| self.event_adder.get_phi(c, timestamp));
+        mem::swap(&mut during_queue, &mut self.event_adder.event_during_queue);
+        self.event_adder.optimize_c = optimize_c;
+
+        energy.ok_or(ReconstructorError::NoFrameBuffered)
+    }
+
+    /// Estimate the sensitivity of the c-optimization energy to `c`, with the central difference
+    /// `(energy(c + delta) - energy(c - delta)) / (2 * delta)`. Useful for gradient-based
+    /// optimization of c, or for diagnosing a flat energy landscape. Like
+    /// [`Reconstructor::energy_for_c`], this doesn't alter the reconstruction state.
+    pub fn energy_gradient_at(&mut self, c: f64, delta: f64) -> Result<f64, ReconstructorError> {
+        if !delta.is_finite() || delta <= 0.0 {
+            return Err(ArgumentError("delta must be positive".to_string()));
+        }
+        if c - delta <= 0.0 {
+            return Err(ArgumentError(
+                "c - delta must be a positive contrast threshold".to_string(),
+            ));
+        }
+        let energy_above = self.energy_for_c(c + delta)?;
+        let energy_below = self.energy_for_c(c - delta)?;
+        Ok((energy_above - energy_below) / (2.0 * delta))
+    }
+
+    /// Eagerly reconstruct until at least `n` frames are queued, so that the next `n` calls to
+    /// [`Reconstructor::next`] return without decoding or deblurring. Useful when the caller knows
+    /// it'll need the upcoming frames soon, e.g., for scrubbing. Frames returned from the queue
+    /// don't carry the packet timestamp or latency.
+    pub async fn prefetch(&mut self, n: usize) -> Result<(), ReconstructorError> {
+        while self.latent_image_queue.len() < n {
+            self.refill_latent_queue().await?;
+        }
+        Ok(())
+    }
+
+    /// Reconstruct the rest of the input and return its frames with their timestamps, last to
+    /// first. Every frame is held in memory until the end of the input, so this is only suitable
+    /// for short recordings. Respects [`Reconstructor::set_reverse`], so with reverse mode enabled
+    /// the result comes out in forward order.
+    pub async fn reconstruct_reversed(&mut self) -> Result<Vec<(Mat, i64)>, ReconstructorError> {
+        let mut frames: Vec<(Mat, i64)> = self.latent_image_queue.drain(..).collect();
+        loop {
+            match self.refill_latent_queue().await {
+                Ok(_) => frames.extend(self.latent_image_queue.drain(..)),
+                Err(ReconstructorError::EndOfStream) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        frames.reverse();
+        Ok(frames)
+    }
+
+    /// Deblur the next APS frame and queue up its latent images. Returns the latency.
+    async fn refill_latent_queue(&mut self) -> Result<u128, ReconstructorError> {
+        let now = Instant::now();
+
+        if self.event_adder.next_blur_info.is_some() {
+            mem::swap(
+                &mut self.event_adder.blur_info,
+                &mut self.event_adder.next_blur_info,
+            );
+            self.event_adder.next_blur_info = None;
+        }
+
+        // let join_handle: thread::JoinHandle<_> = thread::spawn(|| {
+        let latency = self.get_more_images().await?;
+        // });
+        let running_fps =
+            self.latent_image_queue.len() as f64 / now.elapsed().as_millis() as f64 * 1000.0;
+        print!(
+            "\r{} frames in  {}ms -- Current FPS: {:.2}, Current c: {:.5}",
+            self.latent_image_queue.len(),
+            now.elapsed().as_millis(),
+            running_fps,
+            self.event_adder.current_c
+        );
+        if self.optimize_controller
+            && ((self.event_adder.timebase_hz / running_fps) as i64 - self.event_adder.interval_t)
+                .abs()
+                > (self.event_adder.timebase_hz / 50000.0) as i64
+        {
+            // self.event_adder.interval_t =
+            //     (1000000.0 / running_fps).max(1000000.0 / self.output_fps) as i64;
+            // print!(" Target FPS: {}", 1000000 / self.event_adder.interval_t);
+            // self.event_adder.optimize_c = false;
+        } else {
+            // self.event_adder.optimize_c = self.optimize_c;
+        }
+        io::stdout().flush().unwrap();
+        Ok(latency)
+    }
+
+    /// Get the next reconstructed image
+    ///
+    /// With `with_events` in [`OutputMode::SharpFrameOnly`], each frame carries the events before
+    /// its exposure and the events during and after it. In [`OutputMode::FullSequence`], each
+    /// frame instead carries the events in its own interval, as the 'during & after' events, with
+    /// no 'before' events. The two timestamps are then the start and end of the interval.
+    #[deprecated(note = "use `next_frame`, which returns a `ReconstructedFrame`")]
+    pub async fn next(&mut self, with_events: bool) -> IterRet {
+        self.next_iter_val(with_events).await
+    }
+
+    /// Get the next reconstructed frame. Its events are included unless disabled with
+    /// [`Reconstructor::set_retain_frame_events`]; see [`ReconstructedFrame`] for which events
+    /// each mode returns.
+    pub async fn next_frame(&mut self) -> Option<Result<ReconstructedFrame, ReconstructionError>> {
+        let with_events = self.retain_frame_events;
+        let frame = self.next_iter_val(with_events).await?;
+        Some(frame.map(|frame| ReconstructedFrame {
+            timestamp: self.last_returned_timestamp(),
+            energy: self.last_energy(),
+            ..ReconstructedFrame::from(frame)
+        }))
+    }
+
+    async fn next_iter_val(&mut self, with_events: bool) -> IterRet {
+        return match self.latent_image_queue.pop_front() {
+            // If we have a queue of images already, just return the next one
+            Some((image, timestamp)) => {
+                self.track_returned_frame(&image, timestamp);
+                let events = match (with_events, self.event_adder.output_mode) {
+                    (true, OutputMode::FullSequence) => Some(self.sub_frame_events(timestamp)),
+                    _ => None, // TODO: what about event queues in the sharp-frame-only mode?
+                };
+                Some(Ok((image, None, events, None)))
+            }
+
+            // Else we need to rebuild the queue
+            _ => {
+                // An APS frame might not produce any output frames (e.g., when it only fills part
+                // of a supersampled frame), so keep going until there's something to return
+                let latency = loop {
+                    match self.refill_latent_queue().await {
+                        Ok(_) if self.latent_image_queue.is_empty() => continue,
+                        Ok(a) => break a,
+                        Err(ReconstructorError::EndOfStream) => return None,
+                        Err(e) => return Some(Err(e.into())),
+                    }
+                };
+                match self.latent_image_queue.pop_front() {
+                    None => {
+                        panic!("No images in the returned queue")
+                    }
+                    Some((image, timestamp)) => {
+                        self.track_returned_frame(&image, timestamp);
+                        // TODO: handle error
+                        // debug_assert!(
+                        //     self.event_adder
+                        //         .blur_info
+                        //         .as_ref()
+                        //         .unwrap()
+                        //         .exposure_begin_t
+                        //         < self.event_adder.last_interval_start_timestamp
+                        // );
+                        //
+                        // debug_assert!({
+                        //     let true_frame_dt =
+                        //         self.event_adder.blur_info.as_ref().unwrap().exposure_end_t
+                        //             - self
+                        //                 .event_adder
+                        //                 .blur_info
+                        //                 .as_ref()
+                        //                 .unwrap()
+                        //                 .exposure_begin_t;
+                        //     let img_dt_secs = true_frame_dt as f64 / 1000000.0;
+                        //     let frame_length_secs = 1.0 / self.output_fps as f64;
+                        //     img_dt_secs >= frame_length_secs
+                        // });
+
+                        return match (with_events, self.event_adder.output_mode) {
+                            (true, OutputMode::FullSequence) => Some(Ok((
+                                image,
+                                Some(
+                                    self.event_adder
+                                        .blur_info
+                                        .as_ref()
+                                        .unwrap()
+                                        .packet_timestamp,
+                                ),
+                                Some(self.sub_frame_events(timestamp)),
+                                Some(latency),
+                            ))),
+                            (true, OutputMode::SharpFrameOnly) => Some(Ok((
+                                image,
+                                Some(
+                                    self.event_adder
+                                        .blur_info
+                                        .as_ref()
+                                        .unwrap()
+                                        .packet_timestamp,
+                                ),
+                                Some((
+                                    self.event_adder.current_c,
+                                    self.events_return_before
+                                        .iter()
+                                        .map(EdiEvent::from)
+                                        .collect(),
+                                    self.events_return_after
+                                        .iter()
+                                        .map(EdiEvent::from)
+                                        .collect(),
+                                    self.event_adder
+                                        .blur_info
+                                        .as_ref()
+                                        .unwrap()
+                                        .exposure_begin_t,
+                                    self.event_adder.last_interval_start_timestamp,
+                                )),
+                                Some(latency),
+                            ))),
+                            (false, _) => Some(Ok((
+                                image,
+                                Some(
+                                    self.event_adder
+                                        .blur_info
+                                        .as_ref()
+                                        .unwrap()
+                                        .packet_timestamp,
+                                ),
+                                None,
+                                Some(latency),
+                            ))),
+                        };
+                    }
+                }
+            }
+        };
+    }
+
+    /// Get the next reconstructed image as raw bytes, for passing to non-Rust consumers over a pipe
+    /// or shared memory without any image encoding. The bytes are the pixel intensities as
+    /// little-endian `f32`s, in row-major order, so pixel (row, col) starts at byte
+    /// `4 * (row * width + col)`. The [`FrameHeader`] gives the dimensions and the frame's
+    /// timestamp and c.
+    pub async fn next_raw(
+        &mut self,
+    ) -> Option<Result<(Vec<u8>, FrameHeader), ReconstructionError>> {
+        while self.latent_image_queue.is_empty() {
+            match self.refill_latent_queue().await {
+                Ok(_) => {}
+                Err(ReconstructorError::EndOfStream) => return None,
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+        let (image, timestamp) = self.latent_image_queue.pop_front().unwrap();
+        let image = match mat_to_dmatrix(&image) {
+            Ok(image) => image,
+            Err(e) => return Some(Err(e.into())),
+        };
+
+        let mut bytes = Vec::with_capacity(image.len() * 4);
+        for row in image.row_iter() {
+            for px in row.iter() {
+                bytes.write_f32::<LittleEndian>(*px as f32).unwrap();
+            }
+        }
+        Some(Ok((
+            bytes,
+            FrameHeader {
+                width: image.ncols() as u32,
+                height: image.nrows() as u32,
+                timestamp,
+                c: self.event_adder.current_c,
+            },
+        )))
+    }
+
+    /// Reconstruct all the remaining frames of the source, and write them to an `.npz` file for
+    /// analysis in Python. The file holds three arrays:
+    /// - `frames`: the latent images, as `f64` with shape (frames, height, width)
+    /// - `timestamps`: the start timestamp of each frame's interval, as `i64`
+    /// - `c`: the contrast threshold used for each frame, as `f64`
+    ///
+    /// APS frames with a degenerate exposure are skipped, as in [`Reconstructor::next`].
+    pub async fn export_npz(&mut self, path: &Path) -> Result<(), ReconstructorError> {
+        let (height, width) = self.output_dimensions();
+        let mut frames: Vec<f64> = vec![];
+        let mut timestamps: Vec<i64> = vec![];
+        let mut c_values: Vec<f64> = vec![];
+
+        loop {
+            while let Some((image, timestamp)) = self.latent_image_queue.pop_front() {
+                let image = mat_to_dmatrix(&image)?;
+                for row in image.row_iter() {
+                    frames.extend(row.iter());
+                }
+                timestamps.push(timestamp);
+                c_values.push(self.event_adder.current_c);
+            }
+            match self.refill_latent_queue().await {
+                Ok(_) | Err(ReconstructorError::DegenerateExposure { .. }) => {}
+                Err(ReconstructorError::EndOfStream) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let frames =
+            Array3::from_shape_vec((timestamps.len(), height as usize, width as usize), frames)
+                .map_err(|e| ReconstructorError::ExportError(e.to_string()))?;
+        let file =
+            File::create(path).map_err(|e| ReconstructorError::ExportError(e.to_string()))?;
+        let mut npz = NpzWriter::new(file);
+        npz.add_array("frames", &frames)
+            .and_then(|_| npz.add_array("timestamps", &Array1::from(timestamps)))
+            .and_then(|_| npz.add_array("c", &Array1::from(c_values)))
+            .map_err(|e| ReconstructorError::ExportError(e.to_string()))?;
+        npz.finish()
+            .map_err(|e| ReconstructorError::ExportError(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Get the timestamp of the most recently returned frame: the start of its interval, or the
+    /// reference instant of its exposure in [`OutputMode::SharpFrameOnly`]
+    pub fn last_returned_timestamp(&self) -> Option<i64> {
+        self.last_returned.as_ref().map(|(_, timestamp)| *timestamp)
+    }
+
+    fn track_returned_frame(&mut self, image: &Mat, timestamp: i64) {
+        self.previous_returned = self.last_returned.take();
+        self.last_returned = Some((image.clone(), timestamp));
+    }
+
+    /// Measure the flicker between the two most recently returned frames, as the mean absolute
+    /// difference of their intensities over the static pixels (those with at most
+    /// [`STATIC_PIXEL_MAX_EVENTS`] events between the two frames). A consistent reconstruction
+    /// shouldn't change where nothing moved, so higher values indicate flickering. Returns `None`
+    /// until two frames have been returned, or if no pixels are static.
+    pub fn temporal_consistency(&self) -> Option<f64> {
+        let ((previous, previous_t), (last, last_t)) =
+            match (&self.previous_returned, &self.last_returned) {
+                (Some(previous), Some(last)) => (previous, last),
+                _ => return None,
+            };
+        if previous.size().ok()? != last.size().ok()? {
+            return None;
+        }
+
+        let counts = self.event_adder.event_representation_of(
+            self.events_return_before
+                .iter()
+                .chain(self.events_return_after.iter())
+                .filter(|event| event.t() >= *previous_t && event.t() < *last_t),
+            EventRepr::EventCount,
+        );
+        let counts = match self.event_adder.log_polar {
+            true => warp_log_polar(&counts, true).ok()?,
+            false => counts,
+        };
+        let counts = self.crop_output(counts).ok()?;
+        let mut static_mask = Mat::default();
+        in_range(
+            &counts,
+            &Scalar::all(0.0),
+            &Scalar::all(STATIC_PIXEL_MAX_EVENTS),
+            &mut static_mask,
+        )
+        .ok()?;
+        if count_non_zero(&static_mask).ok()? == 0 {
+            return None;
+        }
+
+        let mut difference = Mat::default();
+        absdiff(previous, last, &mut difference).ok()?;
+        Some(mean(&difference, &static_mask).ok()?.0[0])
+    }
+
+    /// Get the events in the output interval starting at `timestamp`, in the form returned by
+    /// [`Reconstructor::next`] for the full-sequence mode
+    fn sub_frame_events(&self, timestamp: i64) -> (f64, Vec<EdiEvent>, Vec<EdiEvent>, i64, i64) {
+        let samples = match self.event_adder.output_mode {
+            OutputMode::FullSequence => self.supersamples as usize,
+            OutputMode::SharpFrameOnly => 1,
+        };
+        let end_t = self.event_adder.interval_timestamp(timestamp, samples);
+        let events = self
+            .events_return_before
+            .iter()
+            .chain(self.events_return_after.iter())
+            .filter(|event| event.t() >= timestamp && event.t() < end_t)
+            .map(EdiEvent::from)
+            .collect();
+        (self.event_adder.current_c, vec![], events, timestamp, end_t)
+    }
+
+    /// Generates reconstructed images from the next packet of events
+    async fn get_more_images(&mut self) -> Result<u128, ReconstructorError> {
+        if self.end_of_stream {
+            return Err(ReconstructorError::EndOfStream);
+        }
+        if self.event_adder.blur_info.is_none() {
+            return Err(ReconstructorError::NoFrameBuffered);
+        }
+        if self.parallel_frames > 1 {
+            return self.get_more_images_parallel().await;
+        }
+        self.sort_packet_queue()?;
+
+        let deblur_res = {
+            self.show_blurred_input()?;
+            deblur_image(&mut self.event_adder)
+        };
+
+        self.prune_imu_samples();
+
+        let latency = (Instant::now()
+            - self
+                .event_adder
+                .blur_info
+                .as_ref()
+                .unwrap()
+                .packet_timestamp)
+            .as_millis();
+        println!("  Latency is {}ms", latency);
+
+        // Don't do anything for files, since latency doesn't make sense in this context. (File
+        // reads happen instantaneously)
+        if self.optimize_controller && self.mode != "file" {
+            let state = self
+                .c_optimization
+                .on_latency(latency > self.target_latency as u128);
+            match (self.c_optimization, state) {
+                (COptimizationState::Active, COptimizationState::Suspended) => {
+                    println!("DISABLING C-OPTIMIZATION");
+                }
+                (COptimizationState::Suspended, COptimizationState::Active) => {
+                    println!("ENABLING C-OPTIMIZATION");
+                }
+                _ => {}
+            }
+            self.c_optimization = state;
+            self.event_adder.optimize_c = state.optimizes();
+        }
+
+        let next_blur_info = self.read_next_blur_info().await;
+        self.finish_frame(deblur_res, next_blur_info)?;
+
+        Ok(latency)
+    }
+
+    /// Deblur up to `parallel_frames` APS frames at once. The events of each frame are sorted in
+    /// turn, as usual, and a copy of the event adder is kept for each. The copies are deblurred in
+    /// parallel, then their latent images are queued in order.
+    async fn get_more_images_parallel(&mut self) -> Result<u128, ReconstructorError> {
+        let mut jobs = Vec::with_capacity(self.parallel_frames);
+        while jobs.len() < self.parallel_frames {
+            self.sort_packet_queue()?;
+            let job = self.event_adder.clone();
+            self.prune_imu_samples();
+
+            // Move on to the next frame as `finish_frame` would, without the deblurred images.
+            // Nothing carries over between independent frames except the interval timestamp,
+            // which doesn't depend on the images in the sharp-frame-only mode.
+            let next_blur_info = self.read_next_blur_info().await;
+            if let Some(next_blur_info) = next_blur_info.as_ref() {
+                if let Some(reference_t) = self.event_adder.reference_timestamp() {
+                    self.event_adder.last_interval_start_timestamp =
+                        self.event_adder.interval_timestamp(reference_t, 1);
+                }
+                self.event_adder.record_queue_lengths();
+                self.event_adder.reset_event_queues();
+                self.event_adder.reserve_queue_capacity();
+                if jobs.len() + 1 < self.parallel_frames {
+                    self.event_adder.blur_info = Some(next_blur_info.clone());
+                } else {
+                    self.event_adder.next_blur_info = Some(next_blur_info.clone());
+                }
+            }
+            let end_of_stream = next_blur_info.is_none();
+            jobs.push((job, next_blur_info));
+            if end_of_stream {
+                break;
+            }
+        }
+
+        let results: Vec<Result<DeblurReturn, ReconstructorError>> = jobs
+            .par_iter_mut()
+            .map(|(job, _)| deblur_image(job))
+            .collect();
+
+        // Finish each frame with its own event adder, so that its stats, outputs, and next frame
+        // are its own. The first error is returned after queueing the rest.
+        let mut latency = 0;
+        let mut first_error = None;
+        for ((job, next_blur_info), deblur_res) in jobs.into_iter().zip(results) {
+            let live = mem::replace(&mut self.event_adder, job);
+            let result = self.show_blurred_input().and_then(|_| {
+                latency = (Instant::now()
+                    - self
+                        .event_adder
+                        .blur_info
+                        .as_ref()
+                        .unwrap()
+                        .packet_timestamp)
+                    .as_millis();
+                self.finish_frame(deblur_res, next_blur_info)
+            });
+            let job = mem::replace(&mut self.event_adder, live);
+            self.event_adder.current_c = job.current_c;
+            self.event_adder.optimal_c = job.optimal_c;
+            self.event_adder.last_energy = job.last_energy;
+            match result {
+                Ok(()) => {}
+                Err(ReconstructorError::EndOfStream) => self.end_of_stream = true,
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None if self.end_of_stream && self.latent_image_queue.is_empty() => {
+                Err(ReconstructorError::EndOfStream)
+            }
+            None => Ok(latency),
+        }
+    }
+
+    /// Sort the queued event packets into the event adder's queues for the current APS frame
+    fn sort_packet_queue(&mut self) -> Result<(), ReconstructorError> {
+        while let Some(p) = self.packet_queue.pop_front() {
+            match FromPrimitive::from_u32(p.packet.stream_id) {
+                Some(StreamContent::Events) => {
+                    // An empty packet just sorts zero events, so keep going. Skip a malformed
+                    // packet rather than losing the whole stream.
+                    match self.event_adder.sort_events(p.packet) {
+                        Ok(_) => {}
+                        Err(ReconstructorError::PacketError(e)) => {
+                            eprintln!("Skipping malformed event packet: {}", e)
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                // Frames, IMU samples, and triggers are handled as they're read, so only events
+                // should be queued. Skip anything else.
+                _ => {}
+            }
+        }
+
+        if !self.fps_resolution_checked {
+            self.check_fps_resolution();
+        }
+
+        Ok(())
+    }
+
+    /// Show the latent image at the optimized c in its own window after each c-optimization, for
+    /// inspecting the optimization. It only appears with the display enabled, and never waits for
+    /// a key press.
+    pub fn set_debug_display(&mut self, debug_display: bool) {
+        self.debug_display = debug_display;
+    }
+
+    fn show_blurred_input(&mut self) -> Result<(), ReconstructorError> {
+        if self.show_blurred_display {
+            let tmp_blurred_mat =
+                dmatrix_to_mat(&self.event_adder.blur_info.as_ref().unwrap().blurred_image)?;
+            match self.display_manager.as_mut() {
+                Some(manager) if manager.wants(DisplayView::Blurred) => {
+                    manager.set_view(DisplayView::Blurred, &tmp_blurred_mat)?
+                }
+                _ => _show_display_force("blurred input", &tmp_blurred_mat, 1, false),
+            }
+        }
+        Ok(())
+    }
+
+    fn prune_imu_samples(&mut self) {
+        // IMU samples before the end of this exposure aren't needed for the next one
+        if self.event_adder.use_imu {
+            let end_t = self.event_adder.blur_info.as_ref().unwrap().exposure_end_t;
+            self.event_adder
+                .imu_angular_speed
+                .retain(|(t, _)| *t >= end_t);
+        }
+    }
+
+    /// Read up to the next APS frame, or `None` at the end of the stream. Malformed frame packets
+    /// are skipped, and the events before them are kept for the next frame.
+    async fn read_next_blur_info(&mut self) -> Option<BlurInfo> {
+        loop {
+            match self.fill_packet_queue_to_frame().await {
+                Ok(blur_info) => {
+                    let frame_exp_dt = blur_info.exposure_end_t - blur_info.exposure_begin_t;
+                    if frame_exp_dt < self.event_adder.interval_t
+                        && self.event_adder.output_mode == OutputMode::SharpFrameOnly
+                    {
+                        self.event_adder.set_interval_length(frame_exp_dt as f64);
+                        self.output_fps = self.event_adder.timebase_hz / frame_exp_dt as f64;
+                    }
+                    return Some(blur_info);
+                }
+                Err(ReconstructorError::PacketError(e)) => {
+                    eprintln!("Skipping malformed frame packet: {}", e);
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// Queue the latent images of a deblurred frame, and get the event adder ready for the next
+    /// frame
+    fn finish_frame(
+        &mut self,
+        deblur_res: Result<DeblurReturn, ReconstructorError>,
+        next_blur_info: Option<BlurInfo>,
+    ) -> Result<(), ReconstructorError> {
+        if next_blur_info.is_none() {
+            self.end_of_stream = true;
+        }
+        match (deblur_res, next_blur_info) {
+            (Err(_), None) => Err(ReconstructorError::EndOfStream),
+            (Ok(_), None)
+                if self.truncated_exposure == TruncatedExposure::Skip
+                    && self.exposure_truncated() =>
+            {
+                Err(ReconstructorError::EndOfStream)
+            }
+            (Err(e), Some(next_blur_info)) => {
+                // Skip this frame, but stay ready for the next one
+                self.event_adder.reset_event_queues();
+                self.event_adder.next_blur_info = Some(next_blur_info);
+                Err(e)
+            }
+            (Ok(mut deblur_return), next_blur_info) => {
+                self.last_frame_stats = Some(self.frame_stats(&deblur_return)?);
+                if self.output_event_counts {
+                    self.last_event_count_map =
+                        Some(dmatrix_to_mat(&self.event_adder.event_count_map())?);
+                }
+                if self.estimate_variance {
+                    self.last_variance_map = Some(self.variance_map(&deblur_return)?);
+                }
+                if self.keep_log_latents {
+                    self.last_log_latents = Some(
+                        deblur_return
+                            .ret_vec
+                            .iter()
+                            .map(|latent| {
+                                let mut log_latent = Mat::default();
+                                log(latent, &mut log_latent).map(|_| log_latent)
+                            })
+                            .collect::<Result<Vec<Mat>, _>>()?,
+                    );
+                }
+                if self.debug_display && self.show_display && self.event_adder.optimize_c {
+                    // The latent at the optimized c, normalized to show its edges
+                    _show_display_force("LATENT", deblur_return.ret_vec.last().unwrap(), 1, true);
+                }
+                if let Some(manager) = self.display_manager.as_mut() {
+                    if manager.wants(DisplayView::Edges) {
+                        let (_, edges) = self
+                            .event_adder
+                            .get_gradient_and_edges(deblur_return.ret_vec.last().unwrap().clone());
+                        manager.set_view(DisplayView::Edges, &edges)?;
+                    }
+                }
+                match next_blur_info.as_ref() {
+                    Some(next) if self.resets_latent(next) => self
+                        .event_adder
+                        .set_latent_image(dmatrix_to_mat(&next.blurred_image)?),
+                    _ => self
+                        .event_adder
+                        .set_latent_image(deblur_return.ret_vec.last().unwrap().clone()),
+                }
+                self.event_adder.last_interval_start_timestamp =
+                    deblur_return.last_interval_start_timestamp;
+                if let Some(half_density) = self.density_blend {
+                    deblur_return.ret_vec =
+                        self.blend_by_event_density(deblur_return.ret_vec, half_density)?;
+                }
+                let mut frames = if self.supersamples > 1
+                    && self.event_adder.output_mode == OutputMode::FullSequence
+                {
+                    // Sub-frames left over from the last APS frame start the next output frame
+                    self.supersample_buffer.extend(
+                        deblur_return
+                            .ret_vec
+                            .into_iter()
+                            .zip(deblur_return.timestamps),
+                    );
+                    let samples = self.supersamples as usize;
+                    let shutter = self.sub_frames_per_shutter();
+                    let complete = self.supersample_buffer.len() / samples * samples;
+                    let frames = self.supersample_buffer[..complete]
+                        .chunks(samples)
+                        .map(|group| {
+                            average_mats(group[..shutter].iter().map(|(mat, _)| mat))
+                                .map(|mat| (mat, group[0].1))
+                        })
+                        .collect::<Result<Vec<(Mat, i64)>, _>>()?;
+                    self.supersample_buffer.drain(..complete);
+                    frames
+                } else {
+                    deblur_return
+                        .ret_vec
+                        .into_iter()
+                        .zip(deblur_return.timestamps)
+                        .collect()
+                };
+                if self.reverse {
+                    frames.reverse();
+                }
+                for (frame, timestamp) in frames {
+                    if self.trigger_window.is_some() && !self.near_trigger(timestamp, timestamp) {
+                        continue;
+                    }
+                    let frame = match self.event_adder.log_polar {
+                        true => warp_log_polar(&frame, true)?,
+                        false => frame,
+                    };
+                    let frame = self.crop_output(frame)?;
+                    let frame = match self.output_depth {
+                        CV_64F => frame,
+                        depth => {
+                            let mut converted = Mat::default();
+                            frame.convert_to(&mut converted, depth, 1.0, 0.0)?;
+                            converted
+                        }
+                    };
+                    self.feed_output_taps(&frame, timestamp);
+                    self.latent_image_queue.push_back((frame, timestamp));
+                }
+                if let Some(window) = self.trigger_window {
+                    // Later frames are all after this point, so older triggers are done with
+                    let oldest_t = self.event_adder.last_interval_start_timestamp - window;
+                    self.trigger_timestamps.retain(|t| *t >= oldest_t);
+                }
+
+                self.event_adder.record_queue_lengths();
+
+                if self.retain_frame_events && self.parallel_frames == 1 {
+                    /*
+                    set the 'return after' queue. Keeps the events from during the exposure time,
+                    and the events occurring afterwards.
+                    */
+                    let mut tmp_vec = vec![];
+                    mem::swap(&mut tmp_vec, &mut self.event_adder.event_during_queue);
+                    self.events_return_after = tmp_vec;
+                    self.events_return_after
+                        .append(&mut self.event_adder.event_after_queue.clone());
+
+                    // set the 'return before' queue
+                    let mut tmp_vec = vec![];
+                    mem::swap(&mut tmp_vec, &mut self.event_adder.event_before_queue);
+                    self.events_return_before = tmp_vec;
+                }
+
+                self.event_adder.reset_event_queues();
+                self.event_adder.reserve_queue_capacity();
+                self.event_adder.next_blur_info = next_blur_info;
+                self.event_adder.current_c = deblur_return.found_c;
+                Ok(())
+            }
+        }
+    }
+
+    /// Read packets until the next APS frame is reached (inclusive), merging any frames that
+    /// follow it too closely. See [`Reconstructor::set_min_frame_gap`].
+    async fn fill_packet_queue_to_frame(&mut self) -> Result<BlurInfo, ReconstructorError> {
+        let mut blur_info = match self.pending_frame.take() {
+            Some((blur_info, packets)) => {
+                self.packet_queue.extend(packets);
+                blur_info
+            }
+            None => self.read_frame().await?,
+        };
+        let min_frame_gap = match self.min_frame_gap {
+            None => return Ok(blur_info),
+            Some(min_frame_gap) => min_frame_gap,
+        };
+
+        // Look ahead to the next frame. If it's far enough away, hold it and the packets before
+        // it back until the current frame is done, so that its events aren't sorted against the
+        // current frame.
+        loop {
+            let queued = self.packet_queue.len();
+            let next_blur_info = match self.read_frame().await {
+                Ok(next_blur_info) => next_blur_info,
+                Err(_) => return Ok(blur_info),
+            };
+            if next_blur_info.exposure_begin_t - blur_info.exposure_end_t >= min_frame_gap {
+                let packets = self.packet_queue.split_off(queued);
+                self.pending_frame = Some((next_blur_info, packets));
+                return Ok(blur_info);
+            }
+            blur_info = blur_info.merge(next_blur_info);
+            self.merged_frames += 1;
+        }
+    }
+
+    /// Read packets until the next APS frame is reached (inclusive). A malformed frame packet
+    /// returns a [`ReconstructorError::PacketError`], after which reading can carry on.
+    async fn read_frame(&mut self) -> Result<BlurInfo, ReconstructorError> {
+        let (height, width) = (self.height as i32, self.width as i32);
+        let blur_info = loop {
+            match self.packet_receiver.next().await {
+                Some(p) => {
+                    if matches!(
+                        FromPrimitive::from_u32(p.packet.stream_id),
+                        Some(StreamContent::Frame)
+                    ) {
+                        let frame =
+                            aedat::frame_generated::size_prefixed_root_as_frame(&p.packet.buffer)
+                                .map_err(|e| ReconstructorError::PacketError(e.to_string()))?;
+
+                        if let (Some(max_desync), Some(latest_event_t), false) =
+                            (self.max_desync, self.latest_event_t, self.mode == "file")
+                        {
+                            if latest_event_t - frame.exposure_end_t() > max_desync {
+                                self.desync_dropped_frames += 1;
+                                eprintln!(
+                                    "Dropping APS frame {} us behind the event stream",
+                                    latest_event_t - frame.exposure_end_t()
+                                );
+                                continue;
+                            }
+                        }
+
+                        if self.trigger_window.is_some()
+                            && !self.near_trigger(frame.exposure_begin_t(), frame.exposure_end_t())
+                        {
+                            continue;
+                        }
+
+                        // The frame may only cover a region of interest of the sensor. Place it
+                        // at its offset, and leave the rest of the image black.
+                        let (roi_x, roi_y) = (frame.offset_x() as i32, frame.offset_y() as i32);
+                        let roi_width = frame.width() as i32;
+
+                        // Skip metadata-only frame markers, and frames with truncated pixel data
+                        let frame_px = match frame.pixels() {
+                            Some(frame_px)
+                                if frame_px.len()
+                                    >= (frame.width() as usize) * (frame.height() as usize) =>
+                            {
+                                frame_px
+                            }
+                            _ => {
+                                eprintln!(
+                                    "Skipping APS frame at {} without pixel data",
+                                    frame.exposure_begin_t()
+                                );
+                                continue;
+                            }
+                        };
+                        if let Some(bins) = self.input_histogram_bins {
+                            let mut histogram = vec![0; bins];
+                            let roi_pixels = (frame.width() as usize) * (frame.height() as usize);
+                            for px in frame_px.iter().take(roi_pixels) {
+                                histogram[*px as usize * bins / 256] += 1;
+                            }
+                            self.last_input_histogram = Some(histogram);
+                        }
+
+                        let mut image = DMatrix::<f64>::zeros(height as usize, width as usize);
+                        for roi_row in 0..frame.height() as i32 {
+                            for roi_col in 0..roi_width {
+                                let (row, col) = (roi_row + roi_y, roi_col + roi_x);
+                                if row < 0 || row >= height || col < 0 || col >= width {
+                                    continue;
+                                }
+                                image[(row as usize, col as usize)] =
+                                    frame_px[(roi_row * roi_width + roi_col) as usize] as f64
+                                        / 255.0;
+                            }
+                        }
+
+                        if self.event_adder.super_resolution_factor != 1.0 {
+                            image = resize_dmatrix(
+                                &image,
+                                self.event_adder.height as usize,
+                                self.event_adder.width as usize,
+                            )?;
+                        }
+
+                        if self.input_temporal_average > 1 {
+                            image = self.average_input(image);
+                        }
+
+                        if self.event_adder.log_polar {
+                            image =
+                                mat_to_dmatrix(&warp_log_polar(&dmatrix_to_mat(&image)?, false)?)?;
+                        }
+
+                        let blur_info = BlurInfo::new(
+                            image,
+                            frame.exposure_begin_t(),
+                            frame.exposure_end_t(),
+                            p.timestamp,
+                        );
+
+                        break blur_info;
+                    } else if matches!(
+                        FromPrimitive::from_u32(p.packet.stream_id),
+                        Some(StreamContent::Events)
+                    ) {
+                        self.queue_event_packet(p);
+                    } else if matches!(
+                        FromPrimitive::from_u32(p.packet.stream_id),
+                        Some(StreamContent::Triggers)
+                    ) {
+                        self.record_triggers(&p.packet);
+                    } else if matches!(
+                        FromPrimitive::from_u32(p.packet.stream_id),
+                        Some(StreamContent::Imus)
+                    ) {
+                        self.record_imus(&p.packet);
+                    }
+                }
+                None => return Err(ReconstructorError::EndOfStream),
+            }
+        };
+
+        // Other streams are skipped. A frame is left for the next call.
+        match self.packet_receiver.next().await {
+            Some(p) => match FromPrimitive::from_u32(p.packet.stream_id) {
+                Some(StreamContent::Events) => self.queue_event_packet(p),
+                Some(StreamContent::Triggers) => self.record_triggers(&p.packet),
+                Some(StreamContent::Imus) => self.record_imus(&p.packet),
+                Some(StreamContent::Frame) => self.packet_receiver.put_back(p),
+                _ => {}
+            },
+            None => return Err(ReconstructorError::EndOfStream),
+        };
+
+        Ok(blur_info)
+    }
+
+    fn record_triggers(&mut self, packet: &Packet) {
+        if self.trigger_window.is_none() {
+            return;
+        }
+        let triggers =
+            match aedat::triggers_generated::size_prefixed_root_as_trigger_packet(&packet.buffer) {
+                Ok(result) => result,
+                Err(_) => {
+                    eprintln!("Skipping malformed trigger packet");
+                    return;
+                }
+            };
+        if let Some(elements) = triggers.elements() {
+            self.trigger_timestamps
+                .extend(elements.iter().map(|trigger| trigger.t()));
+        }
+    }
+
+    fn record_imus(&mut self, packet: &Packet) {
+        let imus = match aedat::imus_generated::size_prefixed_root_as_imu_packet(&packet.buffer) {
+            Ok(result) => result,
+            Err(_) => {
+                eprintln!("Skipping malformed IMU packet");
+                return;
+            }
+        };
+        if let Some(elements) = imus.elements() {
+            if self.event_adder.use_imu {
+                self.event_adder
+                    .imu_angular_speed
+                    .extend(elements.iter().map(|imu| {
+                        let gyro = [imu.gyroscope_x(), imu.gyroscope_y(), imu.gyroscope_z()];
+                        let speed = gyro.iter().map(|w| (*w as f64).powi(2)).sum::<f64>().sqrt();
+                        (imu.t(), speed)
+                    }));
+            }
+            self.imu_queue.extend(elements.iter().map(|imu| ImuSample {
+                timestamp: imu.t(),
+                accel: [
+                    imu.accelerometer_x(),
+                    imu.accelerometer_y(),
+                    imu.accelerometer_z(),
+                ],
+                gyro: [imu.gyroscope_x(), imu.gyroscope_y(), imu.gyroscope_z()],
+            }));
+        }
+        if self.imu_queue.len() > IMU_QUEUE_CAPACITY {
+            let excess = self.imu_queue.len() - IMU_QUEUE_CAPACITY;
+            self.imu_queue.drain(..excess);
+        }
+    }
+
+    /// Whether any trigger received so far is within the trigger window of the span
+    /// `[begin_t, end_t]`
+    fn near_trigger(&self, begin_t: i64, end_t: i64) -> bool {
+        let window = self.trigger_window.unwrap_or(0);
+        self.trigger_timestamps
+            .iter()
+            .any(|t| *t >= begin_t - window && *t <= end_t + window)
+    }
+
+    fn queue_event_packet(&mut self, p: TimestampedPacket) {
+        if self.max_desync.is_some() {
+            if let Some(t) = last_event_t(&p.packet) {
+                self.latest_event_t = Some(max(t, self.latest_event_t.unwrap_or(t)));
+            }
+        }
+        self.packet_queue.push_back(p);
+    }
+}
+
+/// Average a non-empty group of images of the same size and type
+fn average_mats<'a>(mut mats: impl Iterator<Item = &'a Mat>) -> Result<Mat, opencv::Error> {
+    let mut sum = mats.next().unwrap().clone();
+    let mut count = 1;
+    for mat in mats {
+        let mut tmp = Mat::default();
+        add_weighted(&sum, 1.0, mat, 1.0, 0.0, &mut tmp, -1)?;
+        sum = tmp;
+        count += 1;
+    }
+    let mut average = Mat::default();
+    sum.convert_to(&mut average, -1, 1.0 / count as f64, 0.0)?;
+    Ok(average)
+}
+
+/// Builds a [`Reconstructor`] from named settings, rather than the positional arguments of
+/// [`Reconstructor::new`]. The input directory, events filename, and mode are required; the rest
+/// default to those of [`ReconstructorConfig::default`].
+#[derive(Debug, Clone, Default)]
+pub struct ReconstructorBuilder {
+    directory: Option<String>,
+    aedat_filename_0: Option<String>,
+    aedat_filename_1: String,
+    mode: Option<String>,
+    config: ReconstructorConfig,
+}
+
+impl ReconstructorBuilder {
+    pub fn new() -> ReconstructorBuilder {
+        ReconstructorBuilder::default()
+    }
+
+    /// The directory containing the input aedat4 file or sockets
+    pub fn directory(mut self, directory: impl Into<String>) -> Self {
+        self.directory = Some(directory.into());
+        self
+    }
+
+    /// The input aedat4 file, or the events socket
+    pub fn aedat_filename_0(mut self, filename: impl Into<String>) -> Self {
+        self.aedat_filename_0 = Some(filename.into());
+        self
+    }
+
+    /// The frames socket. Unused in the "file" mode.
+    pub fn aedat_filename_1(mut self, filename: impl Into<String>) -> Self {
+        self.aedat_filename_1 = filename.into();
+        self
+    }
+
+    /// The input mode: "file", "socket", or "tcp"
+    pub fn mode(mut self, mode: impl Into<String>) -> Self {
+        self.mode = Some(mode.into());
+        self
+    }
+
+    /// Start from a full config. Settings made before this call are replaced.
+    pub fn config(mut self, config: ReconstructorConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Get the reconstruction settings made so far, e.g., to save them with
+    /// [`ReconstructorConfig::save`] and reproduce a run later
+    pub fn to_config(&self) -> ReconstructorConfig {
+        self.config.clone()
+    }
+
+    pub fn start_c(mut self, start_c: f64) -> Self {
+        self.config.start_c = start_c;
+        self
+    }
+
+    /// Whether to optimize c, and how many APS frames to wait between optimizations
+    pub fn optimize_c(mut self, optimize: bool, frequency: u32) -> Self {
+        self.config.optimize_c = optimize;
+        self.config.optimize_c_frequency = frequency;
+        self
+    }
+
+    pub fn optimize_controller(mut self, optimize_controller: bool) -> Self {
+        self.config.optimize_controller = optimize_controller;
+        self
+    }
+
+    pub fn show_display(mut self, show_display: bool) -> Self {
+        self.config.show_display = show_display;
+        self
+    }
+
+    pub fn show_blurred_display(mut self, show_blurred_display: bool) -> Self {
+        self.config.show_blurred_display = show_blurred_display;
+        self
+    }
+
+    pub fn output_fps(mut self, output_fps: f64) -> Self {
+        self.config.output_fps = output_fps;
+        self
+    }
+
+    pub fn output_mode(mut self, output_mode: OutputMode) -> Self {
+        self.config.output_mode = output_mode;
+        self
+    }
+
+    pub fn events_only(mut self, events_only: bool) -> Self {
+        self.config.events_only = events_only;
+        self
+    }
+
+    /// The target maximum latency, in milliseconds
+    pub fn target_latency(mut self, target_latency: f64) -> Self {
+        self.config.target_latency = target_latency;
+        self
+    }
+
+    pub fn simulate_latency(mut self, simulate_latency: bool) -> Self {
+        self.config.simulate_latency = simulate_latency;
+        self
+    }
+
+    pub fn skip_priming(mut self, skip_priming: bool) -> Self {
+        self.config.skip_priming = skip_priming;
+        self
+    }
+
+    pub fn lambda(mut self, lambda: f64) -> Self {
+        self.config.lambda = lambda;
+        self
+    }
+
+    /// Open the input and build the reconstructor. Fails with an [`ArgumentError`] if the
+    /// directory, events filename, or mode wasn't given, or if the settings are invalid (e.g., a
+    /// non-positive `output_fps` or `target_latency`).
+    pub async fn build(self) -> Result<Reconstructor, ReconstructorError> {
+        self.config.validate().map_err(|e| match e {
+            ReconstructorError::ConfigError(message) => ArgumentError(message),
+            e => e,
+        })?;
+        let required = |value: Option<String>, name: &str| {
+            value.ok_or_else(|| ArgumentError(format!("The {} is required", name)))
+        };
+        let source = InputSource {
+            mode: required(self.mode, "input mode")?,
+            directory: required(self.directory, "input directory")?,
+            aedat_filename_0: required(self.aedat_filename_0, "events filename")?,
+            aedat_filename_1: self.aedat_filename_1,
+        };
+        Reconstructor::from_config(self.config, source).await
+    }
+}
+
+/// Get the timestamp of the last event in an event packet
+fn last_event_t(packet: &Packet) -> Option<i64> {
+    aedat::events_generated::size_prefixed_root_as_event_packet(&packet.buffer)
+        .ok()?
+        .elements()?
+        .iter()
+        .last()
+        .map(|event| event.t())
+}
+
+#[derive(Debug)]
+pub struct ReconstructionError {
+    message: String,
+}
+
+impl ReconstructionError {
+    pub fn _new(message: &str) -> ReconstructionError {
+        ReconstructionError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ReconstructionError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "{}", self.message)
+    }
+}
+
+impl std::convert::From<ReconstructorError> for ReconstructionError {
+    fn from(error: ReconstructorError) -> Self {
+        ReconstructionError {
+            message: error.to_string(),
+        }
+    }
+}
+
+impl std::convert::From<ParseError> for ReconstructionError {
+    fn from(error: ParseError) -> Self {
+        ReconstructionError {
+            message: error.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LatentImage {
+    pub frame: Mat,
+}
+
+// use async_trait::async_trait;
+//
+// #[async_trait]
+// impl Iterator for Reconstructor {
+//     type Item = Result<Mat, ReconstructionError>;
+//
+//     /// Get the next reconstructed image
+//     async fn next(&mut self) -> Option<Self::Item> {
+//         return match self.latent_image_queue.pop_front() {
+//             // If we have a queue of images already, just return the next one
+//             Some(image) => Some(Ok(image)),
+//
+//             // Else we need to rebuild the queue
+//             _ => {
+//                 let now = Instant::now();
+//
+//                 if self.event_adder.next_blur_info.is_some() {
+//                     mem::swap(&mut self.event_adder.blur_info, &mut self.event_adder.next_blur_info);
+//                     self.event_adder.next_blur_info = None;
+//                 }
+//                 //
+//                 //     self.fill_packet_queue_to_frame()
+//
+//
+//                 // let join_handle: thread::JoinHandle<_> = thread::spawn(|| {
+//                 match self.get_more_images().await {
+//                     Ok(_) => {}
+//                     Err(_) => return None
+//                 };
+//                 // });
+//                 let running_fps = self.latent_image_queue.len() as f64
+//                     / now.elapsed().as_millis() as f64 * 1000.0;
+//                 print!(
+//                     "\r{} frames in  {}ms -- Current FPS: {:.2}, Current c: {:.5}",
+//                     self.latent_image_queue.len(),
+//                     now.elapsed().as_millis(),
+//                     running_fps,
+//                     self.event_adder.current_c
+//                 );
+//                 io::stdout().flush().unwrap();
+//                 match self.latent_image_queue.pop_front() {
+//                     None => {
+//                         panic!("No images in the returned queue")
+//                     }
+//                     Some(image) => {
+//                         return Some(Ok(image));
+//                     }
+//                 }
+//             }
+//         };
+//     }
+// }
+
+fn split_camera_info(stream: &Stream) -> (u16, u16) {
+    (stream.height, stream.width)
+}
+
+/// Convert an image matrix to a single-channel `CV_64F` [`Mat`] of the same dimensions
+pub fn dmatrix_to_mat(image: &DMatrix<f64>) -> Result<Mat, ReconstructorError> {
+    Mat::try_from_cv(image).map_err(|e| ReconstructorError::ConversionError(e.to_string()))
+}
+
+/// Convert a single-channel [`Mat`] to an image matrix of the same dimensions. Other depths are
+/// converted to `f64` without scaling, and non-continuous [`Mat`]s (e.g., an ROI) are supported.
+pub fn mat_to_dmatrix(mat: &Mat) -> Result<DMatrix<f64>, ReconstructorError> {
+    if mat.channels() != 1 {
+        return Err(ReconstructorError::ConversionError(format!(
+            "Expected a single-channel Mat, but it has {} channels",
+            mat.channels()
+        )));
+    }
+    let mut converted = Mat::default();
+    mat.convert_to(&mut converted, CV_64F, 1.0, 0.0)?;
+    DMatrix::<f64>::try_from_cv(&converted)
+        .map_err(|e| ReconstructorError::ConversionError(e.to_string()))
+}
+
+/// Bilinearly resize an image to the given dimensions
+fn resize_dmatrix(
+    image: &DMatrix<f64>,
+    height: usize,
+    width: usize,
+) -> Result<DMatrix<f64>, ReconstructorError> {
+    let mat = dmatrix_to_mat(image)?;
+    let mut resized = Mat::default();
+    resize(
+        &mat,
+        &mut resized,
+        Size {
+            width: width as i32,
+            height: height as i32,
+        },
+        0.0,
+        0.0,
+        INTER_LINEAR,
+    )?;
+    mat_to_dmatrix(&resized)
+}
+
+/// If [`MyArgs`]`.show_display`, shows the given [`Mat`] in an OpenCV window
+#[cfg(feature = "display")]
+pub fn show_display(window_name: &str, mat: &Mat, wait: i32, reconstructor: &Reconstructor) -> i32 {
+    if reconstructor.show_display {
+        if let Some(manager) = &reconstructor.display_manager {
+            return manager.show(mat, wait).unwrap();
+        }
+        let mut tmp = Mat::default();
+
+        if mat.rows() != 540 {
+            let factor = mat.rows() as f32 / 540.0;
+            resize(
+                mat,
+                &mut tmp,
+                Size {
+                    width: (mat.cols() as f32 / factor) as i32,
+                    height: 540,
+                },
+                0.0,
+                0.0,
+                0,
+            )
+            .unwrap();
+            highgui::imshow(window_name, &tmp).unwrap();
+        } else {
+            highgui::imshow(window_name, mat).unwrap();
+        }
+        return highgui::wait_key(wait).unwrap();
+    }
+    -1
+}
+
+/// Without the `display` feature, there's no window to show, so this returns -1 as though no key
+/// was pressed
+#[cfg(not(feature = "display"))]
+pub fn show_display(
+    _window_name: &str,
+    _mat: &Mat,
+    _wait: i32,
+    _reconstructor: &Reconstructor,
+) -> i32 {
+    -1
+}
+
+/// TODO: Remove. Just for debugging.
+#[cfg(feature = "display")]
+pub fn _show_display_force(window_name: &str, mat: &Mat, wait: i32, normalize: bool) {
+    let mut normed = mat.clone();
+    let mut tmp = Mat::default();
+    if normalize {
+        opencv::core::normalize(
+            &mat,
+            &mut normed,
+            0.0,
+            1.0,
+            opencv::core::NORM_MINMAX,
+            -1,
+            &opencv::core::no_array(),
+        )
+        .unwrap();
+    }
+
+    if mat.rows() != 540 {
+        let factor = mat.rows() as f32 / 540.0;
+        resize(
+            &normed,
             &mut tmp,
             Size {
                 width: (mat.cols() as f32 / factor) as i32,
@@ -654,9 +3229,2069 @@ pub fn _show_display_force(window_name: &str, mat: &Mat, wait: i32, normalize: b
             0,
         )
         .unwrap();
-        highgui::imshow(window_name, &tmp).unwrap();
-    } else {
-        highgui::imshow(window_name, mat).unwrap();
+        highgui::imshow(window_name, &tmp).unwrap();
+    } else {
+        highgui::imshow(window_name, mat).unwrap();
+    }
+    highgui::wait_key(wait).unwrap();
+}
+
+/// Without the `display` feature, this does nothing
+#[cfg(not(feature = "display"))]
+pub fn _show_display_force(_window_name: &str, _mat: &Mat, _wait: i32, _normalize: bool) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_support::{self, EXPOSURES};
+    use crate::util::threaded_decoder::setup_open_packets;
+
+    /// The largest intensity difference between horizontally adjacent pixels of a row
+    fn max_step(image: &DMatrix<f64>, row: usize) -> f64 {
+        (1..image.ncols())
+            .map(|col| (image[(row, col)] - image[(row, col - 1)]).abs())
+            .fold(0.0, f64::max)
+    }
+
+    /// The frame reconstructed for the interval starting at `t`
+    fn frame_at(frames: &[ReconstructedFrame], t: i64) -> DMatrix<f64> {
+        let frame = frames
+            .iter()
+            .find(|frame| frame.timestamp == Some(t))
+            .unwrap();
+        mat_to_dmatrix(&frame.image).unwrap()
+    }
+
+    fn assert_same_frames(frames: &[ReconstructedFrame], expected: &[ReconstructedFrame]) {
+        assert_eq!(frames.len(), expected.len());
+        for (frame, expected) in frames.iter().zip(expected) {
+            assert_eq!(frame.timestamp, expected.timestamp);
+            assert_eq!(
+                mat_to_dmatrix(&frame.image).unwrap(),
+                mat_to_dmatrix(&expected.image).unwrap()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn super_resolution_deblurs_on_the_finer_grid() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..1]))
+                .await
+                .unwrap();
+        let frames = test_support::all_frames(&mut reconstructor).await;
+        let sensor_latent = resize_dmatrix(&frame_at(&frames, 5000), 32, 32).unwrap();
+
+        let config = ReconstructorConfig {
+            super_resolution_factor: 2.0,
+            ..test_support::config()
+        };
+        let mut reconstructor = test_support::reconstructor(config, scene.record(&EXPOSURES[..1]))
+            .await
+            .unwrap();
+        assert_eq!(reconstructor.dimensions(), (32, 32));
+        let frames = test_support::all_frames(&mut reconstructor).await;
+        let latent = frame_at(&frames, 5000);
+        assert_eq!(latent.shape(), (32, 32));
+
+        // The edge is about as sharp as at the sensor resolution, and much sharper than in the
+        // upscaled blurred frame
+        let blurred = resize_dmatrix(&scene.blurred(2000, 7000), 32, 32).unwrap();
+        assert!(max_step(&latent, 8) > 0.8 * max_step(&sensor_latent, 8));
+        assert!(max_step(&latent, 8) > 1.5 * max_step(&blurred, 8));
+    }
+
+    #[tokio::test]
+    async fn empty_event_packets_dont_end_the_stream() {
+        let scene = test_support::moving_edge(&EXPOSURES);
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES))
+                .await
+                .unwrap();
+        let expected = test_support::all_frames(&mut reconstructor).await;
+
+        // Empty packets ahead of the events leading up to each frame
+        let packets = scene
+            .record(&EXPOSURES)
+            .into_iter()
+            .enumerate()
+            .flat_map(|(index, packet)| match index % 3 {
+                0 => vec![
+                    test_support::elementless_event_packet(),
+                    test_support::event_packet(&[]),
+                    packet,
+                ],
+                _ => vec![packet],
+            })
+            .collect();
+        let mut reconstructor = test_support::reconstructor(test_support::config(), packets)
+            .await
+            .unwrap();
+        let frames = test_support::all_frames(&mut reconstructor).await;
+        assert_same_frames(&frames, &expected);
+    }
+
+    #[tokio::test]
+    async fn energy_for_c_needs_a_frame() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..1]))
+                .await
+                .unwrap();
+        reconstructor.event_adder.blur_info = None;
+        assert!(matches!(
+            reconstructor.energy_for_c(test_support::C),
+            Err(ReconstructorError::NoFrameBuffered)
+        ));
+    }
+
+    #[tokio::test]
+    async fn energy_for_c_is_repeatable_and_leaves_the_frames_alone() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        let expected = test_support::all_frames(&mut reconstructor).await;
+
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        let mut frames = vec![reconstructor.next_frame().await.unwrap().unwrap()];
+        let energy = reconstructor.energy_for_c(test_support::C).unwrap();
+        assert!(energy.is_finite());
+        reconstructor.energy_for_c(0.4).unwrap();
+        assert_eq!(reconstructor.energy_for_c(test_support::C).unwrap(), energy);
+
+        frames.extend(test_support::all_frames(&mut reconstructor).await);
+        assert_same_frames(&frames, &expected);
+    }
+
+    #[tokio::test]
+    async fn energy_for_c_uses_the_frames_own_reference() {
+        // Events throughout the exposure except in one bin, so the quietest time depends on them
+        let exposure = (2000, 17000);
+        let bin_length = (exposure.1 - exposure.0) / 16;
+        let events = (0..16_i16)
+            .filter(|bin| *bin != 11)
+            .flat_map(|bin| {
+                let t = exposure.0 + bin as i64 * bin_length + 400;
+                (0..3).map(move |step| Event::new(t + step, bin, 0, step % 2 == 0))
+            })
+            .collect();
+        let scene = test_support::Scene::new(
+            DMatrix::from_element(
+                test_support::HEIGHT as usize,
+                test_support::WIDTH as usize,
+                test_support::DARK,
+            ),
+            events,
+        );
+        let config = ReconstructorConfig {
+            output_mode: OutputMode::SharpFrameOnly,
+            latent_reference: LatentReference::MinimumMotion,
+            ..test_support::config()
+        };
+        let mut reconstructor =
+            test_support::reconstructor(config.clone(), scene.record(&[exposure]))
+                .await
+                .unwrap();
+        let frame = reconstructor.next_frame().await.unwrap().unwrap();
+        assert_eq!(
+            reconstructor.energy_for_c(test_support::C).ok(),
+            frame.energy
+        );
+
+        // Without the frame's events, there's nothing to evaluate the energy on
+        let config = ReconstructorConfig {
+            retain_frame_events: false,
+            ..config
+        };
+        let mut reconstructor = test_support::reconstructor(config, scene.record(&[exposure]))
+            .await
+            .unwrap();
+        reconstructor.next_frame().await.unwrap().unwrap();
+        assert!(matches!(
+            reconstructor.energy_for_c(test_support::C),
+            Err(ArgumentError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn event_count_map_counts_the_frames_events() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let config = ReconstructorConfig {
+            output_event_counts: true,
+            ..test_support::config()
+        };
+        let mut reconstructor = test_support::reconstructor(config, scene.record(&EXPOSURES[..1]))
+            .await
+            .unwrap();
+        reconstructor.next_frame().await.unwrap().unwrap();
+        let counts = mat_to_dmatrix(reconstructor.last_event_count_map().unwrap()).unwrap();
+
+        let during = scene
+            .events
+            .iter()
+            .filter(|event| (2000..=7000).contains(&event.t()))
+            .count();
+        assert_eq!(counts.sum(), during as f64);
+        assert_eq!(counts[(0, 1)], test_support::EDGE_EVENTS as f64);
+        assert_eq!(counts[(0, 3)], 0.0);
+    }
+
+    #[tokio::test]
+    async fn output_intensity_limit_clamps_over_integrated_frames() {
+        // Too large a c over-integrates the edge's events, brightening the frames
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let brightest = |limit| {
+            let config = ReconstructorConfig {
+                start_c: 0.5,
+                output_intensity_limit: limit,
+                ..test_support::config()
+            };
+            let packets = scene.record(&EXPOSURES[..2]);
+            async move {
+                let mut reconstructor = test_support::reconstructor(config, packets).await.unwrap();
+                let frames = test_support::all_frames(&mut reconstructor).await;
+                let images: Vec<DMatrix<f64>> = frames
+                    .iter()
+                    .map(|frame| mat_to_dmatrix(&frame.image).unwrap())
+                    .collect();
+                assert!(images.iter().all(|image| image.min() >= 0.0));
+                images.iter().map(|image| image.max()).fold(0.0, f64::max)
+            }
+        };
+        assert!(brightest(None).await > 0.5);
+        assert!(brightest(Some(0.4)).await <= 0.4);
+    }
+
+    #[tokio::test]
+    async fn log_space_intermediates_match_linear_space() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let linear =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+        let config = ReconstructorConfig {
+            log_space_intermediates: true,
+            ..test_support::config()
+        };
+        let log_space = test_support::frames(config, scene.record(&EXPOSURES[..2])).await;
+
+        assert_eq!(log_space.len(), linear.len());
+        for (log_frame, linear_frame) in log_space.iter().zip(&linear) {
+            assert_eq!(log_frame.timestamp, linear_frame.timestamp);
+            let log_image = mat_to_dmatrix(&log_frame.image).unwrap();
+            let linear_image = mat_to_dmatrix(&linear_frame.image).unwrap();
+            assert!((log_image - linear_image).abs().max() < 1e-12);
+        }
+
+        // Between the APS frames, a pixel without events keeps the last latent intensity
+        let last_latent = frame_at(&log_space, 7000);
+        for t in [8000, 9000, 10000, 11000] {
+            let intermediate = frame_at(&log_space, t);
+            assert!((intermediate[(15, 0)] - last_latent[(15, 0)]).abs() < 1e-12);
+        }
+    }
+
+    #[tokio::test]
+    async fn sharp_frame_only_gives_one_frame_per_aps_frame() {
+        let scene = test_support::moving_edge(&EXPOSURES);
+        let config = ReconstructorConfig {
+            output_mode: OutputMode::SharpFrameOnly,
+            ..test_support::config()
+        };
+        let frames = test_support::frames(config, scene.record(&EXPOSURES)).await;
+        assert_eq!(frames.len(), EXPOSURES.len());
+        for (frame, (begin_t, end_t)) in frames.iter().zip(EXPOSURES) {
+            assert!((begin_t..=end_t).contains(&frame.timestamp.unwrap()));
+        }
+
+        // Six intervals in each exposure, and four between each pair of exposures
+        let frames = test_support::frames(test_support::config(), scene.record(&EXPOSURES)).await;
+        assert_eq!(
+            frames.len(),
+            6 * EXPOSURES.len() + 4 * (EXPOSURES.len() - 1)
+        );
+    }
+
+    #[test]
+    fn c_optimization_is_suspended_while_latency_is_over_target() {
+        let mut state = COptimizationState::Active;
+        let mut optimizes = vec![];
+        for over_target in [false, true, true, false, true, false] {
+            state = state.on_latency(over_target);
+            optimizes.push(state.optimizes());
+        }
+        assert_eq!(optimizes, [true, false, false, true, false, true]);
+        assert_eq!(
+            COptimizationState::Active.on_latency(true),
+            COptimizationState::Suspended
+        );
+
+        // The controller never overrides the user disabling it
+        for over_target in [true, false] {
+            assert_eq!(
+                COptimizationState::Disabled.on_latency(over_target),
+                COptimizationState::Disabled
+            );
+        }
+        assert!(!COptimizationState::Disabled.optimizes());
+    }
+
+    #[tokio::test]
+    async fn frames_lagging_the_event_stream_are_dropped() {
+        let scene = test_support::moving_edge(&EXPOSURES);
+
+        // The second frame arrives after the events up to the end of the third exposure, as
+        // though its socket had stalled
+        let mut packets = scene.record(&EXPOSURES);
+        let lagging = packets.remove(4);
+        packets.insert(6, lagging);
+        let config = ReconstructorConfig {
+            max_desync: Some(5000),
+            ..test_support::config()
+        };
+
+        let mut reconstructor =
+            test_support::reconstructor_with_mode(config.clone(), "socket", packets.clone())
+                .await
+                .unwrap();
+        let frames = test_support::all_frames(&mut reconstructor).await;
+        assert_eq!(reconstructor.desync_dropped_frames(), 1);
+
+        // Frames read from a file are never dropped, and the reconstruction still reaches the
+        // last frame after the drop
+        let mut reconstructor = test_support::reconstructor(config.clone(), packets)
+            .await
+            .unwrap();
+        let expected = test_support::all_frames(&mut reconstructor).await;
+        assert_eq!(reconstructor.desync_dropped_frames(), 0);
+        assert_eq!(
+            frames.last().unwrap().timestamp,
+            expected.last().unwrap().timestamp
+        );
+
+        // Nor are frames that keep up with the events
+        let mut reconstructor =
+            test_support::reconstructor_with_mode(config, "socket", scene.record(&EXPOSURES))
+                .await
+                .unwrap();
+        test_support::all_frames(&mut reconstructor).await;
+        assert_eq!(reconstructor.desync_dropped_frames(), 0);
+    }
+
+    #[tokio::test]
+    async fn supersampling_averages_the_sub_frames_within_the_shutter() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let sub_frames =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+
+        for (shutter_angle, shutter) in [(360.0, 2), (180.0, 1)] {
+            let config = ReconstructorConfig {
+                output_fps: 500.0,
+                supersamples: 2,
+                shutter_angle,
+                ..test_support::config()
+            };
+            let frames = test_support::frames(config, scene.record(&EXPOSURES[..2])).await;
+            assert!(!frames.is_empty());
+            for frame in &frames {
+                let t = frame.timestamp.unwrap();
+                let expected = (0..shutter)
+                    .map(|sample| frame_at(&sub_frames, t + 1000 * sample))
+                    .fold(DMatrix::zeros(16, 16), |sum, sub_frame| sum + sub_frame)
+                    / shutter as f64;
+                let image = mat_to_dmatrix(&frame.image).unwrap();
+                assert!((image - expected).abs().max() < 1e-9);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn crop_margin_trims_each_side_of_the_output() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let expected =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+
+        let config = ReconstructorConfig {
+            output_crop_margin: 2,
+            ..test_support::config()
+        };
+        let mut reconstructor = test_support::reconstructor(config, scene.record(&EXPOSURES[..2]))
+            .await
+            .unwrap();
+        assert_eq!(reconstructor.output_dimensions(), (12, 12));
+        let frames = test_support::all_frames(&mut reconstructor).await;
+        assert_eq!(frames.len(), expected.len());
+        for (frame, expected) in frames.iter().zip(&expected) {
+            let expected = mat_to_dmatrix(&expected.image).unwrap();
+            assert_eq!(
+                mat_to_dmatrix(&frame.image).unwrap(),
+                expected.view((2, 2), (12, 12))
+            );
+        }
+
+        assert!(reconstructor.set_output_crop_margin(8).is_err());
+    }
+
+    #[tokio::test]
+    async fn prefetched_frames_are_returned_without_reading_more_packets() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let expected =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        reconstructor.prefetch(5).await.unwrap();
+        let bytes_received = reconstructor.packet_receiver.bytes_received;
+        let mut frames = vec![];
+        for _ in 0..5 {
+            frames.push(reconstructor.next_frame().await.unwrap().unwrap());
+        }
+        assert_eq!(reconstructor.packet_receiver.bytes_received, bytes_received);
+
+        frames.extend(test_support::all_frames(&mut reconstructor).await);
+        assert_same_frames(&frames, &expected);
+    }
+
+    #[tokio::test]
+    async fn full_sequence_frames_carry_their_intervals_events() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let frames =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+        let mut checked = 0;
+        for frame in &frames {
+            let begin_t = frame.timestamp.unwrap();
+            let end_t = begin_t + 1000;
+            assert_eq!(frame.exposure_begin_t, Some(begin_t));
+            assert_eq!(frame.last_interval_start_t, Some(end_t));
+            assert_eq!(frame.events_before.as_deref(), Some(&[][..]));
+            let events = frame.events_after.as_ref().unwrap();
+            assert!(events
+                .iter()
+                .all(|event| event.t >= begin_t && event.t < end_t));
+
+            // The events of the intervals within an exposure are all buffered
+            if EXPOSURES[..2]
+                .iter()
+                .any(|&(exposure_begin_t, exposure_end_t)| {
+                    begin_t >= exposure_begin_t && end_t <= exposure_end_t
+                })
+            {
+                let expected = scene
+                    .events
+                    .iter()
+                    .filter(|event| event.t() >= begin_t && event.t() < end_t)
+                    .count();
+                assert_eq!(events.len(), expected);
+                checked += 1;
+            }
+        }
+        assert_eq!(checked, 10);
+    }
+
+    #[tokio::test]
+    async fn intermediate_frames_hold_the_latent_across_an_event_gap() {
+        let edge = test_support::moving_edge(&EXPOSURES[..2]);
+        // Only a single event in the bottom row between the exposures
+        let events = edge
+            .events
+            .into_iter()
+            .filter(|event| event.t() <= EXPOSURES[0].1 || event.t() >= EXPOSURES[1].0)
+            .chain([Event::new(11500, 0, 15, true)])
+            .collect();
+        let scene = test_support::Scene::new(edge.initial, events);
+        let frames =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+
+        let latent = frame_at(&frames, 7000);
+        for t in (8000..12000).step_by(1000) {
+            assert_eq!(frame_at(&frames, t).rows(0, 15), latent.rows(0, 15));
+        }
+        assert!(frames.iter().any(|frame| frame.timestamp == Some(12000)));
+    }
+
+    #[test]
+    fn conversions_between_dmatrix_and_mat_round_trip() {
+        let image = DMatrix::<f64>::from_fn(3, 5, |row, col| (row * 5 + col) as f64 / 16.0);
+        let mat = dmatrix_to_mat(&image).unwrap();
+        assert_eq!((mat.rows(), mat.cols(), mat.typ()), (3, 5, CV_64F));
+        assert_eq!(*mat.at_2d::<f64>(1, 4).unwrap(), image[(1, 4)]);
+        assert_eq!(mat_to_dmatrix(&mat).unwrap(), image);
+
+        // Other depths are converted without scaling
+        let mat =
+            Mat::new_rows_cols_with_default(2, 3, opencv::core::CV_8U, Scalar::all(200.0)).unwrap();
+        assert_eq!(
+            mat_to_dmatrix(&mat).unwrap(),
+            DMatrix::from_element(2, 3, 200.0)
+        );
+
+        let mat =
+            Mat::new_rows_cols_with_default(2, 3, opencv::core::CV_8UC3, Scalar::all(1.0)).unwrap();
+        assert!(matches!(
+            mat_to_dmatrix(&mat),
+            Err(ReconstructorError::ConversionError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn roi_frames_are_placed_at_their_offset() {
+        let blurred = |offset| async move {
+            let roi = DMatrix::from_element(6, 8, 0.6);
+            let packets = vec![
+                test_support::event_packet(&[]),
+                test_support::roi_frame_packet(2000, 7000, &roi, offset),
+                test_support::event_packet(&[]),
+            ];
+            let reconstructor = test_support::reconstructor(test_support::config(), packets)
+                .await
+                .unwrap();
+            let blur_info = reconstructor.event_adder.blur_info.as_ref().unwrap();
+            blur_info.blurred_image.clone()
+        };
+
+        let image = blurred((4, 3)).await;
+        assert_eq!(image.view((3, 4), (6, 8)), DMatrix::from_element(6, 8, 0.6));
+        assert!((image.sum() - 0.6 * 48.0).abs() < 1e-9);
+
+        // The part of the region outside the sensor is dropped
+        let image = blurred((12, 13)).await;
+        assert_eq!(
+            image.view((13, 12), (3, 4)),
+            DMatrix::from_element(3, 4, 0.6)
+        );
+        assert!((image.sum() - 0.6 * 12.0).abs() < 1e-9);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn skipping_priming_keeps_the_first_frame() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let expected =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+        let source = |name| test_support::aedat_file(name, &scene.record(&EXPOSURES[..2]));
+
+        // Priming skips over the first frame to the events after it
+        let mut reconstructor =
+            Reconstructor::from_config(test_support::config(), source("davis-edi-primed.aedat4"))
+                .await
+                .unwrap();
+        let frames = test_support::all_frames(&mut reconstructor).await;
+        assert_eq!(frames[0].timestamp, Some(EXPOSURES[1].0));
+
+        let config = ReconstructorConfig {
+            skip_priming: true,
+            ..test_support::config()
+        };
+        let mut reconstructor =
+            Reconstructor::from_config(config, source("davis-edi-unprimed.aedat4"))
+                .await
+                .unwrap();
+        let frames = test_support::all_frames(&mut reconstructor).await;
+        assert_same_frames(&frames, &expected);
+    }
+
+    #[tokio::test]
+    async fn quality_check_flags_a_wrong_c() {
+        // Every pixel brightens near the end of the exposure
+        let exposures = [(2000, 7000)];
+        let events = (0..test_support::WIDTH as i16)
+            .flat_map(|x| (0..test_support::HEIGHT as i16 - 1).map(move |y| (x, y)))
+            .flat_map(|(x, y)| {
+                (0..test_support::EDGE_EVENTS).map(move |step| Event::new(6500 + step, x, y, true))
+            })
+            .chain(test_support::exposure_anchors(&exposures))
+            .collect();
+        let scene =
+            test_support::Scene::new(DMatrix::from_element(16, 16, test_support::DARK), events);
+        let stats = |start_c| {
+            let config = ReconstructorConfig {
+                start_c,
+                quality_check_threshold: Some(0.18),
+                ..test_support::config()
+            };
+            let packets = scene.record(&exposures);
+            async move {
+                let mut reconstructor = test_support::reconstructor(config, packets).await.unwrap();
+                reconstructor.next_frame().await.unwrap().unwrap();
+                reconstructor.last_frame_stats().unwrap().clone()
+            }
+        };
+
+        let stats_at_c = stats(test_support::C).await;
+        assert_eq!(stats_at_c.c, test_support::C);
+        assert!((stats_at_c.blurred_mean - scene.blurred(2000, 7000).mean()).abs() < 0.01);
+        assert!(!stats_at_c.quality_warning);
+
+        let stats_off_c = stats(2.0 * test_support::C).await;
+        assert!(stats_off_c.quality_warning);
+        assert!(
+            (stats_off_c.latent_mean - stats_off_c.blurred_mean).abs()
+                > (stats_at_c.latent_mean - stats_at_c.blurred_mean).abs()
+        );
+    }
+
+    #[tokio::test]
+    async fn nanosecond_timestamps_reconstruct_like_microseconds() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let expected =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+
+        let events = scene
+            .events
+            .iter()
+            .map(|event| Event::new(event.t() * 1000, event.x(), event.y(), event.on()))
+            .collect();
+        let scene = test_support::Scene::new(scene.initial.clone(), events);
+        let exposures: Vec<(i64, i64)> = EXPOSURES[..2]
+            .iter()
+            .map(|&(begin_t, end_t)| (begin_t * 1000, end_t * 1000))
+            .collect();
+        let config = ReconstructorConfig {
+            timebase_hz: 1.0e9,
+            ..test_support::config()
+        };
+        let frames = test_support::frames(config, scene.record(&exposures)).await;
+
+        assert_eq!(frames.len(), expected.len());
+        for (frame, expected) in frames.iter().zip(&expected) {
+            assert_eq!(frame.timestamp, expected.timestamp.map(|t| t * 1000));
+            let difference =
+                mat_to_dmatrix(&frame.image).unwrap() - mat_to_dmatrix(&expected.image).unwrap();
+            assert!(difference.abs().max() < 1e-9);
+        }
+    }
+
+    #[tokio::test]
+    async fn npz_export_holds_every_frame() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let expected =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        let path = std::env::temp_dir().join("davis-edi-export.npz");
+        reconstructor.export_npz(&path).await.unwrap();
+
+        // The arrays are stored in the order they're written
+        let mut npz = ndarray_npy::NpzReader::new(File::open(&path).unwrap()).unwrap();
+        let frames: Array3<f64> = npz.by_index(0).unwrap();
+        let timestamps: Array1<i64> = npz.by_index(1).unwrap();
+        let c_values: Array1<f64> = npz.by_index(2).unwrap();
+        assert_eq!(frames.shape(), [expected.len(), 16, 16]);
+        assert_eq!(c_values.len(), expected.len());
+        assert!(c_values.iter().all(|c| *c == test_support::C));
+        for (index, expected) in expected.iter().enumerate() {
+            assert_eq!(Some(timestamps[index]), expected.timestamp);
+            let expected = mat_to_dmatrix(&expected.image).unwrap();
+            let frame = DMatrix::from_fn(16, 16, |row, col| frames[[index, row, col]]);
+            assert_eq!(frame, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_source_without_frames_has_no_frame_buffered() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let packets = vec![test_support::event_packet(&scene.events)];
+        assert!(matches!(
+            test_support::reconstructor(test_support::config(), packets).await,
+            Err(ReconstructorError::NoFrameBuffered)
+        ));
+    }
+
+    #[tokio::test]
+    async fn raw_frames_are_row_major_little_endian_f32() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let expected =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        let mut count = 0;
+        while let Some(raw) = reconstructor.next_raw().await {
+            let (bytes, header) = raw.unwrap();
+            let expected = &expected[count];
+            assert_eq!(Some(header.timestamp), expected.timestamp);
+            assert_eq!((header.width, header.height), (16, 16));
+            assert_eq!(header.c, test_support::C);
+            assert_eq!(bytes.len(), 16 * 16 * 4);
+
+            let expected = mat_to_dmatrix(&expected.image).unwrap();
+            for (row, col) in [(0, 0), (3, 7), (15, 2)] {
+                let start = 4 * (row * 16 + col);
+                let px = f32::from_le_bytes(bytes[start..start + 4].try_into().unwrap());
+                assert_eq!(px, expected[(row, col)] as f32);
+            }
+            count += 1;
+        }
+        assert_eq!(count, expected.len());
+    }
+
+    #[tokio::test]
+    async fn intermediate_frames_across_a_gap_are_capped() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let timestamps = |max_intermediate_frames| {
+            let config = ReconstructorConfig {
+                max_intermediate_frames,
+                ..test_support::config()
+            };
+            let packets = scene.record(&EXPOSURES[..2]);
+            async move {
+                test_support::frames(config, packets)
+                    .await
+                    .iter()
+                    .map(|frame| frame.timestamp.unwrap())
+                    .filter(|t| *t > EXPOSURES[0].1 && *t < EXPOSURES[1].0)
+                    .collect::<Vec<i64>>()
+            }
+        };
+        assert_eq!(timestamps(None).await, [8000, 9000, 10000, 11000]);
+        assert_eq!(timestamps(Some(2)).await, [8000, 9000]);
+    }
+
+    #[tokio::test]
+    async fn input_frames_are_averaged_with_the_previous_ones() {
+        let flat = |intensity| DMatrix::from_element(16, 16, intensity);
+        let packets = vec![
+            test_support::event_packet(&[]),
+            test_support::frame_packet(2000, 7000, &flat(0.2)),
+            test_support::event_packet(&[]),
+            test_support::frame_packet(12000, 17000, &flat(0.6)),
+            test_support::event_packet(&[]),
+            test_support::frame_packet(22000, 27000, &flat(1.0)),
+            test_support::event_packet(&[]),
+        ];
+        let config = ReconstructorConfig {
+            input_temporal_average: 2,
+            ..test_support::config()
+        };
+
+        // Without any events, the latent images are just the blurred images
+        let frames = test_support::frames(config, packets).await;
+        for (t, intensity) in [(2000, 0.2), (12000, 0.4), (22000, 0.8)] {
+            assert!((frame_at(&frames, t) - flat(intensity)).abs().max() < 1e-9);
+        }
+    }
+
+    #[tokio::test]
+    async fn only_frames_near_a_trigger_are_reconstructed() {
+        let scene = test_support::moving_edge(&EXPOSURES);
+        let mut packets = vec![test_support::trigger_packet(&[22500])];
+        packets.extend(scene.record(&EXPOSURES));
+        let config = ReconstructorConfig {
+            trigger_window: Some(1000),
+            ..test_support::config()
+        };
+        let frames = test_support::frames(config, packets).await;
+        let timestamps: Vec<i64> = frames
+            .iter()
+            .map(|frame| frame.timestamp.unwrap())
+            .collect();
+        assert_eq!(timestamps, [22000, 23000]);
+
+        // Without gating, the triggers are ignored
+        let mut packets = vec![test_support::trigger_packet(&[22500])];
+        packets.extend(scene.record(&EXPOSURES));
+        let frames = test_support::frames(test_support::config(), packets).await;
+        assert_eq!(frames[0].timestamp, Some(EXPOSURES[0].0));
+    }
+
+    #[tokio::test]
+    async fn temporal_consistency_measures_flicker_on_static_pixels() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..1]))
+                .await
+                .unwrap();
+        reconstructor.next_frame().await.unwrap().unwrap();
+        assert_eq!(reconstructor.temporal_consistency(), None);
+
+        // The edge crosses column 1 between these frames, and nothing else changes
+        reconstructor.next_frame().await.unwrap().unwrap();
+        let frame = reconstructor.next_frame().await.unwrap().unwrap();
+        assert_eq!(frame.timestamp, Some(4000));
+        assert!(reconstructor.temporal_consistency().unwrap() < 1e-9);
+
+        // Brighten the earlier frame everywhere
+        let (previous, previous_t) = reconstructor.previous_returned.take().unwrap();
+        let brighter = mat_to_dmatrix(&previous).unwrap().add_scalar(0.1);
+        reconstructor.previous_returned = Some((dmatrix_to_mat(&brighter).unwrap(), previous_t));
+        assert!((reconstructor.temporal_consistency().unwrap() - 0.1).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn reverse_mode_emits_each_frames_latents_last_to_first() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let forward =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+        let config = ReconstructorConfig {
+            reverse: true,
+            ..test_support::config()
+        };
+        let reversed = test_support::frames(config, scene.record(&EXPOSURES[..2])).await;
+
+        // The first APS frame's latents come out from its end back to its start
+        let timestamps: Vec<i64> = reversed
+            .iter()
+            .map(|frame| frame.timestamp.unwrap())
+            .collect();
+        assert_eq!(&timestamps[..6], &[7000, 6000, 5000, 4000, 3000, 2000]);
+        assert_eq!(reversed.len(), forward.len());
+        for frame in &reversed {
+            assert_eq!(
+                mat_to_dmatrix(&frame.image).unwrap(),
+                frame_at(&forward, frame.timestamp.unwrap())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn reconstructing_reversed_returns_the_forward_frames_last_to_first() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let forward =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        let reversed = reconstructor.reconstruct_reversed().await.unwrap();
+
+        assert_eq!(reversed.len(), forward.len());
+        for ((image, timestamp), expected) in reversed.iter().zip(forward.iter().rev()) {
+            assert_eq!(Some(*timestamp), expected.timestamp);
+            assert_eq!(
+                mat_to_dmatrix(image).unwrap(),
+                mat_to_dmatrix(&expected.image).unwrap()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn latent_carry_policy_resets_the_latent_after_a_scene_cut() {
+        let (height, width) = (test_support::HEIGHT as usize, test_support::WIDTH as usize);
+        // A single event in the bottom row between the exposures, so the gap gets intermediates
+        let scene = test_support::Scene::new(
+            DMatrix::from_element(height, width, 0.4),
+            vec![Event::new(11500, 0, 15, true)],
+        );
+        let mut packets = scene.record(&EXPOSURES[..2]);
+        // Cut to a much brighter scene for the second frame
+        packets[4] = test_support::frame_packet(
+            EXPOSURES[1].0,
+            EXPOSURES[1].1,
+            &DMatrix::from_element(height, width, 0.8),
+        );
+        let frames_with = |latent_carry| {
+            let packets = packets.clone();
+            async move {
+                let config = ReconstructorConfig {
+                    latent_carry,
+                    ..test_support::config()
+                };
+                test_support::frames(config, packets).await
+            }
+        };
+        // The gap's intermediate frames all match the frame at `reference_t` above the event
+        let assert_gap_holds = |frames: &[ReconstructedFrame], reference_t: i64| {
+            let reference = frame_at(frames, reference_t).rows(0, 15).into_owned();
+            for t in (8000..12000).step_by(1000) {
+                let difference = frame_at(frames, t).rows(0, 15) - &reference;
+                assert!(difference.abs().max() < 1e-9);
+            }
+        };
+
+        // Carrying the latent forward leaves the old scene in the gap
+        assert_gap_holds(&frames_with(LatentCarry::Always).await, EXPOSURES[0].1);
+        let threshold = 0.5;
+        let frames = frames_with(LatentCarry::ResetOnSceneChange { threshold }).await;
+        assert_gap_holds(&frames, EXPOSURES[0].1);
+
+        // Resetting starts the gap from the new scene
+        assert_gap_holds(&frames_with(LatentCarry::Never).await, EXPOSURES[1].0);
+        let threshold = 0.1;
+        let frames = frames_with(LatentCarry::ResetOnSceneChange { threshold }).await;
+        assert_gap_holds(&frames, EXPOSURES[1].0);
+    }
+
+    #[tokio::test]
+    async fn progress_rises_to_one_over_a_file() {
+        let scene = test_support::moving_edge(&EXPOSURES);
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES))
+                .await
+                .unwrap();
+        let mut progress = vec![reconstructor.progress().unwrap()];
+        while let Some(frame) = reconstructor.next_frame().await {
+            frame.unwrap();
+            progress.push(reconstructor.progress().unwrap());
+        }
+        assert!(progress[0] > 0.0);
+        assert!(progress.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(progress[0] < progress[progress.len() - 1]);
+        assert_eq!(progress.last(), Some(&1.0));
+
+        let reconstructor = test_support::reconstructor_with_mode(
+            test_support::config(),
+            "socket",
+            scene.record(&EXPOSURES),
+        )
+        .await
+        .unwrap();
+        assert_eq!(reconstructor.progress(), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn deterministic_runs_are_identical() {
+        let scene = test_support::moving_edge(&EXPOSURES);
+        let source =
+            test_support::aedat_file("davis-edi-deterministic.aedat4", &scene.record(&EXPOSURES));
+        let config = ReconstructorConfig {
+            optimize_c: true,
+            deterministic: true,
+            ..test_support::config()
+        };
+        let run = |config: ReconstructorConfig| {
+            let source = source.clone();
+            async move {
+                let mut reconstructor = Reconstructor::from_config(config, source).await.unwrap();
+                test_support::all_frames(&mut reconstructor).await
+            }
+        };
+        let frames = run(config.clone()).await;
+        assert!(!frames.is_empty());
+        let rerun = run(config.clone()).await;
+        assert_same_frames(&rerun, &frames);
+        for (frame, expected) in rerun.iter().zip(&frames) {
+            assert_eq!(frame.contrast_c, expected.contrast_c);
+        }
+
+        // The threaded reader reaches the same result on this small file
+        let threaded = ReconstructorConfig {
+            deterministic: false,
+            ..config.clone()
+        };
+        assert_same_frames(&run(threaded).await, &frames);
+
+        let socket = InputSource {
+            mode: "socket".to_string(),
+            ..source
+        };
+        assert!(matches!(
+            Reconstructor::from_config(config, socket).await,
+            Err(ReconstructorError::ArgumentError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn variance_is_the_latent_spread_plus_an_event_prior() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let config = ReconstructorConfig {
+            estimate_variance: true,
+            ..test_support::config()
+        };
+        let mut reconstructor = test_support::reconstructor(config, scene.record(&EXPOSURES[..1]))
+            .await
+            .unwrap();
+        let latents: Vec<DMatrix<f64>> = test_support::all_frames(&mut reconstructor)
+            .await
+            .iter()
+            .map(|frame| mat_to_dmatrix(&frame.image).unwrap())
+            .collect();
+        assert_eq!(latents.len(), 6);
+        let variance = mat_to_dmatrix(&reconstructor.last_variance_map().unwrap()).unwrap();
+        assert_eq!(variance.shape(), latents[0].shape());
+
+        let expected = |pixel: (usize, usize), events: f64| {
+            let values: Vec<f64> = latents.iter().map(|latent| latent[pixel]).collect();
+            let mean = values.iter().sum::<f64>() / 6.0;
+            let spread = values.iter().map(|v| v * v).sum::<f64>() / 6.0 - mean * mean;
+            spread.max(0.0) + (test_support::C * mean).powi(2) / (1.0 + events)
+        };
+        // The edge crosses column 1 during the exposure, and column 5 only after it
+        assert!(
+            (variance[(0, 1)] - expected((0, 1), test_support::EDGE_EVENTS as f64)).abs() < 1e-9
+        );
+        assert!((variance[(0, 5)] - expected((0, 5), 0.0)).abs() < 1e-9);
+
+        reconstructor.set_estimate_variance(false);
+        assert!(reconstructor.last_variance_map().is_none());
+    }
+
+    #[tokio::test]
+    async fn malformed_event_packets_are_skipped() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let expected =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+        let mut packets = scene.record(&EXPOSURES[..2]);
+        let mut truncated = test_support::event_packet(&scene.events);
+        truncated.buffer.truncate(truncated.buffer.len() / 2);
+        packets.insert(2, truncated);
+        let frames = test_support::frames(test_support::config(), packets).await;
+        assert_same_frames(&frames, &expected);
+    }
+
+    #[tokio::test]
+    async fn malformed_frame_packets_are_errors_not_panics() {
+        let scene = test_support::moving_edge(&EXPOSURES[..3]);
+        let truncate = |packet: &mut Packet| packet.buffer.truncate(packet.buffer.len() / 2);
+        let frame_indices = |packets: &[Packet]| -> Vec<usize> {
+            packets
+                .iter()
+                .enumerate()
+                .filter(|(_, packet)| packet.stream_id == StreamContent::Frame as u32)
+                .map(|(index, _)| index)
+                .collect()
+        };
+
+        // A file that ends before its first frame
+        let events = test_support::event_packet(&scene.events[..8]);
+        let source = test_support::aedat_file("davis-edi-frameless.aedat4", &[events]);
+        assert!(matches!(
+            Reconstructor::from_config(test_support::config(), source).await,
+            Err(ReconstructorError::EndOfStream)
+        ));
+
+        // A truncated first frame, whether it's primed past or deblurred
+        let mut packets = scene.record(&EXPOSURES[..3]);
+        let first = frame_indices(&packets)[0];
+        truncate(&mut packets[first]);
+        let source = test_support::aedat_file("davis-edi-truncated-frame.aedat4", &packets);
+        assert!(matches!(
+            Reconstructor::from_config(test_support::config(), source).await,
+            Err(ReconstructorError::PacketError(_))
+        ));
+        assert!(matches!(
+            test_support::reconstructor(test_support::config(), packets).await,
+            Err(ReconstructorError::PacketError(_))
+        ));
+
+        // A truncated frame later on is skipped, and the frames either side are deblurred
+        let config = ReconstructorConfig {
+            output_mode: OutputMode::SharpFrameOnly,
+            ..test_support::config()
+        };
+        let mut packets = scene.record(&EXPOSURES[..3]);
+        let second = frame_indices(&packets)[1];
+        truncate(&mut packets[second]);
+        let mut reconstructor = test_support::reconstructor(config, packets).await.unwrap();
+        let frames = test_support::all_frames(&mut reconstructor).await;
+        let exposures: Vec<_> = frames
+            .iter()
+            .map(|frame| frame.exposure_begin_t.unwrap())
+            .collect();
+        assert_eq!(exposures, [EXPOSURES[0].0, EXPOSURES[2].0]);
+    }
+
+    #[tokio::test]
+    async fn gap_filling_keeps_one_frame_per_interval() {
+        let edge = test_support::moving_edge(&EXPOSURES[..2]);
+        // No events at all between the exposures
+        let events = edge
+            .events
+            .into_iter()
+            .filter(|event| event.t() <= EXPOSURES[0].1 || event.t() >= EXPOSURES[1].0)
+            .collect();
+        let scene = test_support::Scene::new(edge.initial, events);
+        let timestamps = |frames: &[ReconstructedFrame]| -> Vec<i64> {
+            frames
+                .iter()
+                .map(|frame| frame.timestamp.unwrap())
+                .collect()
+        };
+
+        let frames =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+        assert!(!timestamps(&frames).contains(&8000));
+
+        let config = ReconstructorConfig {
+            fill_interval_gaps: true,
+            ..test_support::config()
+        };
+        let frames = test_support::frames(config, scene.record(&EXPOSURES[..2])).await;
+        assert_eq!(
+            timestamps(&frames),
+            (2000..=17000).step_by(1000).collect::<Vec<i64>>()
+        );
+        let latent = frame_at(&frames, 7000);
+        for t in (8000..12000).step_by(1000) {
+            assert_eq!(frame_at(&frames, t), latent);
+        }
+    }
+
+    #[tokio::test]
+    async fn energy_gradient_is_the_central_difference() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..1]))
+                .await
+                .unwrap();
+        reconstructor.next_frame().await.unwrap().unwrap();
+
+        let (c, delta) = (test_support::C, 0.01);
+        let expected = (reconstructor.energy_for_c(c + delta).unwrap()
+            - reconstructor.energy_for_c(c - delta).unwrap())
+            / (2.0 * delta);
+        assert_eq!(
+            reconstructor.energy_gradient_at(c, delta).unwrap(),
+            expected
+        );
+
+        for delta in [0.0, -0.01, f64::NAN, c] {
+            assert!(matches!(
+                reconstructor.energy_gradient_at(c, delta),
+                Err(ReconstructorError::ArgumentError(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn getters_report_the_returned_frames_size_and_rate() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let config = ReconstructorConfig {
+            output_crop_margin: 2,
+            ..test_support::config()
+        };
+        let mut reconstructor = test_support::reconstructor(config, scene.record(&EXPOSURES[..1]))
+            .await
+            .unwrap();
+        assert_eq!(reconstructor.dimensions(), (12, 12));
+        assert_eq!(reconstructor.current_output_fps(), 1000.0);
+        let frame = reconstructor.next_frame().await.unwrap().unwrap();
+        assert_eq!((frame.image.rows(), frame.image.cols()), (12, 12));
+
+        // A sharp frame's exposure shorter than the output interval sets the rate
+        let exposures = [(2000, 2500), (12000, 12500)];
+        let scene = test_support::moving_edge(&exposures);
+        let config = ReconstructorConfig {
+            output_mode: OutputMode::SharpFrameOnly,
+            ..test_support::config()
+        };
+        let reconstructor = test_support::reconstructor(config, scene.record(&exposures))
+            .await
+            .unwrap();
+        assert_eq!(reconstructor.current_output_fps(), 2000.0);
+    }
+
+    #[tokio::test]
+    async fn density_blend_mixes_in_the_blurred_frame_where_events_are_sparse() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let latents =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..1])).await;
+        let config = ReconstructorConfig {
+            density_blend: Some(test_support::EDGE_EVENTS as f64),
+            ..test_support::config()
+        };
+        let frames = test_support::frames(config, scene.record(&EXPOSURES[..1])).await;
+        assert_eq!(frames.len(), latents.len());
+
+        // Column 1 has as many events as the half density, so it takes half of each, and column 5
+        // has none, so it's just the blurred frame
+        let blurred = scene.blurred(EXPOSURES[0].0, EXPOSURES[0].1);
+        for (frame, latent) in frames.iter().zip(&latents) {
+            let (frame, latent) = (
+                mat_to_dmatrix(&frame.image).unwrap(),
+                mat_to_dmatrix(&latent.image).unwrap(),
+            );
+            let blurred_part = frame[(0, 1)] - 0.5 * latent[(0, 1)];
+            assert!((blurred_part - 0.5 * blurred[(0, 1)]).abs() < 1.0 / 255.0);
+            assert!((frame[(0, 5)] - blurred[(0, 5)]).abs() < 1.0 / 255.0);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn events_before_the_first_frame_can_be_kept() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let expected =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+        let config = ReconstructorConfig {
+            require_initial_frame: false,
+            ..test_support::config()
+        };
+        let source = test_support::aedat_file(
+            "davis-edi-no-initial-frame.aedat4",
+            &scene.record(&EXPOSURES[..2]),
+        );
+        let mut reconstructor = Reconstructor::from_config(config, source).await.unwrap();
+        let frames = test_support::all_frames(&mut reconstructor).await;
+        assert_same_frames(&frames, &expected);
+    }
+
+    #[tokio::test]
+    async fn waiting_for_the_first_frame_times_out() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let (_sender, packet_receiver) =
+            setup_open_packets(vec![test_support::event_packet(&scene.events)]);
+        let config = ReconstructorConfig {
+            initial_frame_timeout: Some(50.0),
+            ..test_support::config()
+        };
+        let result = Reconstructor::from_packet_receiver(
+            config,
+            "socket".to_string(),
+            packet_receiver,
+            (test_support::HEIGHT, test_support::WIDTH),
+            test_support::streams(),
+            None,
+        )
+        .await;
+        assert!(matches!(result, Err(ReconstructorError::ArgumentError(_))));
+    }
+
+    #[tokio::test]
+    async fn frames_without_pixel_data_are_skipped() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let expected =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+        let mut packets = scene.record(&EXPOSURES[..2]);
+        packets.insert(3, test_support::pixelless_frame_packet(9000, 9500));
+        let frames = test_support::frames(test_support::config(), packets).await;
+        assert_same_frames(&frames, &expected);
+    }
+
+    #[tokio::test]
+    async fn c_search_ranges_must_be_positive_and_ordered() {
+        let scene = test_support::moving_edge(&EXPOSURES[..1]);
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..1]))
+                .await
+                .unwrap();
+        reconstructor.set_c_search(0.2, 0.3, 21).unwrap();
+        for (min_c, max_c, n_points) in [
+            (0.0, 0.3, 21),
+            (0.3, 0.2, 21),
+            (0.2, f64::INFINITY, 21),
+            (0.2, 0.3, 2),
+            (0.2, 0.3, MAX_C_SEARCH_POINTS + 1),
+        ] {
+            assert!(matches!(
+                reconstructor.set_c_search(min_c, max_c, n_points),
+                Err(ReconstructorError::ArgumentError(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn output_taps_get_the_first_frame_at_each_of_their_frame_times() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        let (slow_sink, slow) = crossbeam::channel::unbounded();
+        let (fast_sink, fast) = crossbeam::channel::unbounded();
+        let (dropped_sink, dropped) = crossbeam::channel::unbounded();
+        reconstructor.add_output_tap(400.0, slow_sink).unwrap();
+        reconstructor.add_output_tap(2000.0, fast_sink).unwrap();
+        reconstructor.add_output_tap(400.0, dropped_sink).unwrap();
+        drop(dropped);
+        assert!(matches!(
+            reconstructor.add_output_tap(0.0, crossbeam::channel::unbounded().0),
+            Err(ReconstructorError::ArgumentError(_))
+        ));
+        let frames = test_support::all_frames(&mut reconstructor).await;
+        assert_eq!(reconstructor.output_taps.len(), 2);
+
+        let slow: Vec<(Mat, i64)> = slow.try_iter().collect();
+        let timestamps: Vec<i64> = slow.iter().map(|(_, t)| *t).collect();
+        assert_eq!(timestamps, [2000, 5000, 7000, 10000, 12000, 15000, 17000]);
+        for (image, t) in &slow {
+            assert_eq!(mat_to_dmatrix(image).unwrap(), frame_at(&frames, *t));
+        }
+        assert_eq!(fast.try_iter().count(), frames.len());
+    }
+
+    #[tokio::test]
+    async fn events_older_than_the_last_latent_are_dropped() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        let expected = test_support::all_frames(&mut reconstructor).await;
+        assert_eq!(reconstructor.late_events_dropped(), 0);
+
+        // An event from the first exposure arrives after its frame was reconstructed
+        let mut packets = scene.record(&EXPOSURES[..2]);
+        let late = test_support::event_packet(&[Event::new(6500, 5, 5, true)]);
+        packets.insert(3, late);
+        let mut reconstructor = test_support::reconstructor(test_support::config(), packets)
+            .await
+            .unwrap();
+        let frames = test_support::all_frames(&mut reconstructor).await;
+        assert_eq!(reconstructor.late_events_dropped(), 1);
+        assert_same_frames(&frames, &expected);
+    }
+
+    #[tokio::test]
+    async fn frames_carry_no_events_unless_they_are_retained() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let expected =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+        let config = ReconstructorConfig {
+            retain_frame_events: false,
+            ..test_support::config()
+        };
+        let mut reconstructor = test_support::reconstructor(config, scene.record(&EXPOSURES[..2]))
+            .await
+            .unwrap();
+        let frames = test_support::all_frames(&mut reconstructor).await;
+        assert_same_frames(&frames, &expected);
+        assert!(expected
+            .iter()
+            .any(|frame| !frame.events_after.as_ref().unwrap().is_empty()));
+        for frame in &frames {
+            assert!(frame.events_before.as_ref().map_or(true, Vec::is_empty));
+            assert!(frame.events_after.as_ref().map_or(true, Vec::is_empty));
+        }
+        assert!(reconstructor.events_return_before.is_empty());
+        assert!(reconstructor.events_return_after.is_empty());
+    }
+
+    #[tokio::test]
+    #[allow(deprecated)]
+    async fn next_frame_matches_the_tuples_of_next() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let frames =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        let mut count = 0;
+        while let Some(result) = reconstructor.next(true).await {
+            let (image, _, events, _) = result.unwrap();
+            let (c, before, after, begin_t, end_t) = events.unwrap();
+            let frame = &frames[count];
+            assert_eq!(
+                mat_to_dmatrix(&image).unwrap(),
+                mat_to_dmatrix(&frame.image).unwrap()
+            );
+            assert_eq!(frame.contrast_c, Some(c));
+            assert_eq!(frame.events_before.as_ref(), Some(&before));
+            assert_eq!(frame.events_after.as_ref(), Some(&after));
+            assert_eq!(frame.exposure_begin_t, Some(begin_t));
+            assert_eq!(frame.last_interval_start_t, Some(end_t));
+            count += 1;
+        }
+        assert_eq!(count, frames.len());
+
+        // Without events, a frame has none of the fields that come with them
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        let frame = ReconstructedFrame::from(reconstructor.next(false).await.unwrap().unwrap());
+        assert_eq!(
+            (
+                frame.contrast_c,
+                frame.exposure_begin_t,
+                frame.last_interval_start_t
+            ),
+            (None, None, None)
+        );
+        assert!(frame.events_before.is_none() && frame.events_after.is_none());
+    }
+
+    #[tokio::test]
+    async fn single_precision_frames_match_the_double_precision_ones() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let expected =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+        let config = ReconstructorConfig {
+            single_precision_output: true,
+            ..test_support::config()
+        };
+        let frames = test_support::frames(config, scene.record(&EXPOSURES[..2])).await;
+        assert_eq!(frames.len(), expected.len());
+        for (frame, expected) in frames.iter().zip(&expected) {
+            assert_eq!(frame.image.depth(), CV_32F);
+            assert_eq!(expected.image.depth(), CV_64F);
+            assert_eq!(frame.timestamp, expected.timestamp);
+            let difference =
+                mat_to_dmatrix(&frame.image).unwrap() - mat_to_dmatrix(&expected.image).unwrap();
+            assert!(difference.amax() < 1e-6);
+        }
+
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        assert!(matches!(
+            reconstructor.set_output_depth(opencv::core::CV_8U),
+            Err(ArgumentError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn log_latents_exponentiate_to_the_returned_frames() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let config = ReconstructorConfig {
+            keep_log_latents: true,
+            ..test_support::config()
+        };
+        let mut reconstructor = test_support::reconstructor(config, scene.record(&EXPOSURES[..2]))
+            .await
+            .unwrap();
+        assert!(reconstructor.last_log_latents().is_none());
+        let first = reconstructor.next_frame().await.unwrap().unwrap();
+        let log_latents: Vec<DMatrix<f64>> = reconstructor
+            .last_log_latents()
+            .unwrap()
+            .iter()
+            .map(|log_latent| mat_to_dmatrix(log_latent).unwrap())
+            .collect();
+        let (begin_t, end_t) = EXPOSURES[0];
+        assert_eq!(log_latents.len(), ((end_t - begin_t) / 1000 + 1) as usize);
+
+        let mut frames = vec![first];
+        while frames.len() < log_latents.len() {
+            frames.push(reconstructor.next_frame().await.unwrap().unwrap());
+        }
+        for (log_latent, frame) in log_latents.iter().zip(&frames) {
+            let difference = log_latent.map(f64::exp) - mat_to_dmatrix(&frame.image).unwrap();
+            assert!(difference.amax() < 1e-9);
+        }
+
+        reconstructor.set_keep_log_latents(false);
+        assert!(reconstructor.last_log_latents().is_none());
+    }
+
+    #[tokio::test]
+    async fn intervals_shorter_than_the_events_can_be_capped() {
+        // Four pixels each fire 2500 ticks apart during the first exposure
+        let (height, width) = (test_support::HEIGHT as usize, test_support::WIDTH as usize);
+        let events = (0..4)
+            .flat_map(|x| [Event::new(3000, x, 0, true), Event::new(5500, x, 0, false)])
+            .chain(test_support::exposure_anchors(&EXPOSURES[..2]))
+            .collect();
+        let scene = test_support::Scene::new(DMatrix::from_element(height, width, 0.5), events);
+        let first_frame_fps = |check| {
+            let packets = scene.record(&EXPOSURES[..2]);
+            async move {
+                let config = ReconstructorConfig {
+                    fps_resolution_check: check,
+                    ..test_support::config()
+                };
+                let mut reconstructor = test_support::reconstructor(config, packets).await.unwrap();
+                reconstructor.next_frame().await.unwrap().unwrap();
+                (
+                    reconstructor.current_output_fps(),
+                    reconstructor.event_adder.interval_length,
+                )
+            }
+        };
+
+        assert_eq!(
+            first_frame_fps(FpsResolutionCheck::Off).await,
+            (1000.0, 1000.0)
+        );
+        assert_eq!(
+            first_frame_fps(FpsResolutionCheck::Warn).await,
+            (1000.0, 1000.0)
+        );
+        assert_eq!(
+            first_frame_fps(FpsResolutionCheck::Cap).await,
+            (400.0, 2500.0)
+        );
+
+        // Intervals already longer than the time between events are left alone
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let config = ReconstructorConfig {
+            fps_resolution_check: FpsResolutionCheck::Cap,
+            ..test_support::config()
+        };
+        let mut reconstructor = test_support::reconstructor(config, scene.record(&EXPOSURES[..2]))
+            .await
+            .unwrap();
+        reconstructor.next_frame().await.unwrap().unwrap();
+        assert_eq!(reconstructor.current_output_fps(), 1000.0);
+    }
+
+    #[tokio::test]
+    async fn the_builder_matches_a_config() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let source =
+            test_support::aedat_file("davis-edi-builder.aedat4", &scene.record(&EXPOSURES[..2]));
+        let config = ReconstructorConfig {
+            deterministic: true,
+            ..test_support::config()
+        };
+        let mut reconstructor = Reconstructor::from_config(config.clone(), source.clone())
+            .await
+            .unwrap();
+        let expected = test_support::all_frames(&mut reconstructor).await;
+        assert!(!expected.is_empty());
+
+        let builder = ReconstructorBuilder::new()
+            .directory(source.directory.clone())
+            .aedat_filename_0(source.aedat_filename_0.clone())
+            .mode("file")
+            .config(config.clone());
+        let mut reconstructor = builder.clone().build().await.unwrap();
+        assert_same_frames(
+            &test_support::all_frames(&mut reconstructor).await,
+            &expected,
+        );
+
+        // Named settings apply on top of the config
+        let mut reconstructor = builder
+            .clone()
+            .output_mode(OutputMode::SharpFrameOnly)
+            .build()
+            .await
+            .unwrap();
+        let sharp_frames = test_support::all_frames(&mut reconstructor).await;
+        assert!(sharp_frames.len() < expected.len());
+
+        for builder in [
+            ReconstructorBuilder::new()
+                .aedat_filename_0("a.aedat4")
+                .mode("file"),
+            ReconstructorBuilder::new().directory(".").mode("file"),
+            ReconstructorBuilder::new()
+                .directory(".")
+                .aedat_filename_0("a.aedat4"),
+        ] {
+            assert!(matches!(builder.build().await, Err(ArgumentError(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn input_histogram_counts_the_blurred_pixels() {
+        // Half the pixels are black, a quarter mid-gray, and a quarter white
+        let (height, width) = (test_support::HEIGHT as usize, test_support::WIDTH as usize);
+        let image = DMatrix::from_fn(height, width, |_, col| match col * 4 / width {
+            0 | 1 => 0.0,
+            2 => 0.5,
+            _ => 1.0,
+        });
+        let scene =
+            test_support::Scene::new(image, test_support::exposure_anchors(&EXPOSURES[..2]));
+        let histogram = |bins| {
+            let packets = scene.record(&EXPOSURES[..2]);
+            async move {
+                let config = ReconstructorConfig {
+                    input_histogram_bins: bins,
+                    ..test_support::config()
+                };
+                let mut reconstructor = test_support::reconstructor(config, packets).await.unwrap();
+                reconstructor.next_frame().await.unwrap().unwrap();
+                reconstructor.last_input_histogram()
+            }
+        };
+
+        let quarter = (height * width / 4) as u32;
+        assert_eq!(histogram(None).await, None);
+        assert_eq!(histogram(Some(1)).await, Some(vec![4 * quarter]));
+        assert_eq!(
+            histogram(Some(4)).await,
+            Some(vec![2 * quarter, 0, quarter, quarter])
+        );
+        let fine = histogram(Some(256)).await.unwrap();
+        assert_eq!(
+            (fine[0], fine[128], fine[255]),
+            (2 * quarter, quarter, quarter)
+        );
+        assert_eq!(fine.iter().sum::<u32>(), 4 * quarter);
+
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        for bins in [0, 257] {
+            assert!(matches!(
+                reconstructor.set_input_histogram_bins(Some(bins)),
+                Err(ArgumentError(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_arguments_fail_before_opening_the_input() {
+        // The file doesn't exist, so these only fail as arguments if they're checked first
+        let result = Reconstructor::new(
+            ".".to_string(),
+            "davis-edi-missing.aedat4".to_string(),
+            String::new(),
+            "file".to_string(),
+            0.3,
+            false,
+            1,
+            false,
+            false,
+            false,
+            100.0,
+            true,
+            true,
+            1000.0,
+            false,
+        )
+        .await;
+        assert!(matches!(result, Err(ArgumentError(_))));
+
+        let builder = ReconstructorBuilder::new()
+            .directory(".")
+            .aedat_filename_0("davis-edi-missing.aedat4")
+            .mode("file");
+        for builder in [
+            builder.clone().output_fps(0.0),
+            builder.clone().output_fps(f64::NAN),
+            builder.clone().target_latency(-1.0),
+            builder
+                .clone()
+                .events_only(true)
+                .output_mode(OutputMode::SharpFrameOnly),
+        ] {
+            assert!(matches!(builder.build().await, Err(ArgumentError(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn imu_packets_after_a_frame_are_skipped() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let expected =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+
+        // The packet read right after each frame is an IMU packet
+        let imu = |t| test_support::imu_packet(&[(t, [0.0, 0.0, 1.0], [0.0; 3])]);
+        let mut packets = scene.record(&EXPOSURES[..2]);
+        packets.insert(5, imu(17000));
+        packets.insert(2, imu(7000));
+        let frames = test_support::frames(test_support::config(), packets).await;
+        assert_same_frames(&frames, &expected);
+    }
+
+    #[tokio::test]
+    async fn imu_samples_are_drained_in_order() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let sample = |t: i64| (t, [0.0, 0.0, 1.0], [t as f32 / 1000.0, 0.0, -1.0]);
+        let mut packets = scene.record(&EXPOSURES[..2]);
+        packets.insert(5, test_support::imu_packet(&[sample(17000)]));
+        packets.insert(0, test_support::imu_packet(&[sample(1000), sample(1500)]));
+        let mut reconstructor = test_support::reconstructor(test_support::config(), packets)
+            .await
+            .unwrap();
+        test_support::all_frames(&mut reconstructor).await;
+
+        let expected: Vec<ImuSample> = [1000, 1500, 17000]
+            .into_iter()
+            .map(|t| {
+                let (timestamp, accel, gyro) = sample(t);
+                ImuSample {
+                    timestamp,
+                    accel,
+                    gyro,
+                }
+            })
+            .collect();
+        assert_eq!(reconstructor.drain_imu(), expected);
+        assert!(reconstructor.drain_imu().is_empty());
+
+        // Malformed packets are skipped, and only the newest samples are kept
+        let mut malformed = test_support::imu_packet(&[sample(20000)]);
+        malformed.buffer.truncate(malformed.buffer.len() / 2);
+        reconstructor.record_imus(&malformed);
+        assert!(reconstructor.drain_imu().is_empty());
+        let samples: Vec<_> = (0..IMU_QUEUE_CAPACITY as i64 + 5).map(sample).collect();
+        reconstructor.record_imus(&test_support::imu_packet(&samples));
+        let drained = reconstructor.drain_imu();
+        assert_eq!(drained.len(), IMU_QUEUE_CAPACITY);
+        assert_eq!(drained[0].timestamp, 5);
+    }
+
+    #[tokio::test]
+    async fn imu_speeds_are_kept_only_until_their_exposure_is_deblurred() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let mut packets = scene.record(&EXPOSURES[..2]);
+        packets.insert(
+            0,
+            test_support::imu_packet(&[
+                (4000, [0.0; 3], [3.0, 4.0, 0.0]),
+                (12500, [0.0; 3], [0.0, 0.0, -2.0]),
+            ]),
+        );
+        let config = ReconstructorConfig {
+            use_imu: true,
+            latent_reference: LatentReference::MinimumMotion,
+            ..test_support::config()
+        };
+        let mut reconstructor = test_support::reconstructor(config, packets).await.unwrap();
+        reconstructor.next_frame().await.unwrap().unwrap();
+        assert_eq!(reconstructor.event_adder.imu_angular_speed, [(12500, 2.0)]);
+
+        reconstructor.set_use_imu(false);
+        assert!(reconstructor.event_adder.imu_angular_speed.is_empty());
+
+        let config = ReconstructorConfig {
+            use_imu: true,
+            ..test_support::config()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(ReconstructorError::ConfigError(_))
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reconstruct_all_blocks_for_every_frame() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        // Fill the gap between the exposures, so there's a frame for every output interval
+        let config = ReconstructorConfig {
+            fill_interval_gaps: true,
+            ..test_support::config()
+        };
+        let expected = test_support::frames(config.clone(), scene.record(&EXPOSURES[..2])).await;
+        let mut reconstructor =
+            test_support::reconstructor(config.clone(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        let images = reconstructor.reconstruct_all().unwrap();
+        assert_eq!(images.len(), expected.len());
+        for (image, expected) in images.iter().zip(&expected) {
+            assert_eq!(
+                mat_to_dmatrix(image).unwrap(),
+                mat_to_dmatrix(&expected.image).unwrap()
+            );
+        }
+
+        // One frame per output interval over the recording, counting both ends
+        let duration_s = (EXPOSURES[1].1 - EXPOSURES[0].0) as f64 / 1e6;
+        assert_eq!(
+            images.len(),
+            (duration_s * config.output_fps).round() as usize + 1
+        );
+
+        // No APS frame is held on to once the file is drained
+        assert!(reconstructor.event_adder.blur_info.is_none());
+        assert!(reconstructor.event_adder.next_blur_info.is_none());
+        assert!(reconstructor.reconstruct_all().unwrap().is_empty());
+
+        let mut reconstructor = test_support::reconstructor_with_mode(
+            test_support::config(),
+            "socket",
+            scene.record(&EXPOSURES[..2]),
+        )
+        .await
+        .unwrap();
+        assert!(reconstructor.reconstruct_all().is_err());
+    }
+
+    #[tokio::test]
+    async fn reconstruct_all_rejects_a_current_thread_runtime() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        assert!(reconstructor.reconstruct_all().is_err());
+
+        // Nothing was read, so the frames can still be reconstructed on this runtime
+        let frames = test_support::all_frames(&mut reconstructor).await;
+        let expected =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+        assert_eq!(frames.len(), expected.len());
+    }
+
+    #[test]
+    fn reconstruct_all_runs_outside_a_runtime() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (expected, mut reconstructor) = runtime.block_on(async {
+            let expected =
+                test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+            let reconstructor =
+                test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                    .await
+                    .unwrap();
+            (expected, reconstructor)
+        });
+        drop(runtime);
+        assert_eq!(
+            reconstructor.reconstruct_all().unwrap().len(),
+            expected.len()
+        );
+    }
+
+    #[tokio::test]
+    async fn reset_contrast_threshold_needs_a_positive_c() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        for c in [0.0, -0.1, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                reconstructor.reset_contrast_threshold(c),
+                Err(ArgumentError(_))
+            ));
+        }
+        reconstructor.reset_contrast_threshold(0.3).unwrap();
+        let frame = reconstructor.next_frame().await.unwrap().unwrap();
+        assert_eq!(frame.contrast_c, Some(0.3));
+    }
+
+    #[tokio::test]
+    async fn frame_events_are_owned_copies_that_serialize() {
+        let event = Event::new(3750, 1, 14, false);
+        let edi_event = EdiEvent::from(&event);
+        assert_eq!(
+            edi_event,
+            EdiEvent {
+                t: 3750,
+                x: 1,
+                y: 14,
+                polarity: false
+            }
+        );
+        let json = serde_json::to_string(&edi_event).unwrap();
+        assert_eq!(serde_json::from_str::<EdiEvent>(&json).unwrap(), edi_event);
+
+        // Each frame of the sequence carries the events of its own interval
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let frames =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+        let frame = frames
+            .iter()
+            .find(|frame| frame.timestamp == Some(3000))
+            .unwrap();
+        let expected: Vec<EdiEvent> = scene
+            .events
+            .iter()
+            .filter(|event| (3000..4000).contains(&event.t()))
+            .map(EdiEvent::from)
+            .collect();
+        assert_eq!(expected.len(), 4 * (test_support::HEIGHT as usize - 1));
+        assert_eq!(frame.events_after.as_ref(), Some(&expected));
+    }
+
+    #[tokio::test]
+    async fn parallel_batches_match_deblurring_one_frame_at_a_time() {
+        let scene = test_support::moving_edge(&EXPOSURES);
+        let config = ReconstructorConfig {
+            output_mode: OutputMode::SharpFrameOnly,
+            latent_carry: LatentCarry::Never,
+            ..test_support::config()
+        };
+        let expected = test_support::frames(config.clone(), scene.record(&EXPOSURES)).await;
+        assert_eq!(expected.len(), EXPOSURES.len());
+
+        // The last batch is partial
+        let parallel = ReconstructorConfig {
+            parallel_frames: 3,
+            ..config.clone()
+        };
+        let frames = test_support::frames(parallel, scene.record(&EXPOSURES)).await;
+        assert_same_frames(&frames, &expected);
+
+        let mut reconstructor = test_support::reconstructor(config, scene.record(&EXPOSURES))
+            .await
+            .unwrap();
+        assert!(matches!(
+            reconstructor.set_parallel_frames(0),
+            Err(ArgumentError(_))
+        ));
+        reconstructor.set_latent_carry(LatentCarry::Always).unwrap();
+        assert!(matches!(
+            reconstructor.set_parallel_frames(2),
+            Err(ArgumentError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn final_exposures_past_the_events_are_skipped_or_flagged() {
+        let run = |scene: test_support::Scene, truncated_exposure| async move {
+            let config = ReconstructorConfig {
+                truncated_exposure,
+                ..test_support::config()
+            };
+            let mut reconstructor =
+                test_support::reconstructor(config, scene.record(&EXPOSURES[..2]))
+                    .await
+                    .unwrap();
+            let frames = test_support::all_frames(&mut reconstructor).await;
+            let last_t = frames.last().unwrap().timestamp.unwrap();
+            (
+                last_t,
+                reconstructor.last_frame_stats().unwrap().exposure_truncated,
+            )
+        };
+
+        // The anchor event at the end of each exposure covers it
+        let covered = test_support::moving_edge(&EXPOSURES[..2]);
+        assert_eq!(run(covered, TruncatedExposure::Skip).await, (17000, false));
+
+        // Without the anchors of the second exposure, its last event is at 16253
+        let truncated = || test_support::moving_edge(&EXPOSURES[..1]);
+        let (last_t, _) = run(truncated(), TruncatedExposure::Skip).await;
+        assert!(last_t < EXPOSURES[1].0);
+        assert_eq!(
+            run(truncated(), TruncatedExposure::BestEffort).await,
+            (17000, true)
+        );
+    }
+
+    #[tokio::test]
+    async fn hot_pixel_thresholds_must_be_positive() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        for threshold in [0.0, -1.0, f64::NAN] {
+            assert!(matches!(
+                reconstructor.set_hot_pixel_threshold(Some(threshold)),
+                Err(ArgumentError(_))
+            ));
+        }
+
+        // None of the edge's pixels fire often enough to be masked
+        reconstructor.set_hot_pixel_threshold(Some(2.0)).unwrap();
+        reconstructor.next_frame().await.unwrap().unwrap();
+        assert_eq!(reconstructor.hot_pixels_masked(), 0);
+    }
+
+    #[tokio::test]
+    async fn edge_decay_tau_must_be_positive() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        for tau in [0.0, -1.0, f64::INFINITY] {
+            assert!(matches!(
+                reconstructor.set_edge_decay_tau(Some(tau)),
+                Err(ArgumentError(_))
+            ));
+        }
+        reconstructor.set_edge_decay_tau(Some(100.0)).unwrap();
+        assert_eq!(reconstructor.event_adder.edge_decay_tau, Some(100.0));
+    }
+
+    #[tokio::test]
+    async fn frames_carry_the_energy_of_their_aps_frame() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let mut reconstructor =
+            test_support::reconstructor(test_support::config(), scene.record(&EXPOSURES[..2]))
+                .await
+                .unwrap();
+        assert_eq!(reconstructor.last_energy(), None);
+        let frame = reconstructor.next_frame().await.unwrap().unwrap();
+        assert!(frame.energy.unwrap().is_finite());
+        assert_eq!(frame.energy, reconstructor.last_energy());
+    }
+
+    #[tokio::test]
+    async fn close_frames_are_merged_into_one_exposure() {
+        let exposures = [(2000, 4000), (4200, 7000), (12000, 17000)];
+        let scene = test_support::moving_edge(&exposures);
+        let frames = |min_frame_gap| {
+            let packets = scene.record(&exposures);
+            async move {
+                let config = ReconstructorConfig {
+                    output_mode: OutputMode::SharpFrameOnly,
+                    min_frame_gap,
+                    ..test_support::config()
+                };
+                let mut reconstructor = test_support::reconstructor(config, packets).await.unwrap();
+                let frames = test_support::all_frames(&mut reconstructor).await;
+                let exposures: Vec<_> = frames
+                    .iter()
+                    .map(|frame| frame.exposure_begin_t.unwrap())
+                    .collect();
+                (exposures, reconstructor.merged_frames())
+            }
+        };
+
+        assert_eq!(frames(None).await, (vec![2000, 4200, 12000], 0));
+        assert_eq!(frames(Some(500)).await, (vec![2000, 12000], 1));
+
+        // A gap of 200 isn't less than 200
+        assert_eq!(frames(Some(200)).await, (vec![2000, 4200, 12000], 0));
+        let config = ReconstructorConfig {
+            min_frame_gap: Some(0),
+            ..test_support::config()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[cfg(not(feature = "display"))]
+    #[tokio::test]
+    async fn headless_builds_reconstruct_with_the_display_on() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let expected =
+            test_support::frames(test_support::config(), scene.record(&EXPOSURES[..2])).await;
+        let config = ReconstructorConfig {
+            show_display: true,
+            show_blurred_display: true,
+            ..test_support::config()
+        };
+        let frames = test_support::frames(config, scene.record(&EXPOSURES[..2])).await;
+        assert_same_frames(&frames, &expected);
+    }
+
+    #[tokio::test]
+    async fn streams_are_listed_from_the_header() {
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let source =
+            test_support::aedat_file("davis-edi-streams.aedat4", &scene.record(&EXPOSURES[..2]));
+        let mut reconstructor = Reconstructor::from_config(test_support::config(), source)
+            .await
+            .unwrap();
+        let expected = [
+            StreamContent::Events,
+            StreamContent::Frame,
+            StreamContent::Imus,
+            StreamContent::Triggers,
+        ];
+        let check = |streams: Vec<StreamInfo>| {
+            assert_eq!(streams.len(), expected.len());
+            for (stream, content) in streams.iter().zip(expected) {
+                assert_eq!(stream.id, content as u32);
+                assert_eq!(stream.content as u32, content as u32);
+                assert_eq!((stream.width, stream.height), (16, 16));
+            }
+        };
+        check(reconstructor.streams());
+
+        // The list doesn't change as the packets are read
+        test_support::all_frames(&mut reconstructor).await;
+        check(reconstructor.streams());
+    }
+
+    #[cfg(not(feature = "display"))]
+    #[tokio::test]
+    async fn debug_display_leaves_the_optimized_frames_unchanged() {
+        assert!(!ReconstructorConfig::default().debug_display);
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let optimized = ReconstructorConfig {
+            optimize_c: true,
+            deterministic: true,
+            ..test_support::config()
+        };
+        let expected = test_support::frames(optimized.clone(), scene.record(&EXPOSURES[..2])).await;
+        let config = ReconstructorConfig {
+            show_display: true,
+            debug_display: true,
+            ..optimized
+        };
+        let frames = test_support::frames(config.clone(), scene.record(&EXPOSURES[..2])).await;
+        assert_same_frames(&frames, &expected);
+
+        // Turning it off partway through doesn't change the frames either
+        let mut reconstructor = test_support::reconstructor(config, scene.record(&EXPOSURES[..2]))
+            .await
+            .unwrap();
+        let mut frames = vec![reconstructor.next_frame().await.unwrap().unwrap()];
+        reconstructor.set_debug_display(false);
+        frames.extend(test_support::all_frames(&mut reconstructor).await);
+        assert_same_frames(&frames, &expected);
     }
-    highgui::wait_key(wait).unwrap();
 }
@@ -0,0 +1,191 @@
+use crate::util::config::{InputSource, ReconstructorConfig};
+use crate::util::reconstructor::{ReconstructionError, Reconstructor, ReconstructorError};
+use opencv::core::Mat;
+
+/// Reconstructs a pair of recordings from a stereo rig of two DAVIS cameras. Each camera has its
+/// own reconstructor, and thus its own event adder and contrast threshold. Their frames are paired
+/// up by timestamp: when one camera's frame arrives before the other's, it's buffered until the
+/// other camera catches up, and a frame with no counterpart within half an output interval is
+/// dropped.
+pub struct StereoReconstructor {
+    left: Reconstructor,
+    right: Reconstructor,
+    timebase_hz: f64,
+    left_buffer: Option<(Mat, i64)>,
+    right_buffer: Option<(Mat, i64)>,
+}
+
+impl StereoReconstructor {
+    /// Open the left and right aedat4 files in `directory`, reconstructing both with `config`
+    pub async fn from_config(
+        config: ReconstructorConfig,
+        directory: &str,
+        left_filename: &str,
+        right_filename: &str,
+    ) -> Result<StereoReconstructor, ReconstructorError> {
+        let source = |filename: &str| InputSource {
+            mode: "file".to_string(),
+            directory: directory.to_string(),
+            aedat_filename_0: filename.to_string(),
+            aedat_filename_1: String::new(),
+        };
+        let timebase_hz = config.timebase_hz;
+        let mut left = Reconstructor::from_config(config.clone(), source(left_filename)).await?;
+        let mut right = Reconstructor::from_config(config, source(right_filename)).await?;
+
+        // Only the images are paired up
+        left.set_retain_frame_events(false);
+        right.set_retain_frame_events(false);
+        if left.output_dimensions() != right.output_dimensions() {
+            return Err(ReconstructorError::ArgumentError(
+                "The stereo cameras must have the same resolution".to_string(),
+            ));
+        }
+        Ok(StereoReconstructor {
+            left,
+            right,
+            timebase_hz,
+            left_buffer: None,
+            right_buffer: None,
+        })
+    }
+
+    /// The reconstructor for the left camera, for configuring it further
+    pub fn left(&mut self) -> &mut Reconstructor {
+        &mut self.left
+    }
+
+    /// The reconstructor for the right camera, for configuring it further
+    pub fn right(&mut self) -> &mut Reconstructor {
+        &mut self.right
+    }
+
+    /// Get the next pair of (left, right) latent images for the same instant. Ends when either
+    /// camera's stream ends.
+    pub async fn next(&mut self) -> Option<Result<(Mat, Mat), ReconstructionError>> {
+        loop {
+            if self.left_buffer.is_none() {
+                match Self::next_timestamped(&mut self.left).await? {
+                    Ok(frame) => self.left_buffer = Some(frame),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            if self.right_buffer.is_none() {
+                match Self::next_timestamped(&mut self.right).await? {
+                    Ok(frame) => self.right_buffer = Some(frame),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            let (left_t, right_t) = match (&self.left_buffer, &self.right_buffer) {
+                (Some((_, left_t)), Some((_, right_t))) => (*left_t, *right_t),
+                _ => unreachable!(),
+            };
+
+            // Both cameras should use the same interval, but the output frame rate of each can
+            // change in the sharp-frame-only mode
+            let interval_t = self.timebase_hz
+                / self
+                    .left
+                    .current_output_fps()
+                    .max(self.right.current_output_fps());
+            if ((left_t - right_t) as f64).abs() <= interval_t / 2.0 {
+                let (left, _) = self.left_buffer.take().unwrap();
+                let (right, _) = self.right_buffer.take().unwrap();
+                return Some(Ok((left, right)));
+            }
+
+            // Drop the earlier frame, which has no counterpart, and keep the later one buffered
+            match left_t < right_t {
+                true => self.left_buffer = None,
+                false => self.right_buffer = None,
+            }
+        }
+    }
+
+    async fn next_timestamped(
+        reconstructor: &mut Reconstructor,
+    ) -> Option<Result<(Mat, i64), ReconstructionError>> {
+        Some(
+            reconstructor
+                .next_frame()
+                .await?
+                .map(|frame| (frame.image, frame.timestamp.unwrap_or_default())),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::reconstructor::mat_to_dmatrix;
+    use crate::util::test_support::{self, EXPOSURES};
+    use nalgebra::DMatrix;
+
+    /// Reconstruct a file alone, as (timestamp, image) pairs
+    async fn solo_frames(
+        config: &ReconstructorConfig,
+        source: &InputSource,
+    ) -> Vec<(i64, DMatrix<f64>)> {
+        let mut reconstructor = Reconstructor::from_config(config.clone(), source.clone())
+            .await
+            .unwrap();
+        test_support::all_frames(&mut reconstructor)
+            .await
+            .iter()
+            .map(|frame| {
+                (
+                    frame.timestamp.unwrap(),
+                    mat_to_dmatrix(&frame.image).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn frames_are_paired_by_timestamp() {
+        // The right camera starts recording an exposure later than the left
+        let scene = test_support::moving_edge(&EXPOSURES[..2]);
+        let left = test_support::aedat_file(
+            "davis-edi-stereo-left.aedat4",
+            &scene.record(&EXPOSURES[..2]),
+        );
+        let right = test_support::aedat_file(
+            "davis-edi-stereo-right.aedat4",
+            &test_support::moving_edge(&EXPOSURES[1..2]).record(&EXPOSURES[1..2]),
+        );
+        let config = ReconstructorConfig {
+            skip_priming: true,
+            ..test_support::config()
+        };
+        let left_frames = solo_frames(&config, &left).await;
+        let right_frames = solo_frames(&config, &right).await;
+        assert!(left_frames.len() > right_frames.len());
+
+        let mut stereo = StereoReconstructor::from_config(
+            config,
+            &left.directory,
+            &left.aedat_filename_0,
+            &right.aedat_filename_0,
+        )
+        .await
+        .unwrap();
+        let mut pairs = vec![];
+        while let Some(pair) = stereo.next().await {
+            let (left_image, right_image) = pair.unwrap();
+            pairs.push((
+                mat_to_dmatrix(&left_image).unwrap(),
+                mat_to_dmatrix(&right_image).unwrap(),
+            ));
+        }
+
+        // The left frames before the right camera starts have no counterpart
+        let expected: Vec<_> = right_frames
+            .iter()
+            .map(|(t, right_image)| {
+                let (_, left_image) = left_frames.iter().find(|(left_t, _)| left_t == t).unwrap();
+                (left_image.clone(), right_image.clone())
+            })
+            .collect();
+        assert_eq!(pairs, expected);
+    }
+}
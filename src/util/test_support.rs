@@ -0,0 +1,549 @@
+use crate::util::config::{InputSource, ReconstructorConfig};
+use crate::util::event_adder::{BlurInfo, EventAdder};
+use crate::util::reconstructor::{
+    OutputMode, ReconstructedFrame, Reconstructor, ReconstructorError, StreamInfo, DEFAULT_LAMBDA,
+    DEFAULT_MAX_C, DEFAULT_MIN_C, DEFAULT_N_POINTS,
+};
+use crate::util::threaded_decoder::{setup_queued_packets, PACKET_HEADER_BYTES};
+use aedat::base::{Packet, StreamContent};
+use aedat::events_generated::Event;
+use nalgebra::DMatrix;
+use std::time::Instant;
+
+/// The resolution of the synthetic sensor
+pub(crate) const HEIGHT: u16 = 16;
+pub(crate) const WIDTH: u16 = 16;
+
+/// The contrast threshold that the synthetic events are generated with
+pub(crate) const C: f64 = 0.25;
+
+/// The intensity of the synthetic scene before the edge reaches it
+pub(crate) const DARK: f64 = 0.2;
+
+/// The number of events each pixel fires as the edge passes it
+pub(crate) const EDGE_EVENTS: i64 = 4;
+
+/// When the edge reaches the first column, and how long it takes to reach each column after that
+pub(crate) const EDGE_START_T: i64 = 1250;
+pub(crate) const EDGE_COLUMN_TICKS: i64 = 2500;
+
+/// The exposures of the synthetic APS frames. The edge crosses two columns during each.
+pub(crate) const EXPOSURES: [(i64, i64); 4] =
+    [(2000, 7000), (12000, 17000), (22000, 27000), (32000, 37000)];
+
+/// A field of a flatbuffer table
+enum Field {
+    I64(i64),
+    I16(i16),
+
+    /// A vector of `len` elements, encoded as `bytes`, whose elements are aligned to `align`
+    Vector {
+        len: usize,
+        bytes: Vec<u8>,
+        align: usize,
+    },
+}
+
+fn pad_to(buffer: &mut Vec<u8>, align: usize, remainder: usize) {
+    while buffer.len() % align != remainder {
+        buffer.push(0);
+    }
+}
+
+/// Encode a size-prefixed flatbuffer holding a single table, as the aedat packets are, with the
+/// given file identifier. The fields are in the order of the schema, with `None` for absent
+/// fields. All positions are aligned relative to the start of the buffer, as the flatbuffers
+/// verifier expects.
+fn table(identifier: &[u8; 4], fields: Vec<Option<Field>>) -> Vec<u8> {
+    // The size prefix and the root offset are filled in last
+    let mut buffer = vec![0; 8];
+    buffer.extend_from_slice(identifier);
+
+    let vtable_pos = buffer.len();
+    buffer.extend_from_slice(&(4 + 2 * fields.len() as u16).to_le_bytes());
+    buffer.extend_from_slice(&[0; 2]);
+    buffer.extend(std::iter::repeat(0).take(2 * fields.len()));
+    pad_to(&mut buffer, 8, 0);
+
+    let table_pos = buffer.len();
+    buffer.extend_from_slice(&((table_pos - vtable_pos) as i32).to_le_bytes());
+    let mut vectors = vec![];
+    for (index, field) in fields.into_iter().enumerate() {
+        let field_pos = match field {
+            None => continue,
+            Some(Field::I64(value)) => {
+                pad_to(&mut buffer, 8, 0);
+                buffer.extend_from_slice(&value.to_le_bytes());
+                buffer.len() - 8
+            }
+            Some(Field::I16(value)) => {
+                pad_to(&mut buffer, 2, 0);
+                buffer.extend_from_slice(&value.to_le_bytes());
+                buffer.len() - 2
+            }
+            Some(Field::Vector { len, bytes, align }) => {
+                pad_to(&mut buffer, 4, 0);
+                vectors.push((buffer.len(), len, bytes, align));
+                buffer.extend_from_slice(&[0; 4]);
+                buffer.len() - 4
+            }
+        };
+        let vtable_entry = vtable_pos + 4 + 2 * index;
+        buffer[vtable_entry..vtable_entry + 2]
+            .copy_from_slice(&((field_pos - table_pos) as u16).to_le_bytes());
+    }
+    let table_size = (buffer.len() - table_pos) as u16;
+    buffer[vtable_pos + 2..vtable_pos + 4].copy_from_slice(&table_size.to_le_bytes());
+
+    // Each vector is its length, then its elements, after the table
+    for (field_pos, len, bytes, align) in vectors {
+        pad_to(&mut buffer, align.max(4), align.max(4) - 4);
+        let offset = (buffer.len() - field_pos) as u32;
+        buffer[field_pos..field_pos + 4].copy_from_slice(&offset.to_le_bytes());
+        buffer.extend_from_slice(&(len as u32).to_le_bytes());
+        buffer.extend_from_slice(&bytes);
+    }
+
+    let root_offset = (table_pos - 4) as u32;
+    buffer[4..8].copy_from_slice(&root_offset.to_le_bytes());
+    let size = (buffer.len() - 4) as u32;
+    buffer[..4].copy_from_slice(&size.to_le_bytes());
+    buffer
+}
+
+/// A packet of the events stream
+pub(crate) fn event_packet(events: &[Event]) -> Packet {
+    let mut bytes = Vec::with_capacity(16 * events.len());
+    for event in events {
+        bytes.extend_from_slice(&event.t().to_le_bytes());
+        bytes.extend_from_slice(&event.x().to_le_bytes());
+        bytes.extend_from_slice(&event.y().to_le_bytes());
+        bytes.push(event.on() as u8);
+        bytes.extend_from_slice(&[0; 3]);
+    }
+    Packet {
+        buffer: table(
+            b"EVTS",
+            vec![Some(Field::Vector {
+                len: events.len(),
+                bytes,
+                align: 8,
+            })],
+        ),
+        stream_id: StreamContent::Events as u32,
+    }
+}
+
+/// A packet of the events stream without an elements vector at all
+pub(crate) fn elementless_event_packet() -> Packet {
+    Packet {
+        buffer: table(b"EVTS", vec![None]),
+        stream_id: StreamContent::Events as u32,
+    }
+}
+
+/// A packet of the triggers stream, with an external signal's rising edge at each timestamp
+pub(crate) fn trigger_packet(timestamps: &[i64]) -> Packet {
+    let mut bytes = Vec::with_capacity(16 * timestamps.len());
+    for t in timestamps {
+        bytes.extend_from_slice(&t.to_le_bytes());
+        bytes.push(1);
+        bytes.extend_from_slice(&[0; 7]);
+    }
+    Packet {
+        buffer: table(
+            b"TRIG",
+            vec![Some(Field::Vector {
+                len: timestamps.len(),
+                bytes,
+                align: 8,
+            })],
+        ),
+        stream_id: StreamContent::Triggers as u32,
+    }
+}
+
+/// A packet of the IMU stream, with a sample of the accelerometer and gyroscope at each timestamp.
+/// The temperature and magnetometer are zero.
+pub(crate) fn imu_packet(samples: &[(i64, [f32; 3], [f32; 3])]) -> Packet {
+    let mut bytes = Vec::with_capacity(48 * samples.len());
+    for (t, accel, gyro) in samples {
+        bytes.extend_from_slice(&t.to_le_bytes());
+        bytes.extend_from_slice(&0f32.to_le_bytes());
+        for value in accel.iter().chain(gyro).chain(&[0.0; 3]) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    Packet {
+        buffer: table(
+            b"IMUS",
+            vec![Some(Field::Vector {
+                len: samples.len(),
+                bytes,
+                align: 8,
+            })],
+        ),
+        stream_id: StreamContent::Imus as u32,
+    }
+}
+
+/// A packet of the frames stream, exposed over `[begin_t, end_t]`. The intensities of `image`
+/// are quantized from `[0, 1]` to 8 bits, like those of the sensor.
+pub(crate) fn frame_packet(begin_t: i64, end_t: i64, image: &DMatrix<f64>) -> Packet {
+    roi_frame_packet(begin_t, end_t, image, (0, 0))
+}
+
+/// A packet of the frames stream, like [`frame_packet`], but for a region of interest of the
+/// sensor at the given (x, y) offset
+pub(crate) fn roi_frame_packet(
+    begin_t: i64,
+    end_t: i64,
+    image: &DMatrix<f64>,
+    (offset_x, offset_y): (i16, i16),
+) -> Packet {
+    let pixels: Vec<u8> = (0..image.nrows())
+        .flat_map(|row| (0..image.ncols()).map(move |col| (row, col)))
+        .map(|(row, col)| (image[(row, col)] * 255.0).round().clamp(0.0, 255.0) as u8)
+        .collect();
+    let (width, height) = (image.ncols() as i16, image.nrows() as i16);
+    frame_table_packet(
+        begin_t,
+        end_t,
+        (width, height),
+        (offset_x, offset_y),
+        Some(pixels),
+    )
+}
+
+/// A packet of the frames stream with the dimensions of the sensor, but no pixel data, like the
+/// metadata-only frames that some recordings hold
+pub(crate) fn pixelless_frame_packet(begin_t: i64, end_t: i64) -> Packet {
+    frame_table_packet(begin_t, end_t, (WIDTH as i16, HEIGHT as i16), (0, 0), None)
+}
+
+fn frame_table_packet(
+    begin_t: i64,
+    end_t: i64,
+    (width, height): (i16, i16),
+    (offset_x, offset_y): (i16, i16),
+    pixels: Option<Vec<u8>>,
+) -> Packet {
+    Packet {
+        buffer: table(
+            b"FRME",
+            vec![
+                Some(Field::I64(begin_t)),
+                Some(Field::I64(begin_t)),
+                Some(Field::I64(end_t)),
+                Some(Field::I64(begin_t)),
+                Some(Field::I64(end_t)),
+                None,
+                Some(Field::I16(width)),
+                Some(Field::I16(height)),
+                Some(Field::I16(offset_x)),
+                Some(Field::I16(offset_y)),
+                pixels.map(|pixels| Field::Vector {
+                    len: pixels.len(),
+                    bytes: pixels,
+                    align: 1,
+                }),
+            ],
+        ),
+        stream_id: StreamContent::Frame as u32,
+    }
+}
+
+/// A synthetic scene: an initial image, and the events that change it. Each event multiplies the
+/// intensity of its pixel by `exp(C)` or `exp(-C)`, so the events are exactly those that an ideal
+/// sensor with contrast threshold [`C`] would fire.
+pub(crate) struct Scene {
+    pub(crate) initial: DMatrix<f64>,
+
+    /// The events, in order
+    pub(crate) events: Vec<Event>,
+}
+
+impl Scene {
+    pub(crate) fn new(initial: DMatrix<f64>, mut events: Vec<Event>) -> Scene {
+        events.sort_by_key(|event| event.t());
+        Scene { initial, events }
+    }
+
+    fn apply(image: &mut DMatrix<f64>, event: &Event) {
+        let change = if event.on() { C } else { -C };
+        image[(event.y() as usize, event.x() as usize)] *= change.exp();
+    }
+
+    /// The sharp image at `t`, after the events up to and including `t`
+    pub(crate) fn intensity_at(&self, t: i64) -> DMatrix<f64> {
+        let mut image = self.initial.clone();
+        for event in self.events.iter().take_while(|event| event.t() <= t) {
+            Scene::apply(&mut image, event);
+        }
+        image
+    }
+
+    /// The mean intensity over `[begin_t, end_t]`, as an APS frame exposed over it records
+    pub(crate) fn blurred(&self, begin_t: i64, end_t: i64) -> DMatrix<f64> {
+        let mut image = self.intensity_at(begin_t);
+        let mut integral = DMatrix::<f64>::zeros(image.nrows(), image.ncols());
+        let mut last_t = DMatrix::<f64>::from_element(image.nrows(), image.ncols(), begin_t as f64);
+        for event in self
+            .events
+            .iter()
+            .filter(|event| event.t() > begin_t && event.t() <= end_t)
+        {
+            let pixel = (event.y() as usize, event.x() as usize);
+            integral[pixel] += image[pixel] * (event.t() as f64 - last_t[pixel]);
+            last_t[pixel] = event.t() as f64;
+            Scene::apply(&mut image, event);
+        }
+        integral += image.component_mul(&last_t.map(|t| end_t as f64 - t));
+        integral / (end_t - begin_t) as f64
+    }
+
+    /// Record the scene as a sequence of packets, with an APS frame for each exposure. The events
+    /// up to the end of each exposure come before its frame, and the events up to the beginning
+    /// of the next exposure (or all the rest, for the last) right after it, since that's how far
+    /// the reconstructor reads ahead of each frame.
+    pub(crate) fn record(&self, exposures: &[(i64, i64)]) -> Vec<Packet> {
+        let mut packets = vec![];
+        let mut remaining = &self.events[..];
+        for (index, &(begin_t, end_t)) in exposures.iter().enumerate() {
+            let split = remaining.partition_point(|event| event.t() <= end_t);
+            packets.push(event_packet(&remaining[..split]));
+            remaining = &remaining[split..];
+            packets.push(frame_packet(begin_t, end_t, &self.blurred(begin_t, end_t)));
+            let split = match exposures.get(index + 1) {
+                Some(&(next_begin_t, _)) => {
+                    remaining.partition_point(|event| event.t() < next_begin_t)
+                }
+                None => remaining.len(),
+            };
+            packets.push(event_packet(&remaining[..split]));
+            remaining = &remaining[split..];
+        }
+        packets
+    }
+}
+
+/// When the edge of [`moving_edge`] reaches column `x`
+pub(crate) fn edge_crossing_t(x: i16) -> i64 {
+    EDGE_START_T + x as i64 * EDGE_COLUMN_TICKS
+}
+
+/// The [`EDGE_EVENTS`] events, a tick apart from `t`, that each pixel of column `x` fires as an
+/// edge of the given polarity passes it. The bottom row is left for [`exposure_anchors`].
+pub(crate) fn column_events(x: i16, t: i64, on: bool) -> Vec<Event> {
+    (0..EDGE_EVENTS)
+        .flat_map(|step| (0..HEIGHT as i16 - 1).map(move |y| Event::new(t + step, x, y, on)))
+        .collect()
+}
+
+/// An ON event at the beginning of each exposure and an OFF event at the end, at the bottom right
+/// pixel. The reconstruction integrates over the span of an exposure's events, so these make it
+/// span the exposure exactly.
+pub(crate) fn exposure_anchors(exposures: &[(i64, i64)]) -> Vec<Event> {
+    let (x, y) = (WIDTH as i16 - 1, HEIGHT as i16 - 1);
+    exposures
+        .iter()
+        .flat_map(|&(begin_t, end_t)| {
+            [
+                Event::new(begin_t, x, y, true),
+                Event::new(end_t, x, y, false),
+            ]
+        })
+        .collect()
+}
+
+/// A bright vertical edge moving right across a dark scene, reaching a column every
+/// [`EDGE_COLUMN_TICKS`], with [`exposure_anchors`] for the given exposures
+pub(crate) fn moving_edge(exposures: &[(i64, i64)]) -> Scene {
+    let events = (0..WIDTH as i16)
+        .flat_map(|x| column_events(x, edge_crossing_t(x), true))
+        .chain(exposure_anchors(exposures))
+        .collect();
+    Scene::new(
+        DMatrix::from_element(HEIGHT as usize, WIDTH as usize, DARK),
+        events,
+    )
+}
+
+/// The streams of a DAVIS recording, as the packets above identify them
+pub(crate) fn streams() -> Vec<StreamInfo> {
+    [
+        StreamContent::Events,
+        StreamContent::Frame,
+        StreamContent::Imus,
+        StreamContent::Triggers,
+    ]
+    .into_iter()
+    .map(|content| StreamInfo {
+        id: content as u32,
+        content,
+        width: WIDTH,
+        height: HEIGHT,
+    })
+    .collect()
+}
+
+/// The XML description of [`streams`], as in the header of an aedat4 file
+fn stream_description() -> String {
+    let streams: String = streams()
+        .iter()
+        .map(|stream| {
+            let (identifier, info) = match stream.content {
+                StreamContent::Events => ("EVTS", true),
+                StreamContent::Frame => ("FRME", true),
+                StreamContent::Imus => ("IMUS", false),
+                StreamContent::Triggers => ("TRIG", false),
+            };
+            let path = format!("/mainloop/Recorder/outInfo/{}/", stream.id);
+            let info = match info {
+                true => format!(
+                    "<node name=\"info\" path=\"{}info/\">\
+                     <attr key=\"sizeX\" type=\"int\">{}</attr>\
+                     <attr key=\"sizeY\" type=\"int\">{}</attr></node>",
+                    path, stream.width, stream.height
+                ),
+                false => String::new(),
+            };
+            format!(
+                "<node name=\"{}\" path=\"{}\">\
+                 <attr key=\"typeIdentifier\" type=\"string\">{}</attr>{}</node>",
+                stream.id, path, identifier, info
+            )
+        })
+        .collect();
+    format!(
+        "<dv version=\"2.0\">\
+         <node name=\"outInfo\" path=\"/mainloop/Recorder/outInfo/\">{}</node></dv>",
+        streams
+    )
+}
+
+/// Write the packets to an uncompressed aedat4 file of the given name in the temporary
+/// directory, and get it as a "file" source
+pub(crate) fn aedat_file(name: &str, packets: &[Packet]) -> InputSource {
+    let mut bytes = b"#!AER-DAT4.0\r\n".to_vec();
+
+    // The header is a plain flatbuffer preceded by its length, which is just how a size prefix
+    // looks. Without a compression or a data table position, the defaults apply.
+    let mut description = stream_description().into_bytes();
+    let len = description.len();
+    description.push(0);
+    bytes.extend(table(
+        &[0; 4],
+        vec![
+            None,
+            None,
+            Some(Field::Vector {
+                len,
+                bytes: description,
+                align: 1,
+            }),
+        ],
+    ));
+
+    for packet in packets {
+        bytes.extend_from_slice(&packet.stream_id.to_le_bytes());
+        bytes.extend_from_slice(&(packet.buffer.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&packet.buffer);
+    }
+    let directory = std::env::temp_dir();
+    std::fs::write(directory.join(name), bytes).unwrap();
+    InputSource {
+        mode: "file".to_string(),
+        directory: directory.to_str().unwrap().to_string(),
+        aedat_filename_0: name.to_string(),
+        aedat_filename_1: String::new(),
+    }
+}
+
+/// The defaults, with c fixed at the true [`C`] and intervals of 1000 ticks
+pub(crate) fn config() -> ReconstructorConfig {
+    ReconstructorConfig {
+        start_c: C,
+        output_fps: 1000.0,
+        ..Default::default()
+    }
+}
+
+/// Build a reconstructor reading the given packets as though from a file
+pub(crate) async fn reconstructor(
+    config: ReconstructorConfig,
+    packets: Vec<Packet>,
+) -> Result<Reconstructor, ReconstructorError> {
+    reconstructor_with_mode(config, "file", packets).await
+}
+
+/// Build a reconstructor reading the given packets as though from a source of the given mode
+pub(crate) async fn reconstructor_with_mode(
+    config: ReconstructorConfig,
+    mode: &str,
+    packets: Vec<Packet>,
+) -> Result<Reconstructor, ReconstructorError> {
+    config.validate()?;
+    let input_bytes = match mode {
+        "file" => Some(
+            packets
+                .iter()
+                .map(|packet| packet.buffer.len() as u64 + PACKET_HEADER_BYTES)
+                .sum(),
+        ),
+        _ => None,
+    };
+    Reconstructor::from_packet_receiver(
+        config,
+        mode.to_string(),
+        setup_queued_packets(packets),
+        (HEIGHT, WIDTH),
+        streams(),
+        input_bytes,
+    )
+    .await
+}
+
+/// Reconstruct all the frames of the given packets
+pub(crate) async fn frames(
+    config: ReconstructorConfig,
+    packets: Vec<Packet>,
+) -> Vec<ReconstructedFrame> {
+    let mut reconstructor = reconstructor(config, packets).await.unwrap();
+    all_frames(&mut reconstructor).await
+}
+
+/// Reconstruct all the remaining frames
+pub(crate) async fn all_frames(reconstructor: &mut Reconstructor) -> Vec<ReconstructedFrame> {
+    let mut frames = vec![];
+    while let Some(frame) = reconstructor.next_frame().await {
+        frames.push(frame.unwrap());
+    }
+    frames
+}
+
+/// An event adder with the scene's exposure `[begin_t, end_t]` buffered as an unquantized blurred
+/// image, and no events yet. Intervals are 1000 ticks, and c is fixed at the true [`C`].
+pub(crate) fn event_adder(scene: &Scene, begin_t: i64, end_t: i64) -> EventAdder {
+    let mut event_adder = EventAdder::new(
+        HEIGHT,
+        WIDTH,
+        1000.0,
+        C,
+        false,
+        1,
+        OutputMode::FullSequence,
+        false,
+        DEFAULT_LAMBDA,
+        DEFAULT_MIN_C,
+        DEFAULT_MAX_C,
+        DEFAULT_N_POINTS,
+    );
+    event_adder.blur_info = Some(BlurInfo::new(
+        scene.blurred(begin_t, end_t),
+        begin_t,
+        end_t,
+        Instant::now(),
+    ));
+    event_adder
+}
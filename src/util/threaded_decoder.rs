@@ -1,5 +1,6 @@
 use aedat::base::{Decoder, Packet, StreamContent};
 use num_traits::FromPrimitive;
+use std::cmp::max;
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc::{Receiver, UnboundedReceiver};
 use tokio::time::sleep;
@@ -9,9 +10,24 @@ pub(crate) struct TimestampedPacket {
     pub packet: Packet,
 }
 
+/// How often a live reader thread retries a stream that has stopped, within the grace period
+const GRACE_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The size of the stream ID and length fields before each packet in an aedat4 file
+pub(crate) const PACKET_HEADER_BYTES: u64 = 8;
+
 pub(crate) struct PacketReceiver {
     bounded_receiver: Option<Receiver<TimestampedPacket>>,
     unbounded_receiver: Option<UnboundedReceiver<TimestampedPacket>>,
+
+    /// Decodes on demand in the caller's task instead, in deterministic mode
+    serial_decoder: Option<Decoder>,
+
+    /// A packet that was received but handed back, to be returned again by the next call
+    put_back: Option<TimestampedPacket>,
+
+    /// The number of bytes of input consumed by the packets received so far
+    pub(crate) bytes_received: u64,
 }
 
 struct PacketTimingSim {
@@ -21,24 +37,67 @@ struct PacketTimingSim {
 
 impl PacketReceiver {
     pub(crate) async fn next(&mut self) -> Option<TimestampedPacket> {
-        if self.bounded_receiver.is_some() {
-            return self.bounded_receiver.as_mut().unwrap().recv().await;
-        }
-        if self.unbounded_receiver.is_some() {
-            return self.unbounded_receiver.as_mut().unwrap().recv().await;
+        let packet = if let Some(p) = self.put_back.take() {
+            Some(p)
+        } else if let Some(decoder) = self.serial_decoder.as_mut() {
+            match decoder.next() {
+                None => None,
+                Some(Ok(mut p)) => {
+                    p.stream_id = stream_content_id(decoder, p.stream_id);
+                    Some(TimestampedPacket {
+                        timestamp: Instant::now(),
+                        packet: p,
+                    })
+                }
+                Some(Err(e)) => {
+                    // End the stream, as the reader threads do
+                    eprintln!("{}. Ending the stream", e);
+                    self.serial_decoder = None;
+                    None
+                }
+            }
+        } else if self.bounded_receiver.is_some() {
+            self.bounded_receiver.as_mut().unwrap().recv().await
+        } else if self.unbounded_receiver.is_some() {
+            self.unbounded_receiver.as_mut().unwrap().recv().await
+        } else {
+            None
+        };
+        if let Some(p) = &packet {
+            self.bytes_received += p.packet.buffer.len() as u64 + PACKET_HEADER_BYTES;
         }
-        None
+        packet
+    }
+
+    /// Hand back a packet, so that the next call to `next` returns it again
+    pub(crate) fn put_back(&mut self, p: TimestampedPacket) {
+        self.bytes_received -= p.packet.buffer.len() as u64 + PACKET_HEADER_BYTES;
+        self.put_back = Some(p);
     }
 }
 
+/// Map a packet's stream ID to the ID of its stream's content type, which is what the
+/// reconstructor matches on. The stream IDs in a file are arbitrary, so they can't be used
+/// directly. A stream missing from the decoder's table keeps its ID.
+fn stream_content_id(decoder: &Decoder, stream_id: u32) -> u32 {
+    decoder
+        .id_to_stream
+        .get(&stream_id)
+        .map_or(stream_id, |stream| stream.content as u32)
+}
+
 pub(crate) fn setup_packet_threads(
     aedat_decoder_0: Decoder,
     aedat_decoder_1: Option<Decoder>,
     simulate_latency: bool,
+    end_of_stream_grace: Option<Duration>,
 ) -> PacketReceiver {
     let mut packet_receiver = PacketReceiver {
         bounded_receiver: None,
         unbounded_receiver: None,
+        serial_decoder: None,
+        put_back: None,
+        bytes_received: 0,
     };
     match aedat_decoder_1 {
         None => {
@@ -54,14 +113,65 @@ pub(crate) fn setup_packet_threads(
                 tokio::sync::mpsc::UnboundedSender<TimestampedPacket>,
                 tokio::sync::mpsc::UnboundedReceiver<TimestampedPacket>,
             ) = tokio::sync::mpsc::unbounded_channel();
-            setup_socket_threads(sender, aedat_decoder_0, decoder_1);
+            setup_socket_threads(sender, aedat_decoder_0, decoder_1, end_of_stream_grace);
             packet_receiver.unbounded_receiver = Some(receiver);
         }
     };
     packet_receiver
 }
 
-/// Use a bounded channel for a file source, so that we don't just read in the whole file at once
+/// Decode a file source serially, one packet at a time as the reconstructor asks for them, with
+/// no reader thread or latency simulation. Packets then always arrive in the same order relative
+/// to the reconstruction, so the output is reproducible across runs.
+pub(crate) fn setup_serial_packets(aedat_decoder_0: Decoder) -> PacketReceiver {
+    PacketReceiver {
+        bounded_receiver: None,
+        unbounded_receiver: None,
+        serial_decoder: Some(aedat_decoder_0),
+        put_back: None,
+        bytes_received: 0,
+    }
+}
+
+/// Deliver the given packets in order, then end the stream, as a file source would. The stream IDs
+/// must already be those of their content types.
+#[cfg(test)]
+pub(crate) fn setup_queued_packets(packets: Vec<Packet>) -> PacketReceiver {
+    let (_, packet_receiver) = setup_open_packets(packets);
+    packet_receiver
+}
+
+/// Deliver the given packets in order, then wait for more from the returned sender, as a live
+/// source would. The stream ends when the sender is dropped.
+#[cfg(test)]
+pub(crate) fn setup_open_packets(
+    packets: Vec<Packet>,
+) -> (
+    tokio::sync::mpsc::UnboundedSender<TimestampedPacket>,
+    PacketReceiver,
+) {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    for packet in packets {
+        sender
+            .send(TimestampedPacket {
+                timestamp: Instant::now(),
+                packet,
+            })
+            .unwrap();
+    }
+    let packet_receiver = PacketReceiver {
+        bounded_receiver: None,
+        unbounded_receiver: Some(receiver),
+        serial_decoder: None,
+        put_back: None,
+        bytes_received: 0,
+    };
+    (sender, packet_receiver)
+}
+
+/// Use a bounded channel for a file source, so that we don't just read in the whole file at once.
+/// A single task decodes, delays (when simulating latency), and sends each packet in turn, so
+/// packets always arrive in file order; the simulated latency only changes when they arrive.
 fn setup_file_threads(
     sender: tokio::sync::mpsc::Sender<TimestampedPacket>,
     mut decoder_0: Decoder,
@@ -76,7 +186,8 @@ fn setup_file_threads(
                     eprintln!("End of file. Leaving reader thread");
                     break;
                 }
-                Some(Ok(p)) => {
+                Some(Ok(mut p)) => {
+                    p.stream_id = stream_content_id(&decoder_0, p.stream_id);
                     if simulate_latency {
                         latency_sim_update(&mut timing_sim, &mut packet_end_time, &p).await;
                     }
@@ -99,23 +210,32 @@ fn setup_file_threads(
     });
 }
 
+/// Live sources may pause (e.g., a network hiccup), so each reader thread keeps retrying for
+/// `end_of_stream_grace` before concluding that its stream has ended
 fn setup_socket_threads(
     sender_main: tokio::sync::mpsc::UnboundedSender<TimestampedPacket>,
     mut decoder_0: Decoder,
     mut decoder_1: Decoder,
+    end_of_stream_grace: Option<Duration>,
 ) {
     let sender_0 = sender_main;
     let sender_1 = sender_0.clone();
     // Create thread for decoder_0
     tokio::spawn(async move {
+        let mut first_none: Option<Instant> = None;
         loop {
             match decoder_0.next() {
                 None => {
+                    if within_grace(&mut first_none, end_of_stream_grace) {
+                        sleep(GRACE_RETRY_INTERVAL).await;
+                        continue;
+                    }
                     eprintln!("End of file. Leaving reader thread");
                     break;
                 }
                 Some(Ok(mut p)) => {
-                    p.stream_id = decoder_0.id_to_stream.get(&p.stream_id).unwrap().content as u32;
+                    first_none = None;
+                    p.stream_id = stream_content_id(&decoder_0, p.stream_id);
                     if sender_0
                         .send(TimestampedPacket {
                             timestamp: Instant::now(),
@@ -136,14 +256,20 @@ fn setup_socket_threads(
     //     = tokio::sync::mpsc::unbounded_channel();
     // Create thread for decoder_1
     tokio::spawn(async move {
+        let mut first_none: Option<Instant> = None;
         loop {
             match decoder_1.next() {
                 None => {
+                    if within_grace(&mut first_none, end_of_stream_grace) {
+                        sleep(GRACE_RETRY_INTERVAL).await;
+                        continue;
+                    }
                     eprintln!("End of file. Leaving reader thread");
                     break;
                 }
                 Some(Ok(mut p)) => {
-                    p.stream_id = decoder_1.id_to_stream.get(&p.stream_id).unwrap().content as u32;
+                    first_none = None;
+                    p.stream_id = stream_content_id(&decoder_1, p.stream_id);
                     if sender_1
                         .send(TimestampedPacket {
                             timestamp: Instant::now(),
@@ -185,36 +311,40 @@ fn setup_socket_threads(
     // });
 }
 
+/// Whether a stream that has stopped yielding packets (since `first_none`, which is set on the
+/// first call) is still within its grace period
+fn within_grace(first_none: &mut Option<Instant>, grace: Option<Duration>) -> bool {
+    match grace {
+        None => false,
+        Some(grace) => first_none.get_or_insert_with(Instant::now).elapsed() < grace,
+    }
+}
+
+/// Sleep until the packet would have arrived from a live camera, relative to the previous one.
+/// Frames and events are interleaved with overlapping timestamps (e.g., a frame's exposure ends
+/// before the events packet preceding it does), so the embedded time never moves backwards.
+/// Otherwise, the same span of time would be slept through twice.
 async fn latency_sim_update(
     timing_sim: &mut Option<PacketTimingSim>,
     packet_end_time: &mut u64,
     p: &Packet,
 ) {
-    *packet_end_time = match FromPrimitive::from_u32(p.stream_id) {
+    // A malformed packet carries no time, but it's still passed on for the reconstructor to skip
+    let embedded_end_time = match FromPrimitive::from_u32(p.stream_id) {
         Some(StreamContent::Frame) => {
-            let frame = match aedat::frame_generated::size_prefixed_root_as_frame(&p.buffer) {
-                Ok(result) => result,
-                Err(_) => {
-                    panic!("the packet does not have a size prefix");
-                }
-            };
-            frame.exposure_end_t() as u64
+            aedat::frame_generated::size_prefixed_root_as_frame(&p.buffer)
+                .map_or(*packet_end_time, |frame| frame.exposure_end_t() as u64)
         }
         Some(StreamContent::Events) => {
-            let event_packet =
-                match aedat::events_generated::size_prefixed_root_as_event_packet(&p.buffer) {
-                    Ok(result) => result,
-                    Err(_) => {
-                        panic!("the packet does not have a size prefix");
-                    }
-                };
-            match event_packet.elements() {
-                None => *packet_end_time,
-                Some(elems) => elems.last().unwrap().t() as u64,
-            }
+            aedat::events_generated::size_prefixed_root_as_event_packet(&p.buffer)
+                .ok()
+                .and_then(|event_packet| event_packet.elements())
+                .and_then(|elems| elems.last())
+                .map_or(*packet_end_time, |event| event.t() as u64)
         }
         _ => *packet_end_time,
     };
+    *packet_end_time = max(*packet_end_time, embedded_end_time);
 
     match timing_sim {
         None => {
@@ -239,3 +369,98 @@ async fn latency_sim_update(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_support;
+    use aedat::events_generated::Event;
+    use nalgebra::DMatrix;
+
+    #[tokio::test]
+    async fn simulated_latency_never_moves_backwards() {
+        let (height, width) = (test_support::HEIGHT as usize, test_support::WIDTH as usize);
+        let image = DMatrix::from_element(height, width, 0.5);
+        let truncated = |mut packet: Packet| {
+            packet.buffer.truncate(packet.buffer.len() / 2);
+            packet
+        };
+        // The frame's exposure ends before the events packet preceding it does. Empty and
+        // malformed packets carry no time.
+        let packets = [
+            test_support::event_packet(&[Event::new(2000, 0, 0, true)]),
+            test_support::frame_packet(500, 1000, &image),
+            test_support::elementless_event_packet(),
+            test_support::event_packet(&[]),
+            truncated(test_support::frame_packet(2500, 5000, &image)),
+            truncated(test_support::event_packet(&[Event::new(5000, 0, 0, true)])),
+            test_support::event_packet(&[Event::new(3000, 0, 0, true)]),
+        ];
+        let mut timing_sim = None;
+        let mut packet_end_time = 0;
+        let mut end_times = vec![];
+        for packet in &packets {
+            latency_sim_update(&mut timing_sim, &mut packet_end_time, packet).await;
+            end_times.push((
+                packet_end_time,
+                timing_sim.as_ref().unwrap().last_packet_embedded_timestamp,
+            ));
+        }
+        assert_eq!(
+            end_times,
+            [
+                (2000, 2000),
+                (2000, 2000),
+                (2000, 2000),
+                (2000, 2000),
+                (2000, 2000),
+                (2000, 2000),
+                (3000, 3000)
+            ]
+        );
+    }
+
+    #[test]
+    fn grace_runs_from_the_first_missing_packet() {
+        let mut first_none = None;
+        assert!(!within_grace(&mut first_none, None));
+        assert!(first_none.is_none());
+        assert!(!within_grace(&mut first_none, Some(Duration::ZERO)));
+
+        let mut first_none = None;
+        let grace = Some(Duration::from_millis(30));
+        assert!(within_grace(&mut first_none, grace));
+        let started = first_none.unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(within_grace(&mut first_none, grace));
+        // Later calls measure from the first, rather than restarting the period
+        assert_eq!(first_none, Some(started));
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!within_grace(&mut first_none, grace));
+    }
+
+    #[tokio::test]
+    async fn put_back_packets_are_received_again() {
+        let packets = vec![
+            test_support::trigger_packet(&[1000]),
+            test_support::event_packet(&[Event::new(2000, 0, 0, true)]),
+        ];
+        let sizes: Vec<u64> = packets
+            .iter()
+            .map(|packet| packet.buffer.len() as u64 + PACKET_HEADER_BYTES)
+            .collect();
+        let mut packet_receiver = setup_queued_packets(packets);
+        let first = packet_receiver.next().await.unwrap();
+        assert_eq!(packet_receiver.bytes_received, sizes[0]);
+        let buffer = first.packet.buffer.clone();
+        packet_receiver.put_back(first);
+        assert_eq!(packet_receiver.bytes_received, 0);
+
+        assert_eq!(packet_receiver.next().await.unwrap().packet.buffer, buffer);
+        assert_eq!(packet_receiver.bytes_received, sizes[0]);
+        let second = packet_receiver.next().await.unwrap();
+        assert_eq!(second.packet.stream_id, StreamContent::Events as u32);
+        assert_eq!(packet_receiver.bytes_received, sizes[0] + sizes[1]);
+        assert!(packet_receiver.next().await.is_none());
+    }
+}